@@ -0,0 +1,10 @@
+-- Add migration script here
+CREATE TABLE IF NOT EXISTS exec_session (
+    id SERIAL PRIMARY KEY,
+    package TEXT NOT NULL,
+    session TEXT NOT NULL,
+    command TEXT NOT NULL,
+    started_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    ended_at TIMESTAMP,
+    exit_code INTEGER
+);
@@ -309,6 +309,32 @@ async fn assure_backing_up(
     Ok(())
 }
 
+/// The status to hold a package in `status` in while it is backed up, and the one to put it back in afterwards,
+/// or None if it is already being backed up
+fn backup_statuses(status: MainStatus) -> Option<(MainStatus, MainStatus)> {
+    let (started, health) = match &status {
+        MainStatus::Starting { .. } => (Some(Utc::now()), Default::default()),
+        MainStatus::Running { started, health } => (Some(*started), health.clone()),
+        MainStatus::Paused { started } => (Some(*started), Default::default()),
+        MainStatus::Stopped
+        | MainStatus::Stopping
+        | MainStatus::Restarting
+        | MainStatus::CrashLooping { .. } => (None, Default::default()),
+        MainStatus::BackingUp { .. } => return None,
+    };
+    let after = match status {
+        MainStatus::Paused { .. } => status,
+        _ => match started {
+            Some(started) => MainStatus::Running {
+                started,
+                health: health.clone(),
+            },
+            None => MainStatus::Stopped,
+        },
+    };
+    Some((MainStatus::BackingUp { started, health }, after))
+}
+
 #[instrument(skip(ctx, db, backup_guard))]
 async fn perform_backup<Db: DbHandle>(
     ctx: &RpcContext,
@@ -340,33 +366,23 @@ async fn perform_backup<Db: DbHandle>(
         let main_status_model = installed_model.clone().status().main();
 
         main_status_model.lock(&mut tx, LockType::Write).await?;
-        let (started, health) = match main_status_model.get(&mut tx).await?.into_owned() {
-            MainStatus::Starting { .. } => (Some(Utc::now()), Default::default()),
-            MainStatus::Running { started, health } => (Some(started), health.clone()),
-            MainStatus::Stopped | MainStatus::Stopping | MainStatus::Restarting => {
-                (None, Default::default())
-            }
-            MainStatus::BackingUp { .. } => {
-                backup_report.insert(
-                    package_id,
-                    PackageBackupReport {
-                        error: Some(
-                            "Can't do backup because service is in a backing up state".to_owned(),
-                        ),
-                    },
-                );
-                continue;
-            }
-        };
-        main_status_model
-            .put(
-                &mut tx,
-                &MainStatus::BackingUp {
-                    started,
-                    health: health.clone(),
-                },
-            )
-            .await?;
+        let (backing_up, after) =
+            match backup_statuses(main_status_model.get(&mut tx).await?.into_owned()) {
+                Some(statuses) => statuses,
+                None => {
+                    backup_report.insert(
+                        package_id,
+                        PackageBackupReport {
+                            error: Some(
+                                "Can't do backup because service is in a backing up state"
+                                    .to_owned(),
+                            ),
+                        },
+                    );
+                    continue;
+                }
+            };
+        main_status_model.put(&mut tx, &backing_up).await?;
         tx.save().await?; // drop locks
 
         let manifest = installed_model.clone().manifest().get(&mut db).await?;
@@ -416,15 +432,7 @@ async fn perform_backup<Db: DbHandle>(
                 .insert(package_id.clone(), pkg_meta);
         }
 
-        main_status_model
-            .put(
-                &mut tx,
-                &match started {
-                    Some(started) => MainStatus::Running { started, health },
-                    None => MainStatus::Stopped,
-                },
-            )
-            .await?;
+        main_status_model.put(&mut tx, &after).await?;
 
         let mut backup_progress = crate::db::DatabaseModel::new()
             .server_info()
@@ -492,3 +500,38 @@ async fn perform_backup<Db: DbHandle>(
         .await?;
     Ok(backup_report)
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn backup_status_round_trip() {
+        let started = Utc::now();
+        let (backing_up, after) = backup_statuses(MainStatus::Paused { started }).unwrap();
+        assert!(matches!(
+            backing_up,
+            MainStatus::BackingUp {
+                started: Some(s),
+                ..
+            } if s == started
+        ));
+        assert!(matches!(after, MainStatus::Paused { started: s } if s == started));
+
+        let (_, after) = backup_statuses(MainStatus::Running {
+            started,
+            health: Default::default(),
+        })
+        .unwrap();
+        assert!(matches!(after, MainStatus::Running { started: s, .. } if s == started));
+
+        let (backing_up, after) = backup_statuses(MainStatus::Stopped).unwrap();
+        assert!(matches!(
+            backing_up,
+            MainStatus::BackingUp { started: None, .. }
+        ));
+        assert!(matches!(after, MainStatus::Stopped));
+
+        assert!(backup_statuses(backing_up).is_none());
+    }
+}
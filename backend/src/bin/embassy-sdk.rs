@@ -24,7 +24,18 @@ fn inner_main() -> Result<(), Error> {
             ),
         context: matches => {
             if let Err(_) = std::env::var("RUST_LOG") {
-                std::env::set_var("RUST_LOG", "embassy=warn,js_engine=warn");
+                // so run-procedure can show what the script logs
+                let verbose = matches
+                    .subcommand_matches("run-procedure")
+                    .map_or(false, |m| m.is_present("verbose"));
+                std::env::set_var(
+                    "RUST_LOG",
+                    if verbose {
+                        "embassy=warn,js_engine=info"
+                    } else {
+                        "embassy=warn,js_engine=warn"
+                    },
+                );
             }
             EmbassyLogger::init();
             SdkContext::init(matches)?
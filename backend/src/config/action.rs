@@ -6,6 +6,7 @@ use patch_db::HasModel;
 use serde::{Deserialize, Serialize};
 use tracing::instrument;
 
+use super::migration::ConfigMigrations;
 use super::{Config, ConfigSpec};
 use crate::context::RpcContext;
 use crate::dependencies::Dependencies;
@@ -26,9 +27,15 @@ pub struct ConfigRes {
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize, HasModel)]
+#[serde(rename_all = "kebab-case")]
 pub struct ConfigActions {
     pub get: PackageProcedure,
     pub set: PackageProcedure,
+    /// Bumped whenever a stored config needs a migration to match the config spec
+    #[serde(default)]
+    pub spec_version: u32,
+    #[serde(default)]
+    pub migrations: ConfigMigrations,
 }
 impl ConfigActions {
     #[instrument]
@@ -45,6 +52,13 @@ impl ConfigActions {
         self.set
             .validate(container, eos_version, volumes, image_ids, true)
             .with_ctx(|_| (crate::ErrorKind::ValidateS9pk, "Config Set"))?;
+        self.migrations.validate(
+            self.spec_version,
+            container,
+            eos_version,
+            volumes,
+            image_ids,
+        )?;
         Ok(())
     }
     #[instrument(skip(ctx))]
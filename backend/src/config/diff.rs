@@ -0,0 +1,175 @@
+use std::fmt;
+use std::str::FromStr;
+
+use color_eyre::eyre::eyre;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use super::Config;
+use crate::Error;
+
+/// A location within a config, written as a JSON pointer (ie. `/rpc/username`)
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ConfigPath(Vec<String>);
+impl ConfigPath {
+    fn split(&self) -> (&[String], &String) {
+        let (last, parents) = self.0.split_last().expect("ConfigPath is never empty");
+        (parents, last)
+    }
+    fn parent<'a>(&self, config: &'a Config) -> Option<&'a Config> {
+        let mut cur = config;
+        for key in self.split().0 {
+            cur = cur.get(key)?.as_object()?;
+        }
+        Some(cur)
+    }
+    fn parent_mut<'a>(&self, config: &'a mut Config) -> Option<&'a mut Config> {
+        let mut cur = config;
+        for key in self.split().0 {
+            cur = cur.get_mut(key)?.as_object_mut()?;
+        }
+        Some(cur)
+    }
+
+    pub fn get<'a>(&self, config: &'a Config) -> Option<&'a Value> {
+        self.parent(config)?.get(self.split().1)
+    }
+    pub fn get_mut<'a>(&self, config: &'a mut Config) -> Option<&'a mut Value> {
+        let key = self.split().1;
+        self.parent_mut(config)?.get_mut(key)
+    }
+    pub fn remove(&self, config: &mut Config) -> Option<Value> {
+        let key = self.split().1;
+        self.parent_mut(config)?.remove(key)
+    }
+    /// Sets the value at this path, creating any missing parent objects
+    pub fn insert(&self, config: &mut Config, value: Value) -> Result<Option<Value>, Error> {
+        let (parents, key) = self.split();
+        let mut cur = config;
+        for parent in parents {
+            cur = cur
+                .entry(parent.clone())
+                .or_insert_with(|| Value::Object(Config::new()))
+                .as_object_mut()
+                .ok_or_else(|| {
+                    Error::new(
+                        eyre!("{} is not an object", parent),
+                        crate::ErrorKind::ConfigGen,
+                    )
+                })?;
+        }
+        Ok(cur.insert(key.clone(), value))
+    }
+    /// The path of `key` within the same object as this path
+    pub fn with_key(&self, key: &str) -> Self {
+        let mut path = self.0.clone();
+        *path.last_mut().expect("ConfigPath is never empty") = key.to_owned();
+        ConfigPath(path)
+    }
+    /// The path of `key` within the object at this path
+    pub fn join(&self, key: &str) -> Self {
+        let mut path = self.0.clone();
+        path.push(key.to_owned());
+        ConfigPath(path)
+    }
+}
+impl FromStr for ConfigPath {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let segments = s
+            .strip_prefix('/')
+            .ok_or_else(|| {
+                Error::new(
+                    eyre!("Config path must start with /: {}", s),
+                    crate::ErrorKind::InvalidRequest,
+                )
+            })?
+            .split('/')
+            .map(|seg| seg.replace("~1", "/").replace("~0", "~"))
+            .collect::<Vec<_>>();
+        if segments.iter().any(|seg| seg.is_empty()) {
+            return Err(Error::new(
+                eyre!("Config path has an empty segment: {}", s),
+                crate::ErrorKind::InvalidRequest,
+            ));
+        }
+        Ok(ConfigPath(segments))
+    }
+}
+impl From<&str> for ConfigPath {
+    /// The path of a top level key
+    fn from(key: &str) -> Self {
+        ConfigPath(vec![key.to_owned()])
+    }
+}
+impl fmt::Display for ConfigPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for seg in &self.0 {
+            write!(f, "/{}", seg.replace('~', "~0").replace('/', "~1"))?;
+        }
+        Ok(())
+    }
+}
+impl Serialize for ConfigPath {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        crate::util::serde::serialize_display(self, serializer)
+    }
+}
+impl<'de> Deserialize<'de> for ConfigPath {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        crate::util::serde::deserialize_from_str(deserializer)
+    }
+}
+
+/// A value that differs between two configs. A value that is only present on one side is left out on the other.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ConfigChange {
+    pub path: ConfigPath,
+    #[serde(default, deserialize_with = "deserialize_present")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub old: Option<Value>,
+    #[serde(default, deserialize_with = "deserialize_present")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub new: Option<Value>,
+    /// Whether secrets in `old` and `new` were masked, so they no longer hold the real values
+    #[serde(default)]
+    pub masked: bool,
+}
+
+/// Keeps a `null` value as `Some`, so it isn't mistaken for a missing one
+fn deserialize_present<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<Value>, D::Error> {
+    Value::deserialize(deserializer).map(Some)
+}
+
+/// Every value that differs between `old` and `new`, descending into objects
+pub fn diff(old: &Config, new: &Config) -> Vec<ConfigChange> {
+    fn diff_at(path: Option<&ConfigPath>, old: &Config, new: &Config, res: &mut Vec<ConfigChange>) {
+        let keys = old
+            .keys()
+            .chain(new.keys().filter(|k| !old.contains_key(*k)));
+        for key in keys {
+            let path = match path {
+                Some(path) => path.join(key),
+                None => ConfigPath::from(key.as_str()),
+            };
+            match (old.get(key), new.get(key)) {
+                (Some(Value::Object(old)), Some(Value::Object(new))) => {
+                    diff_at(Some(&path), old, new, res)
+                }
+                (old, new) if old != new => res.push(ConfigChange {
+                    path,
+                    old: old.cloned(),
+                    new: new.cloned(),
+                    masked: false,
+                }),
+                _ => (),
+            }
+        }
+    }
+    let mut res = Vec::new();
+    diff_at(None, old, new, &mut res);
+    res
+}
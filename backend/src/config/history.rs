@@ -0,0 +1,455 @@
+use std::fmt;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use clap::ArgMatches;
+use color_eyre::eyre::eyre;
+use itertools::Itertools;
+use rpc_toolkit::command;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tracing::instrument;
+
+use super::action::ConfigRes;
+use super::diff::{ConfigChange, ConfigPath};
+use super::spec::MASK;
+use super::{not_found, Config, ConfigGetReceipts, ConfigSpec};
+use crate::context::RpcContext;
+use crate::dependencies::BreakageRes;
+use crate::s9pk::manifest::PackageId;
+use crate::util::display_none;
+use crate::util::serde::{display_serializable, IoFormat};
+use crate::Error;
+
+/// Where a change to a config came from
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+#[serde(tag = "type")]
+pub enum ConfigSource {
+    /// Set through `package.config set`
+    User,
+    /// Set through `package.config rollback`
+    #[serde(rename_all = "kebab-case")]
+    Rollback { revision: u64 },
+    /// Refreshed because something about a dependency it points to changed
+    #[serde(rename_all = "kebab-case")]
+    Dependency { id: PackageId },
+    /// Auto configured to satisfy a dependent
+    #[serde(rename_all = "kebab-case")]
+    AutoConfigure { dependent: PackageId },
+    /// Reapplied after an update
+    Update,
+}
+impl fmt::Display for ConfigSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigSource::User => write!(f, "user"),
+            ConfigSource::Rollback { revision } => write!(f, "rollback to {}", revision),
+            ConfigSource::Dependency { id } => write!(f, "dependency {}", id),
+            ConfigSource::AutoConfigure { dependent } => {
+                write!(f, "auto configure for {}", dependent)
+            }
+            ConfigSource::Update => write!(f, "update"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ConfigRevision {
+    pub revision: u64,
+    pub time: DateTime<Utc>,
+    pub source: ConfigSource,
+    pub changes: Vec<ConfigChange>,
+}
+impl ConfigRevision {
+    /// Only this many of the most recent revisions are kept
+    pub const HISTORY_LENGTH: usize = 50;
+}
+
+/// Every value that differs between `old` and `new`, with the values of `masked` strings hidden
+pub fn masked_diff(spec: &ConfigSpec, old: &Config, new: &Config) -> Vec<ConfigChange> {
+    let mut masked_old = old.clone();
+    spec.mask(&mut masked_old);
+    let mut masked_new = new.clone();
+    spec.mask(&mut masked_new);
+    super::diff::diff(old, new)
+        .into_iter()
+        .map(|mut change| {
+            let old_value = change.path.get(&masked_old);
+            let new_value = change.path.get(&masked_new);
+            if old_value != change.path.get(old) || new_value != change.path.get(new) {
+                // a change within a masked object has nothing left at its path once masked
+                let hide = |masked: Option<&Value>, value: &Option<Value>| {
+                    value.as_ref().map(|_| {
+                        masked
+                            .cloned()
+                            .unwrap_or_else(|| Value::String(MASK.to_owned()))
+                    })
+                };
+                change.old = hide(old_value, &change.old);
+                change.new = hide(new_value, &change.new);
+                change.masked = true;
+            }
+            change
+        })
+        .collect()
+}
+
+/// Appends a revision with `changes` to `history`, dropping the oldest ones past [`ConfigRevision::HISTORY_LENGTH`].
+/// Returns whether anything was recorded, which it is not if nothing changed.
+pub fn record(
+    history: &mut Vec<ConfigRevision>,
+    source: ConfigSource,
+    changes: Vec<ConfigChange>,
+) -> bool {
+    if changes.is_empty() {
+        return false;
+    }
+    history.push(ConfigRevision {
+        revision: history.last().map_or(1, |r| r.revision + 1),
+        time: Utc::now(),
+        source,
+        changes,
+    });
+    let excess = history.len().saturating_sub(ConfigRevision::HISTORY_LENGTH);
+    history.drain(..excess);
+    true
+}
+
+/// The config as it was right after `revision`, rebuilt by undoing every later revision on top of `current`.
+/// Masked changes can't be undone, so those keep their current value, and are returned alongside.
+pub fn roll_back(
+    history: &[ConfigRevision],
+    revision: u64,
+    mut current: Config,
+) -> Result<(Config, Vec<ConfigPath>), Error> {
+    if !history.iter().any(|r| r.revision == revision) {
+        return Err(Error::new(
+            eyre!("Config revision {} is not in the history", revision),
+            crate::ErrorKind::NotFound,
+        ));
+    }
+    let mut kept = Vec::new();
+    for change in history
+        .iter()
+        .rev()
+        .take_while(|r| r.revision > revision)
+        .flat_map(|r| r.changes.iter().rev())
+    {
+        if change.masked {
+            kept.push(change.path.clone());
+        } else if let Some(old) = &change.old {
+            change.path.insert(&mut current, old.clone())?;
+        } else {
+            change.path.remove(&mut current);
+        }
+    }
+    kept.sort();
+    kept.dedup();
+    Ok((current, kept))
+}
+
+async fn load_history(ctx: &RpcContext, id: &PackageId) -> Result<Vec<ConfigRevision>, Error> {
+    Ok(crate::db::DatabaseModel::new()
+        .package_data()
+        .idx_model(id)
+        .and_then(|pde| pde.installed())
+        .map(|i| i.config_history())
+        .get(&mut ctx.db.handle())
+        .await?
+        .into_owned()
+        .ok_or_else(not_found)?)
+}
+
+async fn load_config(ctx: &RpcContext, id: &PackageId) -> Result<(Config, ConfigSpec), Error> {
+    let mut db = ctx.db.handle();
+    let receipts = ConfigGetReceipts::new(&mut db, id).await?;
+    let action = receipts
+        .manifest_config
+        .get(&mut db)
+        .await?
+        .ok_or_else(|| Error::new(eyre!("{} has no config", id), crate::ErrorKind::NotFound))?;
+    let volumes = receipts.manifest_volumes.get(&mut db).await?;
+    let version = receipts.manifest_version.get(&mut db).await?;
+    let ConfigRes { config, spec } = action.get(ctx, id, &version, &volumes).await?;
+    Ok((config.unwrap_or_default(), spec))
+}
+
+/// The config `id` would get from rolling back to `revision`
+async fn rolled_back(ctx: &RpcContext, id: &PackageId, revision: u64) -> Result<Config, Error> {
+    let history = load_history(ctx, id).await?;
+    let (current, _) = load_config(ctx, id).await?;
+    let (config, kept) = roll_back(&history, revision, current)?;
+    for path in &kept {
+        tracing::warn!(
+            "Rollback of {} keeps the current value of masked {}",
+            id,
+            path
+        );
+    }
+    Ok(config)
+}
+
+fn display_history(history: Vec<ConfigRevision>, matches: &ArgMatches) {
+    use prettytable::*;
+
+    if matches.is_present("format") {
+        return display_serializable(history, matches);
+    }
+
+    let mut table = Table::new();
+    table.add_row(row![bc => "REVISION", "TIME", "SOURCE", "CHANGED"]);
+    for revision in history.iter().rev() {
+        table.add_row(row![
+            &revision.revision.to_string(),
+            &format!("{}", revision.time),
+            &revision.source.to_string(),
+            &revision.changes.iter().map(|c| &c.path).join(", "),
+        ]);
+    }
+    table.print_tty(false).unwrap();
+}
+
+/// The changes recorded for every set of the config, newest last
+#[command(display(display_history))]
+#[instrument(skip(ctx))]
+pub async fn history(
+    #[context] ctx: RpcContext,
+    #[parent_data] id: PackageId,
+    #[allow(unused_variables)]
+    #[arg(long = "format")]
+    format: Option<IoFormat>,
+) -> Result<Vec<ConfigRevision>, Error> {
+    load_history(&ctx, &id).await
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ConfigDiffRes {
+    pub revision: u64,
+    /// What rolling back to the revision would change in the current config
+    pub changes: Vec<ConfigChange>,
+    /// Secrets changed since the revision, which a rollback leaves as they are
+    pub kept: Vec<ConfigPath>,
+}
+
+fn display_diff(res: ConfigDiffRes, matches: &ArgMatches) {
+    use prettytable::*;
+
+    if matches.is_present("format") {
+        return display_serializable(res, matches);
+    }
+
+    let mut table = Table::new();
+    table.add_row(row![bc => "PATH", "CURRENT", &format!("REVISION {}", res.revision)]);
+    let show = |value: &Option<Value>| {
+        value
+            .as_ref()
+            .map_or("(none)".to_owned(), |v| v.to_string())
+    };
+    for change in &res.changes {
+        table.add_row(row![
+            &change.path.to_string(),
+            &show(&change.old),
+            &show(&change.new),
+        ]);
+    }
+    for path in &res.kept {
+        table.add_row(row![&path.to_string(), "(masked)", "(kept)"]);
+    }
+    table.print_tty(false).unwrap();
+}
+
+/// How the current config differs from the config right after `revision`
+#[command(display(display_diff))]
+#[instrument(skip(ctx))]
+pub async fn diff(
+    #[context] ctx: RpcContext,
+    #[parent_data] id: PackageId,
+    #[arg] revision: u64,
+    #[allow(unused_variables)]
+    #[arg(long = "format")]
+    format: Option<IoFormat>,
+) -> Result<ConfigDiffRes, Error> {
+    let history = load_history(&ctx, &id).await?;
+    let (current, spec) = load_config(&ctx, &id).await?;
+    let (config, kept) = roll_back(&history, revision, current.clone())?;
+    Ok(ConfigDiffRes {
+        revision,
+        changes: masked_diff(&spec, &current, &config),
+        kept,
+    })
+}
+
+#[command(
+    subcommands(self(rollback_impl(async, context(RpcContext))), rollback_dry),
+    display(display_none),
+    metadata(sync_db = true)
+)]
+#[instrument]
+pub fn rollback(
+    #[parent_data] id: PackageId,
+    #[arg] revision: u64,
+    #[allow(unused_variables)]
+    #[arg(long = "format")]
+    format: Option<IoFormat>,
+    #[arg(long = "timeout")] timeout: Option<crate::util::serde::Duration>,
+) -> Result<(PackageId, u64, Option<Duration>), Error> {
+    Ok((id, revision, timeout.map(|d| *d)))
+}
+
+#[command(rename = "dry", display(display_serializable))]
+#[instrument(skip(ctx))]
+pub async fn rollback_dry(
+    #[context] ctx: RpcContext,
+    #[parent_data] (id, revision, timeout): (PackageId, u64, Option<Duration>),
+) -> Result<BreakageRes, Error> {
+    let config = rolled_back(&ctx, &id, revision).await?;
+    super::set_dry(ctx, (id, Some(config), timeout)).await
+}
+
+/// Sets the config back to how it was right after `revision`, going through the same checks as a set
+#[instrument(skip(ctx))]
+pub async fn rollback_impl(
+    ctx: RpcContext,
+    (id, revision, timeout): (PackageId, u64, Option<Duration>),
+) -> Result<(), Error> {
+    let config = rolled_back(&ctx, &id, revision).await?;
+    super::set_from(
+        &ctx,
+        &id,
+        Some(config),
+        ConfigSource::Rollback { revision },
+        timeout,
+    )
+    .await
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn roll_back_revisions() {
+        let spec: ConfigSpec = serde_json::from_value(json!({
+            "password": {
+                "type": "string",
+                "name": "Password",
+                "nullable": false,
+                "masked": true,
+                "copyable": false,
+                "default": "hunter2",
+            },
+            "host": {
+                "type": "string",
+                "name": "Host",
+                "nullable": false,
+                "masked": false,
+                "copyable": false,
+                "default": "localhost",
+            },
+        }))
+        .unwrap();
+        let configs = [
+            json!({ "password": "hunter2", "host": "localhost" }),
+            json!({ "password": "correct horse", "host": "localhost" }),
+            json!({ "password": "correct horse", "host": "0.0.0.0" }),
+        ]
+        .map(|c| c.as_object().unwrap().clone());
+        let mut history = Vec::new();
+        for pair in configs.windows(2) {
+            assert!(record(
+                &mut history,
+                ConfigSource::User,
+                masked_diff(&spec, &pair[0], &pair[1])
+            ));
+        }
+        assert!(!record(
+            &mut history,
+            ConfigSource::User,
+            masked_diff(&spec, &configs[2], &configs[2])
+        ));
+        assert_eq!(history.len(), 2);
+        assert!(history[0].changes[0].masked);
+        assert_eq!(
+            history[0].changes[0].new,
+            Some(json!(crate::config::spec::MASK))
+        );
+
+        let (config, kept) = roll_back(&history, 1, configs[2].clone()).unwrap();
+        assert_eq!(config, configs[1]);
+        assert!(kept.is_empty());
+
+        assert!(roll_back(&history, 0, configs[2].clone()).is_err());
+        history.insert(
+            0,
+            ConfigRevision {
+                revision: 0,
+                time: Utc::now(),
+                source: ConfigSource::Update,
+                changes: Vec::new(),
+            },
+        );
+        let (config, kept) = roll_back(&history, 0, configs[2].clone()).unwrap();
+        assert_eq!(config["host"], json!("localhost"));
+        assert_eq!(config["password"], json!("correct horse"));
+        assert_eq!(kept, vec![ConfigPath::from("password")]);
+
+        // a value added since the revision is removed again
+        let mut with_port = configs[2].clone();
+        with_port.insert("port".to_owned(), json!(8332));
+        assert!(record(
+            &mut history,
+            ConfigSource::User,
+            masked_diff(&spec, &configs[2], &with_port)
+        ));
+        assert_eq!(history.last().unwrap().changes[0].old, None);
+        let (config, kept) = roll_back(&history, 2, with_port).unwrap();
+        assert_eq!(config, configs[2]);
+        assert!(kept.is_empty());
+    }
+
+    #[test]
+    fn masked_pointers() {
+        let spec: ConfigSpec = serde_json::from_value(json!({
+            "tor-key": {
+                "type": "pointer",
+                "name": "Tor Key",
+                "description": null,
+                "subtype": "package",
+                "target": "tor-key",
+                "package-id": "lnd",
+                "interface": "main",
+            },
+            "bitcoind": {
+                "type": "pointer",
+                "name": "Bitcoin Core RPC",
+                "description": null,
+                "subtype": "package",
+                "target": "config",
+                "package-id": "bitcoind",
+                "selector": "$.rpc",
+                "multi": false,
+            },
+        }))
+        .unwrap();
+        let old = json!({
+            "tor-key": null,
+            "bitcoind": { "username": "lnd", "password": "hunter2" },
+        });
+        let new = json!({
+            "tor-key": "private key",
+            "bitcoind": { "username": "lnd", "password": "correct horse" },
+        });
+        let changes = masked_diff(&spec, old.as_object().unwrap(), new.as_object().unwrap());
+        assert_eq!(changes.len(), 2);
+        assert!(changes.iter().all(|change| change.masked));
+        let recorded = serde_json::to_string(&changes).unwrap();
+        for secret in ["private key", "hunter2", "correct horse"] {
+            assert!(!recorded.contains(secret), "{}", recorded);
+        }
+    }
+}
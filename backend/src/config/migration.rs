@@ -0,0 +1,246 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use color_eyre::eyre::eyre;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tracing::instrument;
+
+use super::diff::{diff, ConfigChange, ConfigPath};
+use super::Config;
+use crate::context::RpcContext;
+use crate::id::ImageId;
+use crate::procedure::docker::DockerContainers;
+use crate::procedure::{PackageProcedure, ProcedureName};
+use crate::s9pk::manifest::PackageId;
+use crate::util::Version;
+use crate::volume::Volumes;
+use crate::{Error, ResultExt};
+
+/// A declarative change to a stored config
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+#[serde(tag = "type")]
+pub enum ConfigMigrationRule {
+    /// Renames the key at `path`, keeping it within the same object
+    Rename { path: ConfigPath, to: String },
+    /// Moves the value at `from` to `to`, creating any objects on the way
+    Move { from: ConfigPath, to: ConfigPath },
+    /// Sets `value` at `path` if it is missing or null
+    Default { path: ConfigPath, value: Value },
+}
+impl ConfigMigrationRule {
+    pub fn apply(&self, config: &mut Config) -> Result<(), Error> {
+        match self {
+            ConfigMigrationRule::Rename { path, to } => {
+                if let Some(value) = path.remove(config) {
+                    path.with_key(to).insert(config, value)?;
+                }
+            }
+            ConfigMigrationRule::Move { from, to } => {
+                if let Some(value) = from.remove(config) {
+                    to.insert(config, value)?;
+                }
+            }
+            ConfigMigrationRule::Default { path, value } => {
+                if path.get(config).map_or(true, Value::is_null) {
+                    path.insert(config, value.clone())?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// How a stored config is brought up to a newer config spec version
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum ConfigMigration {
+    Rules(Vec<ConfigMigrationRule>),
+    /// Receives the old config, and returns the migrated one
+    Procedure(PackageProcedure),
+}
+
+/// Config migrations keyed by the config spec version they migrate to, from the version before it
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct ConfigMigrations(pub BTreeMap<u32, ConfigMigration>);
+impl ConfigMigrations {
+    #[instrument]
+    pub fn validate(
+        &self,
+        spec_version: u32,
+        container: &Option<DockerContainers>,
+        eos_version: &Version,
+        volumes: &Volumes,
+        image_ids: &BTreeSet<ImageId>,
+    ) -> Result<(), Error> {
+        for (version, migration) in &self.0 {
+            if *version == 0 || *version > spec_version {
+                return Err(Error::new(
+                    eyre!(
+                        "Config migration to {} is outside of config spec versions 1..={}",
+                        version,
+                        spec_version
+                    ),
+                    crate::ErrorKind::ValidateS9pk,
+                ));
+            }
+            if let ConfigMigration::Procedure(procedure) = migration {
+                procedure
+                    .validate(container, eos_version, volumes, image_ids, true)
+                    .with_ctx(|_| {
+                        (
+                            crate::ErrorKind::ValidateS9pk,
+                            format!("Config Migration to {}", version),
+                        )
+                    })?;
+            }
+        }
+        Ok(())
+    }
+
+    fn between(&self, from: u32, to: u32) -> impl Iterator<Item = (&u32, &ConfigMigration)> {
+        self.0
+            .iter()
+            .filter(move |(version, _)| from < **version && **version <= to)
+    }
+
+    /// Migrates `config` from config spec version `from` to `to`, running the migration to each version in between
+    #[instrument(skip(ctx, config))]
+    pub async fn migrate(
+        &self,
+        container: &Option<DockerContainers>,
+        ctx: &RpcContext,
+        pkg_id: &PackageId,
+        pkg_version: &Version,
+        volumes: &Volumes,
+        from: u32,
+        to: u32,
+        mut config: Config,
+    ) -> Result<Config, Error> {
+        for (version, migration) in self.between(from, to) {
+            match migration {
+                ConfigMigration::Rules(rules) => {
+                    for rule in rules {
+                        rule.apply(&mut config)?;
+                    }
+                }
+                ConfigMigration::Procedure(procedure) => {
+                    config = procedure
+                        .sandboxed(
+                            container,
+                            ctx,
+                            pkg_id,
+                            pkg_version,
+                            volumes,
+                            Some(&config),
+                            None,
+                            ProcedureName::ConfigMigration(*version),
+                        )
+                        .await?
+                        .map_err(|(_, e)| {
+                            Error::new(
+                                eyre!("Config migration to {} failed: {}", version, e),
+                                crate::ErrorKind::MigrationFailed,
+                            )
+                        })?;
+                }
+            }
+        }
+        Ok(config)
+    }
+
+    /// What migrating `config` from config spec version `from` to `to` would change, without running any procedures
+    pub fn preview(
+        &self,
+        from: u32,
+        to: u32,
+        config: &Config,
+    ) -> Result<ConfigMigrationPreview, Error> {
+        let mut migrated = config.clone();
+        let mut skipped = Vec::new();
+        for (version, migration) in self.between(from, to) {
+            match migration {
+                ConfigMigration::Rules(rules) => {
+                    for rule in rules {
+                        rule.apply(&mut migrated)?;
+                    }
+                }
+                ConfigMigration::Procedure(_) => skipped.push(*version),
+            }
+        }
+        Ok(ConfigMigrationPreview {
+            from,
+            to,
+            changes: diff(config, &migrated),
+            skipped,
+        })
+    }
+}
+
+/// The dry run of a config migration
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ConfigMigrationPreview {
+    pub from: u32,
+    pub to: u32,
+    pub changes: Vec<ConfigChange>,
+    /// Migrations that run a procedure, whose changes are not part of the preview
+    pub skipped: Vec<u32>,
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn migrate_rules() {
+        let migrations: ConfigMigrations = serde_json::from_value(json!({
+            "1": [
+                { "type": "rename", "path": "/rpc/user", "to": "username" },
+                { "type": "move", "from": "/pruning", "to": "/advanced/pruning" },
+            ],
+            "2": [
+                { "type": "default", "path": "/advanced/dbcache", "value": 450 },
+                { "type": "default", "path": "/rpc/username", "value": "bitcoin" },
+            ],
+        }))
+        .unwrap();
+        let config = json!({
+            "rpc": { "user": "satoshi" },
+            "pruning": "disabled",
+        });
+        let preview = migrations
+            .preview(0, 2, config.as_object().unwrap())
+            .unwrap();
+        assert!(preview.skipped.is_empty());
+        let changes = preview
+            .changes
+            .iter()
+            .map(|c| (c.path.to_string(), c.new.clone()))
+            .collect::<BTreeMap<_, _>>();
+        assert_eq!(
+            changes,
+            [
+                (
+                    "/advanced",
+                    Some(json!({ "pruning": "disabled", "dbcache": 450 }))
+                ),
+                ("/pruning", None),
+                ("/rpc/user", None),
+                ("/rpc/username", Some(json!("satoshi"))),
+            ]
+            .into_iter()
+            .map(|(path, value)| (path.to_owned(), value))
+            .collect()
+        );
+        let changes = migrations
+            .preview(1, 2, config.as_object().unwrap())
+            .unwrap()
+            .changes;
+        assert!(changes
+            .iter()
+            .any(|c| c.path.to_string() == "/rpc/username" && c.new == Some(json!("bitcoin"))));
+    }
+}
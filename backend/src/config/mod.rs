@@ -28,6 +28,9 @@ use crate::util::serde::{display_serializable, parse_stdin_deserializable, IoFor
 use crate::Error;
 
 pub mod action;
+pub mod diff;
+pub mod history;
+pub mod migration;
 pub mod spec;
 pub mod util;
 
@@ -35,6 +38,7 @@ pub use spec::{ConfigSpec, Defaultable};
 use util::NumRange;
 
 use self::action::{ConfigActions, ConfigRes};
+use self::history::{ConfigRevision, ConfigSource};
 use self::spec::{ConfigPointerReceipts, PackagePointerSpec, ValueSpecPointer};
 
 pub type Config = serde_json::Map<String, Value>;
@@ -159,7 +163,7 @@ pub fn verify_spec(#[arg] path: PathBuf) -> Result<(), Error> {
     Ok(())
 }
 
-#[command(subcommands(get, set))]
+#[command(subcommands(get, set, history::history, history::diff, history::rollback))]
 pub fn config(#[arg] id: PackageId) -> Result<PackageId, Error> {
     Ok(id)
 }
@@ -276,6 +280,7 @@ pub struct ConfigReceipts {
     dependency_errors: LockReceipt<DependencyErrors, String>,
     manifest_dependencies_config: LockReceipt<DependencyConfig, (String, String)>,
     docker_containers: LockReceipt<DockerContainers, String>,
+    config_history: LockReceipt<Vec<ConfigRevision>, String>,
 }
 
 impl ConfigReceipts {
@@ -387,6 +392,13 @@ impl ConfigReceipts {
             .and_then(|x| x.manifest().containers())
             .make_locker(LockType::Write)
             .add_to_keys(locks);
+        let config_history = crate::db::DatabaseModel::new()
+            .package_data()
+            .star()
+            .installed()
+            .map(|x| x.config_history())
+            .make_locker(LockType::Write)
+            .add_to_keys(locks);
 
         move |skeleton_key| {
             Ok(Self {
@@ -407,6 +419,7 @@ impl ConfigReceipts {
                 dependency_errors: dependency_errors.verify(skeleton_key)?,
                 manifest_dependencies_config: manifest_dependencies_config.verify(skeleton_key)?,
                 docker_containers: docker_containers.verify(skeleton_key)?,
+                config_history: config_history.verify(skeleton_key)?,
             })
         }
     }
@@ -427,6 +440,7 @@ pub async fn set_dry(
         &mut tx,
         &id,
         config,
+        ConfigSource::User,
         &timeout,
         true,
         &mut BTreeMap::new(),
@@ -444,16 +458,29 @@ pub async fn set_dry(
 pub async fn set_impl(
     ctx: RpcContext,
     (id, config, timeout): (PackageId, Option<Config>, Option<Duration>),
+) -> Result<(), Error> {
+    set_from(&ctx, &id, config, ConfigSource::User, timeout).await
+}
+
+/// Sets the config of `id` like [`set_impl`], recording `source` in its config history
+#[instrument(skip(ctx, config))]
+pub async fn set_from(
+    ctx: &RpcContext,
+    id: &PackageId,
+    config: Option<Config>,
+    source: ConfigSource,
+    timeout: Option<Duration>,
 ) -> Result<(), Error> {
     let mut db = ctx.db.handle();
     let mut tx = db.begin().await?;
     let mut breakages = BTreeMap::new();
     let locks = ConfigReceipts::new(&mut tx).await?;
     configure(
-        &ctx,
+        ctx,
         &mut tx,
-        &id,
+        id,
         config,
+        source,
         &timeout,
         false,
         &mut BTreeMap::new(),
@@ -471,6 +498,7 @@ pub async fn configure<'a, Db: DbHandle>(
     db: &'a mut Db,
     id: &PackageId,
     config: Option<Config>,
+    source: ConfigSource,
     timeout: &Option<Duration>,
     dry_run: bool,
     overrides: &mut BTreeMap<PackageId, Config>,
@@ -478,7 +506,7 @@ pub async fn configure<'a, Db: DbHandle>(
     receipts: &ConfigReceipts,
 ) -> Result<(), Error> {
     configure_rec(
-        ctx, db, id, config, timeout, dry_run, overrides, breakages, receipts,
+        ctx, db, id, config, source, timeout, dry_run, overrides, breakages, receipts,
     )
     .await?;
     receipts.configured.set(db, true, &id).await?;
@@ -491,6 +519,7 @@ pub fn configure_rec<'a, Db: DbHandle>(
     db: &'a mut Db,
     id: &'a PackageId,
     config: Option<Config>,
+    source: ConfigSource,
     timeout: &'a Option<Duration>,
     dry_run: bool,
     overrides: &'a mut BTreeMap<PackageId, Config>,
@@ -607,6 +636,21 @@ pub fn configure_rec<'a, Db: DbHandle>(
                 }
             }
 
+            // record the change, without any secrets
+            let mut history = receipts
+                .config_history
+                .get(db, id)
+                .await?
+                .ok_or_else(not_found)?;
+            let changes = history::masked_diff(
+                &spec,
+                old_config.as_ref().unwrap_or(&Config::new()),
+                &config,
+            );
+            if history::record(&mut history, source, changes) {
+                receipts.config_history.set(db, history, id).await?;
+            }
+
             // track dependency health checks
             current_dependencies = current_dependencies.map(|x| {
                 x.into_iter()
@@ -634,6 +678,7 @@ pub fn configure_rec<'a, Db: DbHandle>(
         remove_from_current_dependents_lists(
             db,
             id,
+            &dependencies,
             &prev_current_dependencies,
             &receipts.current_dependents,
         )
@@ -641,6 +686,7 @@ pub fn configure_rec<'a, Db: DbHandle>(
         add_dependent_to_current_dependents_lists(
             db,
             id,
+            &dependencies,
             &current_dependencies,
             &receipts.current_dependents,
         )
@@ -723,7 +769,15 @@ pub fn configure_rec<'a, Db: DbHandle>(
                     if let PackagePointerSpec::Config(cfg_ptr) = ptr {
                         if cfg_ptr.select(&next) != cfg_ptr.select(&prev) {
                             if let Err(e) = configure_rec(
-                                ctx, db, dependent, None, timeout, dry_run, overrides, breakages,
+                                ctx,
+                                db,
+                                dependent,
+                                None,
+                                ConfigSource::Dependency { id: id.clone() },
+                                timeout,
+                                dry_run,
+                                overrides,
+                                breakages,
                                 receipts,
                             )
                             .await
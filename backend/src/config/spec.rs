@@ -24,6 +24,7 @@ use super::util::{self, CharSet, NumRange, UniqueBy, STATIC_NULL};
 use super::{Config, MatchError, NoMatchWithPath, TimeoutError, TypeOf};
 use crate::config::ConfigurationError;
 use crate::context::RpcContext;
+use crate::dependencies::resolve_provider;
 use crate::net::interface::InterfaceId;
 use crate::s9pk::manifest::{Manifest, PackageId};
 use crate::Error;
@@ -366,6 +367,31 @@ impl ValueSpecAny {
             ValueSpecAny::Union(u) => u.name.as_str(),
         }
     }
+    /// Replaces every `masked` string within `value` with [`MASK`], and every pointer value too,
+    /// since those can hold private keys or the secrets of other packages
+    pub fn mask(&self, value: &mut Value) {
+        fn mask_each(value: &mut Value, f: impl Fn(&mut Value)) {
+            if let Value::Array(values) = value {
+                values.iter_mut().for_each(f)
+            }
+        }
+        match self {
+            ValueSpecAny::String(s) if s.inner.inner.inner.masked => mask_string(value),
+            ValueSpecAny::Object(o) => o.inner.mask(value),
+            ValueSpecAny::Union(u) => u.inner.inner.mask(value),
+            ValueSpecAny::List(ValueSpecList::String(l)) if l.inner.inner.spec.masked => {
+                mask_each(value, mask_string)
+            }
+            ValueSpecAny::List(ValueSpecList::Object(l)) => {
+                mask_each(value, |v| l.inner.inner.spec.mask(v))
+            }
+            ValueSpecAny::List(ValueSpecList::Union(l)) => {
+                mask_each(value, |v| l.inner.inner.spec.inner.mask(v))
+            }
+            ValueSpecAny::Pointer(_) if !value.is_null() => *value = Value::String(MASK.to_owned()),
+            _ => (),
+        }
+    }
 }
 #[async_trait]
 impl ValueSpec for ValueSpecAny {
@@ -1047,6 +1073,13 @@ impl ValueSpec for ValueSpecObject {
         }
     }
 }
+impl ValueSpecObject {
+    fn mask(&self, value: &mut Value) {
+        if let Value::Object(o) = value {
+            self.spec.mask(o)
+        }
+    }
+}
 impl DefaultableWith for ValueSpecObject {
     type DefaultSpec = Config;
     type Error = crate::util::Never;
@@ -1158,6 +1191,24 @@ impl ConfigSpec {
             .iter()
             .any(|(k, v)| v.requires(id, cfg.get(k).unwrap_or(&STATIC_NULL)))
     }
+
+    /// Replaces every `masked` string within `cfg` with [`MASK`], so it can be kept or shown without leaking secrets
+    pub fn mask(&self, cfg: &mut Config) {
+        for (k, vs) in self.0.iter() {
+            if let Some(v) = cfg.get_mut(k) {
+                vs.mask(v);
+            }
+        }
+    }
+}
+
+/// What a `masked` string is replaced with
+pub const MASK: &str = "********";
+
+fn mask_string(value: &mut Value) {
+    if value.is_string() {
+        *value = Value::String(MASK.to_owned());
+    }
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -1455,6 +1506,19 @@ impl<'de> serde::de::Deserialize<'de> for ValueSpecUnion {
     }
 }
 
+impl ValueSpecUnion {
+    fn mask(&self, value: &mut Value) {
+        if let Value::Object(o) = value {
+            let variant = match o.get(&self.tag.id) {
+                Some(Value::String(tag)) => self.variants.get(tag),
+                _ => None,
+            };
+            if let Some(variant) = variant {
+                variant.mask(o)
+            }
+        }
+    }
+}
 #[async_trait]
 impl ValueSpec for ValueSpecUnion {
     fn matches(&self, value: &Value) -> Result<(), NoMatchWithPath> {
@@ -1696,7 +1760,7 @@ impl PackagePointerSpec {
             PackagePointerSpec::Config(ConfigPointer { package_id, .. }) => package_id,
         }
     }
-    async fn deref<Db: DbHandle>(
+    pub async fn deref<Db: DbHandle>(
         &self,
         ctx: &RpcContext,
         db: &mut Db,
@@ -1707,14 +1771,35 @@ impl PackagePointerSpec {
         match &self {
             PackagePointerSpec::TorKey(key) => key.deref(&manifest.id, &ctx.secret_store).await,
             PackagePointerSpec::TorAddress(tor) => {
-                tor.deref(db, &receipts.interface_addresses_receipt).await
+                let package_id = self.provider(db, manifest).await?;
+                tor.deref(db, &package_id, &receipts.interface_addresses_receipt)
+                    .await
             }
             PackagePointerSpec::LanAddress(lan) => {
-                lan.deref(db, &receipts.interface_addresses_receipt).await
+                let package_id = self.provider(db, manifest).await?;
+                lan.deref(db, &package_id, &receipts.interface_addresses_receipt)
+                    .await
+            }
+            PackagePointerSpec::Config(cfg) => {
+                let package_id = self.provider(db, manifest).await?;
+                cfg.deref(ctx, db, &package_id, config_overrides, receipts)
+                    .await
             }
-            PackagePointerSpec::Config(cfg) => cfg.deref(ctx, db, config_overrides, receipts).await,
         }
     }
+    /// The installed package this points into, which is the package providing it if it is a virtual package
+    async fn provider<Db: DbHandle>(
+        &self,
+        db: &mut Db,
+        manifest: &Manifest,
+    ) -> Result<PackageId, ConfigurationError> {
+        let range = manifest.dependencies.version_range(self.package_id());
+        Ok(resolve_provider(db, self.package_id(), &range)
+            .await
+            .map_err(ConfigurationError::SystemError)?
+            .map(|(provider, _)| provider)
+            .unwrap_or_else(|| self.package_id().clone()))
+    }
 }
 impl fmt::Display for PackagePointerSpec {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
@@ -1790,11 +1875,12 @@ impl TorAddressPointer {
     async fn deref<Db: DbHandle>(
         &self,
         db: &mut Db,
+        package_id: &PackageId,
         receipt: &InterfaceAddressesReceipt,
     ) -> Result<Value, ConfigurationError> {
         let addr = receipt
             .interface_addresses
-            .get(db, (&self.package_id, &self.interface))
+            .get(db, (package_id, &self.interface))
             .await
             .map_err(|e| ConfigurationError::SystemError(Error::from(e)))?
             .and_then(|addresses| addresses.tor_address);
@@ -1864,11 +1950,12 @@ impl LanAddressPointer {
     async fn deref<Db: DbHandle>(
         &self,
         db: &mut Db,
+        package_id: &PackageId,
         receipts: &InterfaceAddressesReceipt,
     ) -> Result<Value, ConfigurationError> {
         let addr = receipts
             .interface_addresses
-            .get(db, (&self.package_id, &self.interface))
+            .get(db, (package_id, &self.interface))
             .await
             .ok()
             .flatten()
@@ -1943,13 +2030,14 @@ impl ConfigPointer {
         &self,
         ctx: &RpcContext,
         db: &mut Db,
+        package_id: &PackageId,
         config_overrides: &BTreeMap<PackageId, Config>,
         receipts: &ConfigPointerReceipts,
     ) -> Result<Value, ConfigurationError> {
-        if let Some(cfg) = config_overrides.get(&self.package_id) {
+        if let Some(cfg) = config_overrides.get(package_id) {
             Ok(self.select(&Value::Object(cfg.clone())))
         } else {
-            let id = &self.package_id;
+            let id = package_id;
             let version = receipts.manifest_version.get(db, id).await.ok().flatten();
             let cfg_actions = receipts.config_actions.get(db, id).await.ok().flatten();
             let volumes = receipts.manifest_volumes.get(db, id).await.ok().flatten();
@@ -1957,7 +2045,7 @@ impl ConfigPointer {
                 (&version, &cfg_actions, &volumes)
             {
                 let cfg_res = cfg_actions
-                    .get(ctx, &self.package_id, version, volumes)
+                    .get(ctx, package_id, version, volumes)
                     .await
                     .map_err(|e| ConfigurationError::SystemError(e))?;
                 if let Some(cfg) = cfg_res.config {
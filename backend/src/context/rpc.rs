@@ -25,12 +25,14 @@ use crate::disk::OsPartitionInfo;
 use crate::hostname::HostNameReceipt;
 use crate::init::{init_postgres, pgloader};
 use crate::install::cleanup::{cleanup_failed, uninstall, CleanupFailedReceipts};
+use crate::manager::metrics::PackageMetrics;
 use crate::manager::ManagerMap;
 use crate::middleware::auth::HashSessionToken;
 use crate::net::net_controller::NetController;
 use crate::net::ssl::SslManager;
 use crate::net::wifi::WpaCli;
 use crate::notifications::NotificationManager;
+use crate::s9pk::manifest::PackageId;
 use crate::setup::password_hash;
 use crate::shutdown::Shutdown;
 use crate::status::{MainStatus, Status};
@@ -52,6 +54,8 @@ pub struct RpcContextConfig {
     pub tor_socks: Option<SocketAddr>,
     pub dns_bind: Option<Vec<SocketAddr>>,
     pub revision_cache_size: Option<usize>,
+    /// How many services may be starting at once during boot
+    pub startup_parallelism: Option<usize>,
     pub datadir: Option<PathBuf>,
     pub log_server: Option<Url>,
 }
@@ -135,8 +139,10 @@ pub struct RpcContextSeed {
     pub net_controller: NetController,
     pub managers: ManagerMap,
     pub revision_cache_size: usize,
+    pub startup_parallelism: usize,
     pub revision_cache: RwLock<VecDeque<Arc<Revision>>>,
     pub metrics_cache: RwLock<Option<crate::system::Metrics>>,
+    pub package_metrics_cache: RwLock<BTreeMap<PackageId, PackageMetrics>>,
     pub shutdown: broadcast::Sender<Option<Shutdown>>,
     pub tor_socks: SocketAddr,
     pub notification_manager: NotificationManager,
@@ -269,8 +275,10 @@ impl RpcContext {
             net_controller,
             managers,
             revision_cache_size: base.revision_cache_size.unwrap_or(512),
+            startup_parallelism: base.startup_parallelism.unwrap_or(4),
             revision_cache: RwLock::new(VecDeque::new()),
             metrics_cache,
+            package_metrics_cache: RwLock::new(BTreeMap::new()),
             shutdown,
             tor_socks: tor_proxy,
             notification_manager,
@@ -307,7 +315,7 @@ impl RpcContext {
 
     #[instrument(skip(self))]
     pub async fn shutdown(self) -> Result<(), Error> {
-        self.managers.empty().await?;
+        self.managers.empty(&self).await?;
         self.secret_store.close().await;
         self.is_closed.store(true, Ordering::SeqCst);
         // TODO: shutdown http servers
@@ -362,7 +370,7 @@ impl RpcContext {
                                     MainStatus::Stopped
                                 }
                             }
-                            MainStatus::Running { .. } => {
+                            MainStatus::Running { .. } | MainStatus::Paused { .. } => {
                                 MainStatus::Starting { restarting: false }
                             }
                             a => a.clone(),
@@ -370,6 +378,7 @@ impl RpcContext {
                         let new_package = PackageDataEntry::Installed {
                             installed: InstalledPackageDataEntry {
                                 status: Status { main, ..status },
+                                usage: None,
                                 ..installed
                             },
                             static_files,
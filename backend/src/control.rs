@@ -3,8 +3,11 @@ use std::collections::BTreeMap;
 use color_eyre::eyre::eyre;
 use patch_db::{DbHandle, LockReceipt, LockType};
 use rpc_toolkit::command;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
 use tracing::instrument;
 
+use crate::config::not_found;
 use crate::context::RpcContext;
 use crate::dependencies::{
     break_all_dependents_transitive, heal_all_dependents_transitive, BreakageRes, DependencyError,
@@ -14,7 +17,38 @@ use crate::s9pk::manifest::PackageId;
 use crate::status::MainStatus;
 use crate::util::display_none;
 use crate::util::serde::display_serializable;
-use crate::Error;
+use crate::{Error, ResultExt};
+
+/// What the Manager does when the main procedure of a service exits without being asked to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum RestartPolicy {
+    Always,
+    /// Restart after a crash, but leave the service stopped after it exits successfully
+    OnFailure,
+    Never,
+}
+impl Default for RestartPolicy {
+    fn default() -> Self {
+        RestartPolicy::Always
+    }
+}
+impl std::str::FromStr for RestartPolicy {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        serde_json::from_value(Value::String(s.to_owned()))
+            .with_kind(crate::ErrorKind::Deserialization)
+    }
+}
+impl RestartPolicy {
+    pub fn restarts(&self, success: bool) -> bool {
+        match self {
+            RestartPolicy::Always => true,
+            RestartPolicy::OnFailure => !success,
+            RestartPolicy::Never => false,
+        }
+    }
+}
 
 #[derive(Clone)]
 pub struct StartReceipts {
@@ -144,6 +178,35 @@ pub async fn stop_common<Db: DbHandle>(
     Ok(last_status)
 }
 
+/// Like [stop_common], for when the Manager leaves a service stopped on its own rather than a user stopping it.
+/// Returns the status it replaced, or None, changing nothing, if the service was no longer meant to be running.
+#[instrument(skip(db))]
+pub async fn exit_common<Db: DbHandle>(
+    db: &mut Db,
+    id: &PackageId,
+    exited: MainStatus,
+) -> Result<Option<MainStatus>, Error> {
+    let mut tx = db.begin().await?;
+    let receipts = StopReceipts::new(&mut tx, id).await?;
+    let last_status = receipts.status.get(&mut tx).await?;
+    if !last_status.running() {
+        return Ok(None);
+    }
+    receipts.status.set(&mut tx, exited).await?;
+
+    tx.save().await?;
+    break_all_dependents_transitive(
+        db,
+        id,
+        DependencyError::NotRunning,
+        &mut BTreeMap::new(),
+        &receipts.breaks,
+    )
+    .await?;
+
+    Ok(Some(last_status))
+}
+
 #[command(
     subcommands(self(stop_impl(async)), stop_dry),
     display(display_none),
@@ -206,3 +269,179 @@ pub async fn restart(#[context] ctx: RpcContext, #[arg] id: PackageId) -> Result
 
     Ok(())
 }
+
+/// Freezes the service in place, keeping its memory, until it is resumed
+#[command(display(display_none), metadata(sync_db = true))]
+#[instrument(skip(ctx))]
+pub async fn pause(#[context] ctx: RpcContext, #[arg] id: PackageId) -> Result<(), Error> {
+    let mut db = ctx.db.handle();
+    let mut tx = db.begin().await?;
+    let receipts = StopReceipts::new(&mut tx, &id).await?;
+    let started = match receipts.status.get(&mut tx).await? {
+        MainStatus::Running { started, .. } => started,
+        _ => {
+            return Err(Error::new(
+                eyre!("{} is not running", id),
+                crate::ErrorKind::InvalidRequest,
+            ))
+        }
+    };
+    receipts
+        .status
+        .set(&mut tx, MainStatus::Paused { started })
+        .await?;
+    break_all_dependents_transitive(
+        &mut tx,
+        &id,
+        DependencyError::Paused,
+        &mut BTreeMap::new(),
+        &receipts.breaks,
+    )
+    .await?;
+
+    tx.commit().await?;
+    drop(receipts);
+
+    let version = crate::db::DatabaseModel::new()
+        .package_data()
+        .idx_model(&id)
+        .and_then(|pde| pde.installed())
+        .map(|installed| installed.manifest().version())
+        .get(&mut db)
+        .await?
+        .into_owned()
+        .ok_or_else(not_found)?;
+    ctx.managers
+        .get(&(id, version))
+        .await
+        .ok_or_else(|| Error::new(eyre!("Manager not found"), crate::ErrorKind::InvalidRequest))?
+        .synchronize()
+        .await;
+
+    Ok(())
+}
+
+#[command(display(display_none), metadata(sync_db = true))]
+#[instrument(skip(ctx))]
+pub async fn resume(#[context] ctx: RpcContext, #[arg] id: PackageId) -> Result<(), Error> {
+    let mut db = ctx.db.handle();
+    let mut tx = db.begin().await?;
+    let receipts = StartReceipts::new(&mut tx, &id).await?;
+    let version = receipts.version.get(&mut tx).await?;
+    let started = match receipts.status.get(&mut tx).await? {
+        MainStatus::Paused { started } => started,
+        _ => {
+            return Err(Error::new(
+                eyre!("{} is not paused", id),
+                crate::ErrorKind::InvalidRequest,
+            ))
+        }
+    };
+    receipts
+        .status
+        .set(
+            &mut tx,
+            MainStatus::Running {
+                started,
+                health: BTreeMap::new(),
+            },
+        )
+        .await?;
+    heal_all_dependents_transitive(&ctx, &mut tx, &id, &receipts.dependency_receipt).await?;
+
+    tx.commit().await?;
+    drop(receipts);
+
+    ctx.managers
+        .get(&(id, version))
+        .await
+        .ok_or_else(|| Error::new(eyre!("Manager not found"), crate::ErrorKind::InvalidRequest))?
+        .synchronize()
+        .await;
+
+    Ok(())
+}
+
+/// Clears a crash loop, leaving the service stopped until it is started again
+#[command(
+    rename = "reset-crash-loop",
+    display(display_none),
+    metadata(sync_db = true)
+)]
+pub async fn reset_crash_loop(
+    #[context] ctx: RpcContext,
+    #[arg] id: PackageId,
+) -> Result<(), Error> {
+    let mut db = ctx.db.handle();
+    let mut tx = db.begin().await?;
+
+    let mut status = crate::db::DatabaseModel::new()
+        .package_data()
+        .idx_model(&id)
+        .and_then(|pde| pde.installed())
+        .map(|i| i.status().main())
+        .get_mut(&mut tx)
+        .await?;
+    if !matches!(&*status, Some(MainStatus::CrashLooping { .. })) {
+        return Err(Error::new(
+            eyre!("{} is not crash looping", id),
+            crate::ErrorKind::InvalidRequest,
+        ));
+    }
+    *status = Some(MainStatus::Stopped);
+    status.save(&mut tx).await?;
+    tx.commit().await?;
+
+    Ok(())
+}
+
+#[command(subcommands(set_autostart))]
+pub fn autostart() -> Result<(), Error> {
+    Ok(())
+}
+
+/// Whether the service is started on boot, if it was running before
+#[command(rename = "set", display(display_none))]
+pub async fn set_autostart(
+    #[context] ctx: RpcContext,
+    #[arg] id: PackageId,
+    #[arg] autostart: bool,
+) -> Result<(), Error> {
+    let mut db = ctx.db.handle();
+    crate::db::DatabaseModel::new()
+        .package_data()
+        .idx_model(&id)
+        .and_then(|pde| pde.installed())
+        .expect(&mut db)
+        .await
+        .with_kind(crate::ErrorKind::NotFound)?
+        .autostart()
+        .put(&mut db, &autostart)
+        .await?;
+    Ok(())
+}
+
+#[command(rename = "restart-policy", subcommands(set_restart_policy))]
+pub fn restart_policy() -> Result<(), Error> {
+    Ok(())
+}
+
+#[command(rename = "set", display(display_none))]
+pub async fn set_restart_policy(
+    #[context] ctx: RpcContext,
+    #[arg] id: PackageId,
+    #[arg] policy: RestartPolicy,
+) -> Result<(), Error> {
+    let mut db = ctx.db.handle();
+    crate::db::DatabaseModel::new()
+        .package_data()
+        .idx_model(&id)
+        .and_then(|pde| pde.installed())
+        .expect(&mut db)
+        .await
+        .with_kind(crate::ErrorKind::NotFound)?
+        .restart_policy()
+        .put(&mut db, &policy)
+        .await?;
+    Ok(())
+}
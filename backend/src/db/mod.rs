@@ -59,7 +59,7 @@ async fn ws_handler<
     Ok(())
 }
 
-async fn subscribe_to_session_kill(
+pub(crate) async fn subscribe_to_session_kill(
     ctx: &RpcContext,
     token: HashSessionToken,
 ) -> oneshot::Receiver<()> {
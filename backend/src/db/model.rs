@@ -6,10 +6,11 @@ use chrono::{DateTime, Utc};
 use emver::VersionRange;
 use isocountry::CountryCode;
 use itertools::Itertools;
+use models::DependencyEvent;
 use openssl::hash::MessageDigest;
 use openssl::x509::X509;
 use patch_db::json_ptr::JsonPointer;
-use patch_db::{HasModel, Map, MapModel, OptionModel};
+use patch_db::{DbHandle, HasModel, Map, MapModel, OptionModel};
 use reqwest::Url;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
@@ -17,13 +18,17 @@ use ssh_key::private::Ed25519PrivateKey;
 use ssh_key::public::Ed25519PublicKey;
 use torut::onion::TorSecretKeyV3;
 
+use crate::config::history::ConfigRevision;
 use crate::config::spec::{PackagePointerSpec, SystemPointerSpec};
+use crate::control::RestartPolicy;
 use crate::hostname::{generate_hostname, generate_id};
 use crate::install::progress::InstallProgress;
+use crate::manager::metrics::PackageUsage;
 use crate::net::interface::InterfaceId;
 use crate::net::net_utils::{get_iface_ipv4_addr, get_iface_ipv6_addr};
 use crate::s9pk::manifest::{Manifest, ManifestModel, PackageId};
-use crate::status::health_check::HealthCheckId;
+use crate::scheduled_task::{ScheduledTaskId, ScheduledTaskStatus};
+use crate::status::health_check::{HealthCheckId, RemediationKind};
 use crate::status::Status;
 use crate::util::Version;
 use crate::version::{Current, VersionT};
@@ -66,6 +71,7 @@ impl Database {
                     backup_progress: None,
                     updated: false,
                     update_progress: None,
+                    shutdown_progress: None,
                 },
                 wifi: WifiInfo {
                     ssids: Vec::new(),
@@ -155,6 +161,9 @@ pub struct ServerStatus {
     pub updated: bool,
     #[model]
     pub update_progress: Option<UpdateProgress>,
+    #[model]
+    #[serde(default)]
+    pub shutdown_progress: Option<ShutdownProgress>,
 }
 
 #[derive(Debug, Deserialize, Serialize, HasModel)]
@@ -164,6 +173,17 @@ pub struct UpdateProgress {
     pub downloaded: u64,
 }
 
+#[derive(Debug, Deserialize, Serialize, HasModel)]
+#[serde(rename_all = "kebab-case")]
+pub struct ShutdownProgress {
+    /// The services currently being stopped
+    pub stopping: BTreeSet<PackageId>,
+    pub stopped: usize,
+    pub total: usize,
+    /// Any services still running at this point are killed
+    pub kill_at: DateTime<Utc>,
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 #[serde(rename_all = "kebab-case")]
 pub struct WifiInfo {
@@ -328,6 +348,74 @@ pub struct InstalledPackageDataEntry {
     pub current_dependencies: CurrentDependencies,
     #[model]
     pub interface_addresses: InterfaceAddressMap,
+    /// Whether to start the service on boot if it was running before
+    #[serde(default = "default_autostart")]
+    pub autostart: bool,
+    #[serde(default)]
+    pub restart_policy: RestartPolicy,
+    #[serde(default)]
+    pub scheduled_tasks: BTreeMap<ScheduledTaskId, ScheduledTaskStatus>,
+    /// Roughly the resources the service is using, while it runs
+    #[serde(default)]
+    pub usage: Option<PackageUsage>,
+    /// Things the system did to the service on its own, oldest first
+    #[serde(default)]
+    pub events: Vec<PackageEvent>,
+    /// Every change made to the config, oldest first
+    #[serde(default)]
+    pub config_history: Vec<ConfigRevision>,
+}
+fn default_autostart() -> bool {
+    true
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct PackageEvent {
+    pub time: DateTime<Utc>,
+    #[serde(flatten)]
+    pub kind: PackageEventKind,
+}
+impl PackageEvent {
+    /// Only this many of the most recent events are kept
+    pub const HISTORY_LENGTH: usize = 100;
+
+    /// Appends this event to the history of `id`, dropping the oldest events past [`Self::HISTORY_LENGTH`]
+    pub async fn record<Db: DbHandle>(self, db: &mut Db, id: &PackageId) -> Result<(), Error> {
+        let mut events = crate::db::DatabaseModel::new()
+            .package_data()
+            .idx_model(id)
+            .and_then(|pde| pde.installed())
+            .expect(db)
+            .await?
+            .events()
+            .get_mut(db)
+            .await?;
+        events.push(self);
+        let excess = events.len().saturating_sub(Self::HISTORY_LENGTH);
+        events.drain(..excess);
+        events.save(db).await?;
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+#[serde(tag = "type")]
+pub enum PackageEventKind {
+    #[serde(rename_all = "kebab-case")]
+    Remediation {
+        health_check: HealthCheckId,
+        failures: u32,
+        remediation: RemediationKind,
+        error: Option<String>,
+    },
+    #[serde(rename_all = "kebab-case")]
+    DependencyHook {
+        dependency: PackageId,
+        event: DependencyEvent,
+        error: Option<String>,
+    },
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -421,3 +509,31 @@ pub struct RecoveredPackageInfo {
     pub icon: String,
     pub version: Version,
 }
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+
+    /// An installed, configured and stopped package with the given dependency errors
+    pub fn installed_entry(
+        manifest: Manifest,
+        dependency_errors: serde_json::Value,
+    ) -> InstalledPackageDataEntry {
+        serde_json::from_value(serde_json::json!({
+            "status": {
+                "configured": true,
+                "main": { "status": "stopped" },
+                "dependency-errors": dependency_errors,
+            },
+            "marketplace-url": null,
+            "manifest": manifest,
+            "last-backup": null,
+            "system-pointers": [],
+            "dependency-info": {},
+            "current-dependents": {},
+            "current-dependencies": {},
+            "interface-addresses": {},
+        }))
+        .unwrap()
+    }
+}
@@ -1,11 +1,14 @@
 use std::cmp::Ordering;
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
 use std::time::Duration;
 
+use chrono::Utc;
+use clap::ArgMatches;
 use color_eyre::eyre::eyre;
 use emver::VersionRange;
 use futures::future::BoxFuture;
 use futures::FutureExt;
+use models::DependencyEvent;
 use patch_db::{
     DbHandle, HasModel, LockReceipt, LockTargetId, LockType, Map, MapModel, PatchDbHandle, Verifier,
 };
@@ -15,21 +18,26 @@ use serde::{Deserialize, Serialize};
 use tracing::instrument;
 
 use crate::config::action::{ConfigActions, ConfigRes};
+use crate::config::history::ConfigSource;
 use crate::config::spec::PackagePointerSpec;
 use crate::config::{not_found, Config, ConfigReceipts, ConfigSpec};
 use crate::context::RpcContext;
-use crate::db::model::{CurrentDependencies, CurrentDependents, InstalledPackageDataEntry};
+use crate::db::model::{
+    AllPackageData, CurrentDependencies, CurrentDependents, InstalledPackageDataEntry,
+    PackageDataEntry, PackageEvent, PackageEventKind,
+};
+use crate::id::ImageId;
 use crate::procedure::docker::DockerContainers;
 use crate::procedure::{NoOutput, PackageProcedure, ProcedureName};
 use crate::s9pk::manifest::{Manifest, PackageId};
 use crate::status::health_check::{HealthCheckId, HealthCheckResult};
 use crate::status::{MainStatus, Status};
-use crate::util::serde::display_serializable;
+use crate::util::serde::{display_serializable, IoFormat};
 use crate::util::{display_none, Version};
 use crate::volume::Volumes;
-use crate::Error;
+use crate::{Error, ResultExt};
 
-#[command(subcommands(configure))]
+#[command(subcommands(configure, graph))]
 pub fn dependency() -> Result<(), Error> {
     Ok(())
 }
@@ -49,6 +57,7 @@ pub enum DependencyError {
         error: String,
     }, // { "type": "config-unsatisfied", "error": "Bitcoin Core must have pruning set to manual." }
     NotRunning,   // { "type": "not-running" }
+    Paused,       // { "type": "paused" }
     #[serde(rename_all = "kebab-case")]
     HealthChecksFailed {
         failures: BTreeMap<HealthCheckId, HealthCheckResult>,
@@ -61,10 +70,11 @@ pub enum DependencyError {
 pub struct TryHealReceipts {
     status: LockReceipt<Status, String>,
     manifest: LockReceipt<Manifest, String>,
-    manifest_version: LockReceipt<Version, String>,
     current_dependencies: LockReceipt<CurrentDependencies, String>,
     dependency_errors: LockReceipt<DependencyErrors, String>,
     docker_containers: LockReceipt<DockerContainers, String>,
+    /// All packages, to find the ones that provide a virtual dependency
+    packages: LockReceipt<AllPackageData, ()>,
 }
 
 impl TryHealReceipts {
@@ -76,13 +86,6 @@ impl TryHealReceipts {
     }
 
     pub fn setup(locks: &mut Vec<LockTargetId>) -> impl FnOnce(&Verifier) -> Result<Self, Error> {
-        let manifest_version = crate::db::DatabaseModel::new()
-            .package_data()
-            .star()
-            .installed()
-            .map(|x| x.manifest().version())
-            .make_locker(LockType::Write)
-            .add_to_keys(locks);
         let status = crate::db::DatabaseModel::new()
             .package_data()
             .star()
@@ -119,14 +122,18 @@ impl TryHealReceipts {
             .and_then(|x| x.manifest().containers())
             .make_locker(LockType::Write)
             .add_to_keys(locks);
+        let packages = crate::db::DatabaseModel::new()
+            .package_data()
+            .make_locker(LockType::Read)
+            .add_to_keys(locks);
         move |skeleton_key| {
             Ok(Self {
                 status: status.verify(skeleton_key)?,
-                manifest_version: manifest_version.verify(skeleton_key)?,
                 current_dependencies: current_dependencies.verify(skeleton_key)?,
                 manifest: manifest.verify(skeleton_key)?,
                 dependency_errors: dependency_errors.verify(skeleton_key)?,
                 docker_containers: docker_containers.verify(skeleton_key)?,
+                packages: packages.verify(skeleton_key)?,
             })
         }
     }
@@ -150,6 +157,9 @@ impl DependencyError {
             (NotRunning, NotRunning) => Equal,
             (NotRunning, _) => Greater,
             (_, NotRunning) => Less,
+            (Paused, Paused) => Equal,
+            (Paused, _) => Greater,
+            (_, Paused) => Less,
             (HealthChecksFailed { .. }, HealthChecksFailed { .. }) => Equal,
             (HealthChecksFailed { .. }, _) => Greater,
             (_, HealthChecksFailed { .. }) => Less,
@@ -178,6 +188,7 @@ impl DependencyError {
             (DependencyError::NotRunning, _) | (_, DependencyError::NotRunning) => {
                 DependencyError::NotRunning
             }
+            (DependencyError::Paused, _) | (_, DependencyError::Paused) => DependencyError::Paused,
             (
                 DependencyError::HealthChecksFailed { failures: f0 },
                 DependencyError::HealthChecksFailed { failures: f1 },
@@ -204,26 +215,24 @@ impl DependencyError {
     ) -> BoxFuture<'a, Result<Option<Self>, Error>> {
         async move {
             let container = receipts.docker_containers.get(db, id).await?;
+            // a dependency on a virtual package is checked against the package providing it
+            let packages = receipts.packages.get(db).await?;
+            let (provider, provided_version) =
+                match select_provider(dependency, &info.version, installed_manifests(&packages)) {
+                    Some(provider) => provider,
+                    None => return Ok(Some(DependencyError::NotInstalled)),
+                };
             Ok(match self {
                 DependencyError::NotInstalled => {
-                    if receipts.status.get(db, dependency).await?.is_some() {
-                        DependencyError::IncorrectVersion {
-                            expected: info.version.clone(),
-                            received: Default::default(),
-                        }
-                        .try_heal(ctx, db, id, dependency, dependency_config, info, receipts)
-                        .await?
-                    } else {
-                        Some(DependencyError::NotInstalled)
+                    DependencyError::IncorrectVersion {
+                        expected: info.version.clone(),
+                        received: Default::default(),
                     }
+                    .try_heal(ctx, db, id, dependency, dependency_config, info, receipts)
+                    .await?
                 }
                 DependencyError::IncorrectVersion { expected, .. } => {
-                    let version: Version = receipts
-                        .manifest_version
-                        .get(db, dependency)
-                        .await?
-                        .unwrap_or_default();
-                    if version.satisfies(&expected) {
+                    if provided_version.satisfies(&expected) {
                         DependencyError::ConfigUnsatisfied {
                             error: String::new(),
                         }
@@ -232,7 +241,7 @@ impl DependencyError {
                     } else {
                         Some(DependencyError::IncorrectVersion {
                             expected,
-                            received: version,
+                            received: provided_version,
                         })
                     }
                 }
@@ -241,7 +250,7 @@ impl DependencyError {
                         receipts.manifest.get(db, id).await?.ok_or_else(not_found)?;
                     let dependency_manifest = receipts
                         .manifest
-                        .get(db, dependency)
+                        .get(db, &provider)
                         .await?
                         .ok_or_else(not_found)?;
 
@@ -251,7 +260,7 @@ impl DependencyError {
                         cfg_info
                             .get(
                                 ctx,
-                                dependency,
+                                &provider,
                                 &dependency_manifest.version,
                                 &dependency_manifest.volumes,
                             )
@@ -292,23 +301,38 @@ impl DependencyError {
                 DependencyError::NotRunning => {
                     let status = receipts
                         .status
-                        .get(db, dependency)
+                        .get(db, &provider)
                         .await?
                         .ok_or_else(not_found)?;
                     if status.main.running() {
-                        DependencyError::HealthChecksFailed {
-                            failures: BTreeMap::new(),
-                        }
-                        .try_heal(ctx, db, id, dependency, dependency_config, info, receipts)
-                        .await?
+                        DependencyError::Paused
+                            .try_heal(ctx, db, id, dependency, dependency_config, info, receipts)
+                            .await?
                     } else {
                         Some(DependencyError::NotRunning)
                     }
                 }
+                DependencyError::Paused => {
+                    let status = receipts
+                        .status
+                        .get(db, &provider)
+                        .await?
+                        .ok_or_else(not_found)?;
+                    match status.main {
+                        MainStatus::Paused { .. } => Some(DependencyError::Paused),
+                        _ => {
+                            DependencyError::HealthChecksFailed {
+                                failures: BTreeMap::new(),
+                            }
+                            .try_heal(ctx, db, id, dependency, dependency_config, info, receipts)
+                            .await?
+                        }
+                    }
+                }
                 DependencyError::HealthChecksFailed { .. } => {
                     let status = receipts
                         .status
-                        .get(db, dependency)
+                        .get(db, &provider)
                         .await?
                         .ok_or_else(not_found)?;
                     match status.main {
@@ -361,13 +385,14 @@ impl DependencyError {
                                 )
                                 .await?
                         }
+                        MainStatus::Paused { .. } => Some(DependencyError::Paused),
                         _ => return Ok(Some(DependencyError::NotRunning)),
                     }
                 }
                 DependencyError::Transitive => {
                     if receipts
                         .dependency_errors
-                        .get(db, dependency)
+                        .get(db, &provider)
                         .await?
                         .unwrap_or_default()
                         .0
@@ -397,6 +422,7 @@ impl std::fmt::Display for DependencyError {
                 write!(f, "Configuration Requirements Not Satisfied: {}", error)
             }
             DependencyError::NotRunning => write!(f, "Not Running"),
+            DependencyError::Paused => write!(f, "Paused"),
             DependencyError::HealthChecksFailed { failures } => {
                 write!(f, "Failed Health Check(s): ")?;
                 let mut comma = false;
@@ -430,6 +456,42 @@ pub struct BreakageRes(pub BTreeMap<PackageId, TaggedDependencyError>);
 
 #[derive(Clone, Debug, Default, Deserialize, Serialize)]
 pub struct Dependencies(pub BTreeMap<PackageId, DepInfo>);
+impl Dependencies {
+    #[instrument]
+    pub fn validate(
+        &self,
+        container: &Option<DockerContainers>,
+        eos_version: &Version,
+        volumes: &Volumes,
+        image_ids: &BTreeSet<ImageId>,
+    ) -> Result<(), Error> {
+        for (id, info) in &self.0 {
+            if info.requirement.required() && info.hooks.iter().next().is_some() {
+                return Err(Error::new(
+                    eyre!("Only optional dependencies can have hooks: {}", id),
+                    crate::ErrorKind::ValidateS9pk,
+                ));
+            }
+            for (event, hook) in info.hooks.iter() {
+                hook.validate(container, eos_version, volumes, image_ids, false)
+                    .with_ctx(|_| {
+                        (
+                            crate::ErrorKind::ValidateS9pk,
+                            format!("Dependency {} {} Hook", id, event),
+                        )
+                    })?;
+            }
+        }
+        Ok(())
+    }
+    /// The versions of `id` that are accepted, which is any version if `id` isn't declared
+    pub fn version_range(&self, id: &PackageId) -> VersionRange {
+        self.0
+            .get(id)
+            .map(|info| info.version.clone())
+            .unwrap_or(VersionRange::Any)
+    }
+}
 impl Map for Dependencies {
     type Key = PackageId;
     type Value = DepInfo;
@@ -455,6 +517,67 @@ impl DependencyRequirement {
     }
 }
 
+/// Finds the installed package a dependency on `id` resolves to, along with the version it offers.
+/// See [`select_provider`].
+#[instrument(skip(db))]
+pub async fn resolve_provider<Db: DbHandle>(
+    db: &mut Db,
+    id: &PackageId,
+    range: &VersionRange,
+) -> Result<Option<(PackageId, Version)>, Error> {
+    let packages = crate::db::DatabaseModel::new()
+        .package_data()
+        .get(db)
+        .await?
+        .into_owned();
+    Ok(select_provider(id, range, installed_manifests(&packages)))
+}
+
+/// The manifests of the packages in `packages` that are installed, in id order
+pub fn installed_manifests(packages: &AllPackageData) -> impl Iterator<Item = &Manifest> {
+    packages
+        .0
+        .values()
+        .filter_map(|pde| pde.installed())
+        .map(|installed| &installed.manifest)
+}
+
+/// The package a dependency on `id` resolves to: the package `id` itself if it is among `installed`,
+/// otherwise the first package that provides `id` within `range`, or failing that the first that provides it at all.
+pub fn select_provider<'a>(
+    id: &PackageId,
+    range: &VersionRange,
+    installed: impl IntoIterator<Item = &'a Manifest>,
+) -> Option<(PackageId, Version)> {
+    let mut satisfying = None;
+    let mut fallback = None;
+    for manifest in installed {
+        if &manifest.id == id {
+            return Some((id.clone(), manifest.version.clone()));
+        }
+        if let Some(version) = manifest.provides.get(id) {
+            if satisfying.is_none() && version.satisfies(range) {
+                satisfying = Some((manifest.id.clone(), version.clone()));
+            }
+            fallback.get_or_insert_with(|| (manifest.id.clone(), version.clone()));
+        }
+    }
+    satisfying.or(fallback)
+}
+
+/// The id under which `dependent` declares its dependency on `provider`:
+/// the provider's own id, or one of the virtual packages it provides.
+pub fn declared_dependency<'a>(dependent: &Manifest, provider: &'a Manifest) -> &'a PackageId {
+    if dependent.dependencies.0.contains_key(&provider.id) {
+        return &provider.id;
+    }
+    provider
+        .provides
+        .keys()
+        .find(|id| dependent.dependencies.0.contains_key(*id))
+        .unwrap_or(&provider.id)
+}
+
 #[derive(Clone, Debug, Deserialize, Serialize, HasModel)]
 #[serde(rename_all = "kebab-case")]
 pub struct DepInfo {
@@ -464,6 +587,8 @@ pub struct DepInfo {
     #[serde(default)]
     #[model]
     pub config: Option<DependencyConfig>,
+    #[serde(default)]
+    pub hooks: DependencyHooks,
 }
 impl DepInfo {
     pub async fn satisfied<Db: DbHandle>(
@@ -553,6 +678,42 @@ impl DependencyConfig {
     }
 }
 
+/// Procedures a dependent runs when something happens to one of its optional dependencies
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct DependencyHooks {
+    #[serde(default)]
+    pub installed: Option<PackageProcedure>,
+    #[serde(default)]
+    pub started: Option<PackageProcedure>,
+    #[serde(default)]
+    pub stopped: Option<PackageProcedure>,
+    #[serde(default)]
+    pub uninstalled: Option<PackageProcedure>,
+    #[serde(default)]
+    pub timeout: Option<crate::util::serde::Duration>,
+}
+impl DependencyHooks {
+    pub fn get(&self, event: DependencyEvent) -> Option<&PackageProcedure> {
+        match event {
+            DependencyEvent::Installed => self.installed.as_ref(),
+            DependencyEvent::Started => self.started.as_ref(),
+            DependencyEvent::Stopped => self.stopped.as_ref(),
+            DependencyEvent::Uninstalled => self.uninstalled.as_ref(),
+        }
+    }
+    pub fn iter(&self) -> impl Iterator<Item = (DependencyEvent, &PackageProcedure)> {
+        [
+            DependencyEvent::Installed,
+            DependencyEvent::Started,
+            DependencyEvent::Stopped,
+            DependencyEvent::Uninstalled,
+        ]
+        .into_iter()
+        .filter_map(|event| Some((event, self.get(event)?)))
+    }
+}
+
 pub struct DependencyConfigReceipts {
     config: ConfigReceipts,
     dependencies: LockReceipt<Dependencies, ()>,
@@ -667,7 +828,13 @@ pub async fn configure_impl(
         old_config: _,
         new_config,
         spec: _,
-    } = configure_logic(ctx.clone(), &mut db, (pkg_id, dep_id.clone()), &receipts).await?;
+    } = configure_logic(
+        ctx.clone(),
+        &mut db,
+        (pkg_id.clone(), dep_id.clone()),
+        &receipts,
+    )
+    .await?;
 
     let locks = &receipts.config;
     Ok(crate::config::configure(
@@ -675,6 +842,7 @@ pub async fn configure_impl(
         &mut db,
         &dep_id,
         Some(new_config),
+        ConfigSource::AutoConfigure { dependent: pkg_id },
         &Some(Duration::from_secs(3).into()),
         false,
         &mut BTreeMap::new(),
@@ -788,18 +956,24 @@ pub async fn configure_logic(
 pub async fn add_dependent_to_current_dependents_lists<'a, Db: DbHandle>(
     db: &mut Db,
     dependent_id: &PackageId,
+    dependencies: &Dependencies,
     current_dependencies: &CurrentDependencies,
     current_dependent_receipt: &LockReceipt<CurrentDependents, String>,
 ) -> Result<(), Error> {
     for (dependency, dep_info) in &current_dependencies.0 {
+        let range = dependencies.version_range(dependency);
+        let dependency = match resolve_provider(db, dependency, &range).await? {
+            Some((provider, _)) => provider,
+            None => continue,
+        };
         if let Some(mut dependency_dependents) =
-            current_dependent_receipt.get(db, dependency).await?
+            current_dependent_receipt.get(db, &dependency).await?
         {
             dependency_dependents
                 .0
                 .insert(dependent_id.clone(), dep_info.clone());
             current_dependent_receipt
-                .set(db, dependency_dependents, dependency)
+                .set(db, dependency_dependents, &dependency)
                 .await?;
         }
     }
@@ -873,11 +1047,31 @@ pub async fn break_all_dependents_transitive<'a, Db: DbHandle>(
         .flat_map(|x| x.0.keys())
         .filter(|dependent| id != *dependent)
     {
-        break_transitive(db, dependent, id, error.clone(), breakages, receipts).await?;
+        let declared =
+            declared_by(db, &receipts.dependency_receipt.try_heal, dependent, id).await?;
+        break_transitive(db, dependent, &declared, error.clone(), breakages, receipts).await?;
     }
     Ok(())
 }
 
+/// [`declared_dependency`] for installed packages, falling back to `provider` itself
+async fn declared_by<Db: DbHandle>(
+    db: &mut Db,
+    receipts: &TryHealReceipts,
+    dependent: &PackageId,
+    provider: &PackageId,
+) -> Result<PackageId, Error> {
+    Ok(
+        match (
+            receipts.manifest.get(db, dependent).await?,
+            receipts.manifest.get(db, provider).await?,
+        ) {
+            (Some(dependent), Some(provider)) => declared_dependency(&dependent, &provider).clone(),
+            _ => provider.clone(),
+        },
+    )
+}
+
 #[derive(Clone)]
 pub struct BreakTransitiveReceipts {
     pub dependency_receipt: DependencyReceipt,
@@ -999,7 +1193,8 @@ pub async fn heal_all_dependents_transitive<'a, Db: DbHandle>(
         .await?
         .ok_or_else(not_found)?;
     for dependent in dependents.0.keys().filter(|dependent| id != *dependent) {
-        heal_transitive(ctx, db, dependent, id, locks).await?;
+        let declared = declared_by(db, &locks.try_heal, dependent, id).await?;
+        heal_transitive(ctx, db, dependent, &declared, locks).await?;
     }
     Ok(())
 }
@@ -1048,11 +1243,13 @@ pub async fn reconfigure_dependents_with_live_pointers(
 ) -> Result<(), Error> {
     let dependents = &pde.current_dependents;
     let me = &pde.manifest.id;
+    // pointers may also target a virtual package this provides
+    let is_me = |id: &PackageId| id == me || pde.manifest.provides.contains_key(id);
     for (dependent_id, dependency_info) in &dependents.0 {
         if dependency_info.pointers.iter().any(|ptr| match ptr {
             // dependency id matches the package being uninstalled
-            PackagePointerSpec::TorAddress(ptr) => &ptr.package_id == me && dependent_id != me,
-            PackagePointerSpec::LanAddress(ptr) => &ptr.package_id == me && dependent_id != me,
+            PackagePointerSpec::TorAddress(ptr) => is_me(&ptr.package_id) && dependent_id != me,
+            PackagePointerSpec::LanAddress(ptr) => is_me(&ptr.package_id) && dependent_id != me,
             // we never need to retarget these
             PackagePointerSpec::TorKey(_) => false,
             PackagePointerSpec::Config(_) => false,
@@ -1062,6 +1259,7 @@ pub async fn reconfigure_dependents_with_live_pointers(
                 &mut tx,
                 dependent_id,
                 None,
+                ConfigSource::Dependency { id: me.clone() },
                 &None,
                 false,
                 &mut BTreeMap::new(),
@@ -1074,6 +1272,125 @@ pub async fn reconfigure_dependents_with_live_pointers(
     Ok(())
 }
 
+/// Runs the hooks installed dependents declare for `event` on `dependency` in the background.
+pub fn spawn_dependency_hooks(ctx: &RpcContext, dependency: &PackageId, event: DependencyEvent) {
+    let ctx = ctx.clone();
+    let dependency = dependency.clone();
+    tokio::spawn(async move {
+        if let Err(e) = run_dependency_hooks(&ctx, &dependency, event).await {
+            tracing::error!("Failed to run {} hooks for {}: {}", event, dependency, e);
+            tracing::debug!("{:?}", e);
+        }
+    });
+}
+
+/// Runs the hooks installed dependents declare for `event` on `dependency`,
+/// then reconfigures each configured dependent so its config pointers are refreshed.
+#[instrument(skip(ctx))]
+pub async fn run_dependency_hooks(
+    ctx: &RpcContext,
+    dependency: &PackageId,
+    event: DependencyEvent,
+) -> Result<(), Error> {
+    let mut db = ctx.db.handle();
+    let package_data = crate::db::DatabaseModel::new()
+        .package_data()
+        .get(&mut db)
+        .await?
+        .into_owned();
+    for pde in package_data.0.into_values() {
+        let installed = match pde {
+            PackageDataEntry::Installed { installed, .. } => installed,
+            _ => continue,
+        };
+        let manifest = &installed.manifest;
+        if &manifest.id == dependency {
+            continue;
+        }
+        let (hook, timeout) = match manifest
+            .dependencies
+            .0
+            .get(dependency)
+            .and_then(|info| Some((info.hooks.get(event)?, info.hooks.timeout)))
+        {
+            Some(hook) => hook,
+            None => continue,
+        };
+        tracing::info!(
+            "Running {} hook of {} for {}",
+            event,
+            manifest.id,
+            dependency
+        );
+        let res = match hook
+            .execute::<(), NoOutput>(
+                ctx,
+                &manifest.id,
+                &manifest.version,
+                ProcedureName::DependencyHook(dependency.clone(), event),
+                &manifest.volumes,
+                None,
+                timeout.map(|d| *d),
+            )
+            .await
+        {
+            Ok(Ok(NoOutput)) => Ok(()),
+            Ok(Err((code, error))) => Err(format!("{} (exit code {})", error, code)),
+            Err(e) => Err(e.source.to_string()),
+        };
+        let res = match res {
+            Ok(()) if installed.status.configured && manifest.config.is_some() => {
+                let mut tx = db.begin().await?;
+                let receipts = ConfigReceipts::new(&mut tx).await?;
+                match crate::config::configure(
+                    ctx,
+                    &mut tx,
+                    &manifest.id,
+                    None,
+                    ConfigSource::Dependency {
+                        id: dependency.clone(),
+                    },
+                    &None,
+                    false,
+                    &mut BTreeMap::new(),
+                    &mut BTreeMap::new(),
+                    &receipts,
+                )
+                .await
+                {
+                    Ok(()) => {
+                        tx.commit().await?;
+                        Ok(())
+                    }
+                    Err(e) => Err(format!("Could not refresh config: {}", e.source)),
+                }
+            }
+            res => res,
+        };
+        if let Err(e) = &res {
+            tracing::error!(
+                "{} hook of {} for {} failed: {}",
+                event,
+                manifest.id,
+                dependency,
+                e
+            );
+            tracing::debug!("{:?}", e);
+        }
+        PackageEvent {
+            time: Utc::now(),
+            kind: PackageEventKind::DependencyHook {
+                dependency: dependency.clone(),
+                event,
+                error: res.err(),
+            },
+        }
+        .record(&mut db, &manifest.id)
+        .await?;
+    }
+    Ok(())
+}
+
 #[derive(Clone)]
 pub struct DependencyReceipt {
     pub try_heal: TryHealReceipts,
@@ -1123,3 +1440,489 @@ impl DependencyReceipt {
         }
     }
 }
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct DependencyGraphNode {
+    /// None if the package is depended on but not installed
+    pub version: Option<Version>,
+    pub status: Option<MainStatus>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct DependencyGraphEdge {
+    pub dependent: PackageId,
+    /// The installed package the dependency resolves to, if any, otherwise the id as declared
+    pub dependency: PackageId,
+    /// The virtual package the dependent declares, when `dependency` is the package providing it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub declared: Option<PackageId>,
+    pub requirement: DependencyRequirement,
+    pub version: VersionRange,
+    /// Whether the dependent currently depends on it, which optional dependencies only do once opted into
+    pub active: bool,
+    /// None if the dependency is satisfied
+    pub error: Option<DependencyError>,
+}
+
+/// How a dependency error of a package comes about, down to the packages where it originates
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct DependencyErrorTrace {
+    pub dependent: PackageId,
+    pub dependency: PackageId,
+    pub error: DependencyError,
+    /// For transitive errors, the errors of the dependency that cause it
+    pub caused_by: Vec<DependencyErrorTrace>,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct DependencyGraph {
+    pub nodes: BTreeMap<PackageId, DependencyGraphNode>,
+    pub edges: Vec<DependencyGraphEdge>,
+    #[serde(default)]
+    pub explanation: Option<Vec<DependencyErrorTrace>>,
+}
+impl DependencyGraph {
+    fn from_installed<'a>(
+        installed: impl IntoIterator<Item = &'a InstalledPackageDataEntry>,
+    ) -> Self {
+        let installed = installed.into_iter().collect::<Vec<_>>();
+        let mut graph = DependencyGraph::default();
+        for pkg in &installed {
+            let id = &pkg.manifest.id;
+            graph.nodes.insert(
+                id.clone(),
+                DependencyGraphNode {
+                    version: Some(pkg.manifest.version.clone()),
+                    status: Some(pkg.status.main.clone()),
+                },
+            );
+            for (dep_id, info) in &pkg.manifest.dependencies.0 {
+                let dependency = select_provider(
+                    dep_id,
+                    &info.version,
+                    installed.iter().map(|pkg| &pkg.manifest),
+                )
+                .map_or_else(|| dep_id.clone(), |(provider, _)| provider);
+                graph.edges.push(DependencyGraphEdge {
+                    dependent: id.clone(),
+                    declared: (&dependency != dep_id).then(|| dep_id.clone()),
+                    dependency,
+                    requirement: info.requirement.clone(),
+                    version: info.version.clone(),
+                    active: pkg.current_dependencies.0.contains_key(dep_id),
+                    error: pkg.status.dependency_errors.0.get(dep_id).cloned(),
+                });
+            }
+        }
+        for edge in &graph.edges {
+            if !graph.nodes.contains_key(&edge.dependency) {
+                graph.nodes.insert(
+                    edge.dependency.clone(),
+                    DependencyGraphNode {
+                        version: None,
+                        status: None,
+                    },
+                );
+            }
+        }
+        graph
+    }
+
+    /// Keeps only `id` and the packages it transitively depends on or is depended on by
+    fn retain_connected(&mut self, id: &PackageId) {
+        let mut keep = BTreeSet::new();
+        for downward in [true, false] {
+            let mut queue = vec![id];
+            while let Some(next) = queue.pop() {
+                for edge in &self.edges {
+                    let (from, to) = if downward {
+                        (&edge.dependent, &edge.dependency)
+                    } else {
+                        (&edge.dependency, &edge.dependent)
+                    };
+                    if from == next && !keep.contains(to) {
+                        keep.insert(to.clone());
+                        queue.push(to);
+                    }
+                }
+            }
+        }
+        keep.insert(id.clone());
+        self.nodes.retain(|id, _| keep.contains(id));
+        self.edges
+            .retain(|edge| keep.contains(&edge.dependent) && keep.contains(&edge.dependency));
+    }
+
+    /// Traces the dependency errors of `id` back to where they originate
+    pub fn explain(&self, id: &PackageId) -> Vec<DependencyErrorTrace> {
+        self.trace(id, &mut BTreeSet::new())
+    }
+
+    fn trace(&self, id: &PackageId, seen: &mut BTreeSet<PackageId>) -> Vec<DependencyErrorTrace> {
+        if !seen.insert(id.clone()) {
+            return Vec::new();
+        }
+        let res = self
+            .edges
+            .iter()
+            .filter(|edge| &edge.dependent == id)
+            .filter_map(|edge| {
+                let error = edge.error.clone()?;
+                let caused_by = if let DependencyError::Transitive = error {
+                    self.trace(&edge.dependency, seen)
+                } else {
+                    Vec::new()
+                };
+                Some(DependencyErrorTrace {
+                    dependent: edge.dependent.clone(),
+                    dependency: edge.dependency.clone(),
+                    error,
+                    caused_by,
+                })
+            })
+            .collect();
+        seen.remove(id);
+        res
+    }
+
+    fn to_dot(&self) -> String {
+        let mut res = String::from("digraph dependencies {\n");
+        for (id, node) in &self.nodes {
+            let label = match &node.version {
+                Some(version) => format!("{}\\n{}", id, version.as_str()),
+                None => format!("{}\\nnot installed", id),
+            };
+            res += &format!("    \"{}\" [label=\"{}\"];\n", id, label);
+        }
+        for edge in &self.edges {
+            let mut label = edge.version.to_string();
+            if let Some(declared) = &edge.declared {
+                label = format!("{} {}", declared, label);
+            }
+            if let Some(error) = &edge.error {
+                label += &format!("\\n{}", error);
+            }
+            res += &format!(
+                "    \"{}\" -> \"{}\" [label=\"{}\", color={}, style={}];\n",
+                edge.dependent,
+                edge.dependency,
+                label.replace('"', "\\\""),
+                if edge.error.is_some() { "red" } else { "black" },
+                if edge.active { "solid" } else { "dashed" },
+            );
+        }
+        res += "}\n";
+        res
+    }
+
+    fn print_tree(&self, id: &PackageId, prefix: &str, path: &mut Vec<PackageId>) {
+        let edges = self
+            .edges
+            .iter()
+            .filter(|edge| &edge.dependent == id)
+            .collect::<Vec<_>>();
+        for (idx, edge) in edges.iter().enumerate() {
+            let last = idx == edges.len() - 1;
+            let status = match &edge.error {
+                Some(error) => format!("ERROR: {}", error),
+                None if edge.active => "OK".to_owned(),
+                None => "not in use".to_owned(),
+            };
+            let cycle = path.contains(&edge.dependency);
+            println!(
+                "{}{} {}{} {}{} [{}]{}",
+                prefix,
+                if last { "└──" } else { "├──" },
+                edge.dependency,
+                edge.declared
+                    .as_ref()
+                    .map_or(String::new(), |declared| format!(" (as {})", declared)),
+                edge.version,
+                if edge.requirement.required() {
+                    ""
+                } else {
+                    " (optional)"
+                },
+                status,
+                if cycle { " (cycle)" } else { "" },
+            );
+            if !cycle {
+                path.push(edge.dependency.clone());
+                self.print_tree(
+                    &edge.dependency,
+                    &format!("{}{}", prefix, if last { "    " } else { "│   " }),
+                    path,
+                );
+                path.pop();
+            }
+        }
+    }
+}
+
+fn print_traces(traces: &[DependencyErrorTrace], prefix: &str) {
+    for (idx, trace) in traces.iter().enumerate() {
+        let last = idx == traces.len() - 1;
+        println!(
+            "{}{} {} -> {}: {}{}",
+            prefix,
+            if last { "└──" } else { "├──" },
+            trace.dependent,
+            trace.dependency,
+            trace.error,
+            if trace.caused_by.is_empty() {
+                " (root cause)"
+            } else {
+                ""
+            },
+        );
+        print_traces(
+            &trace.caused_by,
+            &format!("{}{}", prefix, if last { "    " } else { "│   " }),
+        );
+    }
+}
+
+fn display_dependency_graph(graph: DependencyGraph, matches: &ArgMatches) {
+    if matches.is_present("format") {
+        return display_serializable(graph, matches);
+    }
+    if matches.is_present("dot") {
+        print!("{}", graph.to_dot());
+        return;
+    }
+    if let Some(traces) = &graph.explanation {
+        if traces.is_empty() {
+            println!("No dependency errors");
+        } else {
+            print_traces(traces, "");
+        }
+        return;
+    }
+    let roots = match matches.value_of("id") {
+        Some(id) => graph
+            .nodes
+            .keys()
+            .filter(|node| node.as_str() == id)
+            .collect::<Vec<_>>(),
+        None => graph
+            .nodes
+            .keys()
+            .filter(|node| !graph.edges.iter().any(|edge| &edge.dependency == *node))
+            .collect(),
+    };
+    for root in roots {
+        let node = &graph.nodes[root];
+        println!(
+            "{} {}",
+            root,
+            node.version
+                .as_ref()
+                .map_or("(not installed)", |version| version.as_str())
+        );
+        graph.print_tree(root, "", &mut vec![root.clone()]);
+    }
+}
+
+/// The dependencies between installed packages, and whether each one is satisfied
+#[command(display(display_dependency_graph))]
+#[instrument(skip(ctx))]
+pub async fn graph(
+    #[context] ctx: RpcContext,
+    #[arg] id: Option<PackageId>,
+    #[arg(long = "explain")] explain: bool,
+    #[allow(unused_variables)]
+    #[arg(long = "dot")]
+    dot: bool,
+    #[allow(unused_variables)]
+    #[arg(long = "format")]
+    format: Option<IoFormat>,
+) -> Result<DependencyGraph, Error> {
+    let mut db = ctx.db.handle();
+    let package_data = crate::db::DatabaseModel::new()
+        .package_data()
+        .get(&mut db)
+        .await?
+        .into_owned();
+    let mut graph =
+        DependencyGraph::from_installed(package_data.0.values().filter_map(|pde| pde.installed()));
+    if let Some(id) = &id {
+        if !graph.nodes.contains_key(id) {
+            return Err(Error::new(
+                eyre!("{} is not installed", id),
+                crate::ErrorKind::NotFound,
+            ));
+        }
+        graph.retain_connected(id);
+        if explain {
+            graph.explanation = Some(graph.explain(id));
+        }
+    } else if explain {
+        return Err(Error::new(
+            eyre!("--explain needs a package id"),
+            crate::ErrorKind::InvalidRequest,
+        ));
+    }
+    Ok(graph)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn edge(
+        dependent: &str,
+        dependency: &str,
+        error: Option<DependencyError>,
+    ) -> DependencyGraphEdge {
+        DependencyGraphEdge {
+            dependent: dependent.parse().unwrap(),
+            dependency: dependency.parse().unwrap(),
+            declared: None,
+            requirement: DependencyRequirement::Required,
+            version: "*".parse().unwrap(),
+            active: true,
+            error,
+        }
+    }
+
+    #[test]
+    fn explain_transitive() {
+        let graph = DependencyGraph {
+            nodes: BTreeMap::new(),
+            edges: vec![
+                edge("lnd", "bitcoind", Some(DependencyError::Transitive)),
+                edge("bitcoind", "tor", Some(DependencyError::NotRunning)),
+                edge("bitcoind", "electrs", None),
+                edge("rtl", "lnd", Some(DependencyError::Transitive)),
+            ],
+            explanation: None,
+        };
+        let traces = graph.explain(&"rtl".parse().unwrap());
+        assert_eq!(traces.len(), 1);
+        let lnd = &traces[0].caused_by;
+        assert_eq!(lnd.len(), 1);
+        assert_eq!(lnd[0].dependency.as_str(), "bitcoind");
+        let root = &lnd[0].caused_by;
+        assert_eq!(root.len(), 1);
+        assert_eq!(root[0].dependent.as_str(), "bitcoind");
+        assert_eq!(root[0].dependency.as_str(), "tor");
+        assert!(matches!(root[0].error, DependencyError::NotRunning));
+        assert!(root[0].caused_by.is_empty());
+    }
+
+    #[test]
+    fn explain_through_provider() {
+        use crate::db::model::test::installed_entry;
+        use crate::s9pk::manifest::test::manifest;
+
+        let installed = [
+            installed_entry(
+                manifest("bitcoind", "0.22.0", {
+                    let mut fields = depends_on("tor", "*");
+                    fields["provides"] = serde_json::json!({ "bitcoin-node": "0.22.0" });
+                    fields
+                }),
+                serde_json::json!({ "tor": { "type": "not-running" } }),
+            ),
+            installed_entry(
+                manifest("lnd", "0.15.0", depends_on("bitcoin-node", "*")),
+                serde_json::json!({ "bitcoin-node": { "type": "transitive" } }),
+            ),
+        ];
+        let graph = DependencyGraph::from_installed(&installed);
+        assert!(!graph.nodes.contains_key(&"bitcoin-node".parse().unwrap()));
+        let edge = graph
+            .edges
+            .iter()
+            .find(|edge| edge.dependent.as_str() == "lnd")
+            .unwrap();
+        assert_eq!(edge.dependency.as_str(), "bitcoind");
+        assert_eq!(edge.declared.as_ref().unwrap().as_str(), "bitcoin-node");
+
+        let traces = graph.explain(&"lnd".parse().unwrap());
+        assert_eq!(traces.len(), 1);
+        assert!(matches!(traces[0].error, DependencyError::Transitive));
+        let root = &traces[0].caused_by;
+        assert_eq!(root.len(), 1);
+        assert_eq!(root[0].dependency.as_str(), "tor");
+        assert!(matches!(root[0].error, DependencyError::NotRunning));
+    }
+
+    fn depends_on(id: &str, range: &str) -> serde_json::Value {
+        serde_json::json!({
+            "dependencies": {
+                id: { "version": range, "requirement": { "type": "required" }, "description": null }
+            }
+        })
+    }
+
+    #[test]
+    fn declared_through_provides() {
+        use crate::s9pk::manifest::test::manifest;
+
+        let bitcoind = manifest(
+            "bitcoind",
+            "0.21.0",
+            serde_json::json!({ "provides": { "bitcoin-node": "0.21.0" } }),
+        );
+        let lnd = manifest("lnd", "0.15.0", depends_on("bitcoind", "*"));
+        let electrs = manifest("electrs", "0.9.0", depends_on("bitcoin-node", ">=0.20.0"));
+        let nextcloud = manifest("nextcloud", "25.0.0", serde_json::json!({}));
+        assert_eq!(declared_dependency(&lnd, &bitcoind).as_str(), "bitcoind");
+        assert_eq!(
+            declared_dependency(&electrs, &bitcoind).as_str(),
+            "bitcoin-node"
+        );
+        assert_eq!(
+            declared_dependency(&nextcloud, &bitcoind).as_str(),
+            "bitcoind"
+        );
+    }
+
+    #[test]
+    fn select_provider_by_range() {
+        use crate::s9pk::manifest::test::manifest;
+
+        let knots = manifest(
+            "bitcoin-knots",
+            "0.20.0",
+            serde_json::json!({ "provides": { "bitcoin-node": "0.20.0" } }),
+        );
+        let bitcoind = manifest(
+            "bitcoind",
+            "0.22.0",
+            serde_json::json!({ "provides": { "bitcoin-node": "0.22.0" } }),
+        );
+        let select = |id: &str, range: &str| {
+            select_provider(
+                &id.parse().unwrap(),
+                &range.parse().unwrap(),
+                [&knots, &bitcoind],
+            )
+            .map(|(provider, version)| (provider.to_string(), version.to_string()))
+        };
+        let provided = |id: &str, version: &str| Some((id.to_owned(), version.to_owned()));
+        assert_eq!(
+            select("bitcoin-node", ">=0.21.0"),
+            provided("bitcoind", "0.22.0")
+        );
+        assert_eq!(
+            select("bitcoin-node", "*"),
+            provided("bitcoin-knots", "0.20.0")
+        );
+        // nothing satisfies the range, so the first provider is still used
+        assert_eq!(
+            select("bitcoin-node", ">=1.0.0"),
+            provided("bitcoin-knots", "0.20.0")
+        );
+        assert_eq!(
+            select("bitcoind", ">=1.0.0"),
+            provided("bitcoind", "0.22.0")
+        );
+        assert_eq!(select("tor", "*"), None);
+    }
+}
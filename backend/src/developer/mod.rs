@@ -1,15 +1,21 @@
+use std::collections::BTreeMap;
 use std::fs::File;
 use std::io::Write;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
+use clap::ArgMatches;
+use color_eyre::eyre::eyre;
 use ed25519::pkcs8::EncodePrivateKey;
 use ed25519_dalek::Keypair;
 use rpc_toolkit::command;
+use serde_json::Value;
 use tracing::instrument;
 
 use crate::context::SdkContext;
 use crate::util::display_none;
-use crate::{Error, ResultExt};
+use crate::util::serde::{display_serializable, IoFormat};
+use crate::volume::VolumeId;
+use crate::{Error, ErrorKind, ResultExt};
 
 #[command(cli_only, blocking, display(display_none))]
 #[instrument(skip(ctx))]
@@ -43,3 +49,147 @@ pub fn init(#[context] ctx: SdkContext) -> Result<(), Error> {
 pub fn verify() -> Result<(), Error> {
     Ok(())
 }
+
+fn parse_volume_mounts(arg: &str, _: &ArgMatches) -> Result<BTreeMap<VolumeId, PathBuf>, Error> {
+    arg.split(',')
+        .map(|mount| {
+            let (id, path) = mount.split_once('=').ok_or_else(|| {
+                Error::new(
+                    eyre!("Expected <volume-id>=<path>, got {}", mount),
+                    ErrorKind::InvalidRequest,
+                )
+            })?;
+            Ok((id.parse()?, PathBuf::from(path)))
+        })
+        .collect()
+}
+
+/// Runs a script procedure straight from a package's source directory, for testing without a server.
+/// Asset volumes come from the source tree; any other volume must be mounted with `--volumes`.
+#[command(cli_only, rename = "run-procedure", display(display_serializable))]
+#[instrument]
+#[cfg_attr(not(feature = "js_engine"), allow(unused_variables))]
+pub async fn run_procedure(
+    #[arg] path: PathBuf,
+    #[arg] name: String,
+    #[arg(long = "input")] input: Option<PathBuf>,
+    #[arg(long = "volumes", parse(parse_volume_mounts))] volumes: Option<
+        BTreeMap<VolumeId, PathBuf>,
+    >,
+    #[arg(rename = "allow-fetch", long = "allow-fetch")] allow_fetch: bool,
+    #[arg(rename = "container-socket", long = "container-socket")] container_socket: Option<
+        PathBuf,
+    >,
+    #[arg(long = "timeout")] timeout: Option<u64>,
+    // read by embassy-sdk when it sets up logging, to show what the script logs
+    #[allow(unused_variables)]
+    #[arg(short = 'v', long = "verbose")]
+    verbose: bool,
+    #[allow(unused_variables)]
+    #[arg(long = "format")]
+    format: Option<IoFormat>,
+) -> Result<Value, Error> {
+    #[cfg(feature = "js_engine")]
+    {
+        use std::sync::Arc;
+        use std::time::Duration;
+
+        use embassy_container_init::{ProcessGroupId, SignalGroup, SignalGroupParams};
+        use helpers::UnixRpcClient;
+        use js_engine::JsExecutionEnvironment;
+
+        use crate::procedure::js_scripts::{JsProcedure, LocalOsApi, LocalVolumes};
+        use crate::procedure::ProcedureName;
+        use crate::s9pk::manifest::Manifest;
+        use crate::volume::Volume;
+
+        let manifest: Manifest =
+            serde_json::from_value(crate::s9pk::read_manifest_value(&path).await?)
+                .with_kind(ErrorKind::Deserialization)?;
+        let name: ProcedureName = name.parse()?;
+        let input: Option<Value> = if let Some(input) = input {
+            let format = match input.extension().and_then(|ext| ext.to_str()) {
+                Some("yaml") | Some("yml") => IoFormat::Yaml,
+                Some("toml") => IoFormat::Toml,
+                _ => IoFormat::Json,
+            };
+            Some(
+                format.from_slice(
+                    &tokio::fs::read(&input)
+                        .await
+                        .with_ctx(|_| (ErrorKind::Filesystem, input.display().to_string()))?,
+                )?,
+            )
+        } else {
+            None
+        };
+
+        let mut local_volumes = LocalVolumes::new();
+        for (id, volume) in manifest.volumes.iter() {
+            if let Volume::Assets {} = volume {
+                local_volumes.mount(
+                    id.clone(),
+                    path.join(manifest.assets.assets_path()).join(id),
+                    true,
+                );
+            }
+        }
+        for (id, mount) in volumes.unwrap_or_default() {
+            let volume = manifest.volumes.get(&id).ok_or_else(|| {
+                Error::new(
+                    eyre!("Volume {} is not declared in the manifest", id),
+                    ErrorKind::NotFound,
+                )
+            })?;
+            local_volumes.mount(id, mount, volume.readonly());
+        }
+
+        let gid = ProcessGroupId(rand::random());
+        let rpc_client = container_socket.map(|socket| Arc::new(UnixRpcClient::new(socket)));
+        let mut environment = JsExecutionEnvironment::load_from_script(
+            path.join(manifest.assets.scripts_path()).join("embassy.js"),
+            &path,
+            &manifest.id,
+            &manifest.version,
+            Box::new(local_volumes),
+            gid,
+            rpc_client.clone(),
+        )
+        .await
+        .map_err(|(error, message)| {
+            Error::new(
+                eyre!("{}: {}", error.as_code_num(), message),
+                ErrorKind::Javascript,
+            )
+        })?
+        .with_os_api(Arc::new(LocalOsApi::default()));
+        if !allow_fetch {
+            environment = environment.deny_fetch();
+        }
+
+        let res = JsProcedure::default()
+            .run(environment, name, input, timeout.map(Duration::from_millis))
+            .await;
+        if let Some(rpc_client) = rpc_client {
+            if let Err(e) = rpc_client
+                .request(SignalGroup, SignalGroupParams { gid, signal: 9 })
+                .await
+            {
+                tracing::warn!("Could not clean up commands: {}", e.message);
+            }
+        }
+        res.map_err(|(code, message)| {
+            Error::new(
+                eyre!("Procedure failed with code {}: {}", code, message),
+                ErrorKind::Javascript,
+            )
+        })
+    }
+    #[cfg(not(feature = "js_engine"))]
+    {
+        Err(Error::new(
+            eyre!("embassy-sdk was built without script support"),
+            ErrorKind::Javascript,
+        ))
+    }
+}
@@ -0,0 +1,497 @@
+use std::future::Future;
+use std::marker::PhantomData;
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::process::Stdio;
+use std::time::Duration;
+
+use color_eyre::eyre::eyre;
+use futures::{FutureExt, SinkExt, StreamExt};
+use hyper::upgrade::Upgraded;
+use hyper::Error as HyperError;
+use nix::fcntl::{fcntl, FcntlArg, FdFlag};
+use nix::sys::signal::Signal;
+use nix::sys::termios::{cfmakeraw, tcgetattr, tcsetattr, SetArg, Termios};
+use nix::unistd::Pid;
+use rpc_toolkit::command;
+use rpc_toolkit::command_helpers::prelude::RequestParts;
+use rpc_toolkit::yajrc::RpcError;
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::process::{Child, Command};
+use tokio::sync::{mpsc, oneshot};
+use tokio::task::JoinError;
+use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
+use tokio_tungstenite::tungstenite::protocol::CloseFrame;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::WebSocketStream;
+use tracing::instrument;
+
+use crate::context::{CliContext, RpcContext};
+use crate::core::rpc_continuations::{RequestGuid, RpcContinuation};
+use crate::middleware::auth::HashSessionToken;
+use crate::procedure::docker::DockerProcedure;
+use crate::s9pk::manifest::PackageId;
+use crate::status::MainStatus;
+use crate::util::display_none;
+use crate::{Error, ErrorKind, ResultExt};
+
+/// Sent by the client as text frames. Binary frames are written to the terminal as-is.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(tag = "type")]
+#[serde(rename_all = "kebab-case")]
+pub enum ExecControl {
+    Resize { rows: u16, cols: u16 },
+    Signal { signal: i32 },
+}
+
+/// Sent by the server as text frames. Binary frames are terminal output.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(tag = "type")]
+#[serde(rename_all = "kebab-case")]
+pub enum ExecEvent {
+    Exit { code: Option<i32> },
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ExecResponse {
+    guid: RequestGuid,
+}
+
+struct ExecProcess {
+    child: Child,
+    master: std::fs::File,
+}
+impl ExecProcess {
+    #[instrument]
+    fn spawn(id: &PackageId, command: Option<&str>, term: &str) -> Result<Self, Error> {
+        let pty = nix::pty::openpty(None, None).with_kind(ErrorKind::Unknown)?;
+        let master = unsafe { std::fs::File::from_raw_fd(pty.master) };
+        let slave = unsafe { std::fs::File::from_raw_fd(pty.slave) };
+        fcntl(master.as_raw_fd(), FcntlArg::F_SETFD(FdFlag::FD_CLOEXEC))
+            .with_kind(ErrorKind::Unknown)?;
+
+        let mut cmd = Command::new("docker");
+        cmd.arg("exec")
+            .arg("--interactive")
+            .arg("--tty")
+            .arg("--env")
+            .arg(format!("TERM={}", term))
+            .arg(DockerProcedure::container_name(id, None))
+            .arg("sh");
+        if let Some(command) = command {
+            cmd.arg("-c").arg(command);
+        }
+        cmd.stdin(Stdio::from(slave.try_clone()?))
+            .stdout(Stdio::from(slave.try_clone()?))
+            .stderr(Stdio::from(slave))
+            .kill_on_drop(true);
+        unsafe {
+            // make the pty the controlling terminal of a new session, so docker puts the
+            // container side into raw mode and sees our window size
+            cmd.pre_exec(|| {
+                nix::unistd::setsid()?;
+                if libc::ioctl(0, libc::TIOCSCTTY, 0) < 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+        let child = cmd.spawn()?;
+        // `cmd` holds the last copies of the slave, which must be closed for reads on the
+        // master to see the hangup when the process exits
+        drop(cmd);
+
+        Ok(Self { child, master })
+    }
+
+    fn resize(&self, rows: u16, cols: u16) -> Result<(), Error> {
+        set_window_size(self.master.as_raw_fd(), rows, cols)
+    }
+
+    /// The container's terminal turns interrupt, quit and suspend into signals for its
+    /// foreground process group. Anything else goes to the `docker exec` client, which
+    /// hangs up the container's terminal when it exits.
+    async fn signal(&self, writer: &mut tokio::fs::File, signal: i32) -> Result<(), Error> {
+        let signal = Signal::try_from(signal).with_kind(ErrorKind::InvalidRequest)?;
+        let control: &[u8] = match signal {
+            Signal::SIGINT => b"\x03",
+            Signal::SIGQUIT => b"\x1c",
+            Signal::SIGTSTP => b"\x1a",
+            _ => {
+                if let Some(pid) = self.child.id() {
+                    nix::sys::signal::kill(Pid::from_raw(pid as i32), signal)
+                        .with_kind(ErrorKind::Unknown)?;
+                }
+                return Ok(());
+            }
+        };
+        writer.write_all(control).await?;
+        writer.flush().await?;
+        Ok(())
+    }
+
+    async fn finish(mut self) -> Result<Option<i32>, Error> {
+        if self.child.try_wait()?.is_none() {
+            self.child.kill().await?;
+        }
+        Ok(self.child.wait().await?.code())
+    }
+}
+
+fn set_window_size(fd: RawFd, rows: u16, cols: u16) -> Result<(), Error> {
+    let size = libc::winsize {
+        ws_row: rows,
+        ws_col: cols,
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    };
+    if unsafe { libc::ioctl(fd, libc::TIOCSWINSZ, &size) } < 0 {
+        return Err(Error::new(
+            std::io::Error::last_os_error(),
+            ErrorKind::Filesystem,
+        ));
+    }
+    Ok(())
+}
+
+fn get_window_size(fd: RawFd) -> Option<(u16, u16)> {
+    let mut size = libc::winsize {
+        ws_row: 0,
+        ws_col: 0,
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    };
+    if unsafe { libc::ioctl(fd, libc::TIOCGWINSZ, &mut size) } < 0 {
+        return None;
+    }
+    Some((size.ws_row, size.ws_col))
+}
+
+#[instrument(skip(ctx, session, exec, ws_fut))]
+async fn ws_handler<
+    WSFut: Future<Output = Result<Result<WebSocketStream<Upgraded>, HyperError>, JoinError>>,
+>(
+    ctx: RpcContext,
+    session: HashSessionToken,
+    audit_id: i32,
+    exec: ExecProcess,
+    ws_fut: WSFut,
+) -> Result<(), Error> {
+    let kill = crate::db::subscribe_to_session_kill(&ctx, session).await;
+    let mut stream = match ws_fut
+        .await
+        .with_kind(ErrorKind::Network)
+        .and_then(|a| a.with_kind(ErrorKind::Unknown))
+    {
+        Ok(a) => a,
+        Err(e) => {
+            let code = exec.finish().await?;
+            record_exit(&ctx, audit_id, code).await?;
+            return Err(e);
+        }
+    };
+
+    let res = bridge(&exec, kill, &mut stream).await;
+    let code = exec.finish().await?;
+    record_exit(&ctx, audit_id, code).await?;
+    if let Some(close) = res? {
+        stream
+            .send(Message::Text(
+                serde_json::to_string(&ExecEvent::Exit { code })
+                    .with_kind(ErrorKind::Serialization)?,
+            ))
+            .await
+            .with_kind(ErrorKind::Network)?;
+        stream
+            .close(Some(close))
+            .await
+            .with_kind(ErrorKind::Network)?;
+    }
+
+    Ok(())
+}
+
+/// Copies between the websocket and the terminal until either side goes away. Returns
+/// how to close the websocket, if it is still open.
+async fn bridge(
+    exec: &ExecProcess,
+    mut kill: oneshot::Receiver<()>,
+    stream: &mut WebSocketStream<Upgraded>,
+) -> Result<Option<CloseFrame<'static>>, Error> {
+    let mut writer = tokio::fs::File::from_std(exec.master.try_clone()?);
+    let mut reader = tokio::fs::File::from_std(exec.master.try_clone()?);
+    let (send, mut output) = mpsc::channel(16);
+    tokio::spawn(async move {
+        let mut buf = vec![0; crate::BUFFER_SIZE * 4];
+        // reads fail with EIO once the process has hung up the terminal
+        while let Ok(n) = reader.read(&mut buf).await {
+            if n == 0 || send.send(buf[..n].to_vec()).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    loop {
+        tokio::select! {
+            _ = &mut kill => {
+                tracing::info!("Closing Exec Session: Reason: Session Terminated");
+                return Ok(Some(CloseFrame {
+                    code: CloseCode::Error,
+                    reason: "UNAUTHORIZED".into(),
+                }));
+            }
+            data = output.recv() => match data {
+                Some(data) => stream
+                    .send(Message::Binary(data))
+                    .await
+                    .with_kind(ErrorKind::Network)?,
+                None => {
+                    return Ok(Some(CloseFrame {
+                        code: CloseCode::Normal,
+                        reason: "Exec Session Finished".into(),
+                    }))
+                }
+            },
+            message = stream.next() => match message.transpose().with_kind(ErrorKind::Network)? {
+                Some(Message::Binary(data)) => {
+                    writer.write_all(&data).await?;
+                    writer.flush().await?;
+                }
+                Some(Message::Text(control)) => {
+                    match serde_json::from_str(&control).with_kind(ErrorKind::Deserialization)? {
+                        ExecControl::Resize { rows, cols } => exec.resize(rows, cols)?,
+                        ExecControl::Signal { signal } => exec.signal(&mut writer, signal).await?,
+                    }
+                }
+                Some(Message::Close(_)) | None => return Ok(None),
+                _ => (),
+            },
+        }
+    }
+}
+
+/// A session whose websocket has not been opened yet. If its continuation times out or is
+/// removed first, dropping it ends the process and closes the audit row.
+struct PendingExec {
+    ctx: RpcContext,
+    audit_id: i32,
+    exec: Option<ExecProcess>,
+}
+impl PendingExec {
+    fn open(mut self) -> (RpcContext, i32, ExecProcess) {
+        let exec = self.exec.take().unwrap();
+        (self.ctx.clone(), self.audit_id, exec)
+    }
+}
+impl Drop for PendingExec {
+    fn drop(&mut self) {
+        if let Some(exec) = self.exec.take() {
+            let ctx = self.ctx.clone();
+            let audit_id = self.audit_id;
+            tokio::spawn(async move {
+                tracing::info!("Exec session {} expired before it was opened", audit_id);
+                if let Err(e) =
+                    async { record_exit(&ctx, audit_id, exec.finish().await?).await }.await
+                {
+                    tracing::error!("Failed to close exec session {}: {}", audit_id, e);
+                    tracing::debug!("{:?}", e);
+                }
+            });
+        }
+    }
+}
+
+async fn record_exit(ctx: &RpcContext, audit_id: i32, code: Option<i32>) -> Result<(), Error> {
+    tracing::info!("Exec session {} exited with {:?}", audit_id, code);
+    sqlx::query!(
+        "UPDATE exec_session SET ended_at = CURRENT_TIMESTAMP, exit_code = $1 WHERE id = $2",
+        code,
+        audit_id,
+    )
+    .execute(&ctx.secret_store)
+    .await?;
+    Ok(())
+}
+
+#[command(
+    custom_cli(cli_exec(async, context(CliContext))),
+    display(display_none)
+)]
+#[instrument(skip(ctx, req))]
+pub async fn exec(
+    #[context] ctx: RpcContext,
+    #[request] req: &RequestParts,
+    #[arg] id: PackageId,
+    #[arg(long = "command", help = "Run this instead of an interactive shell")] command: Option<
+        String,
+    >,
+    #[arg(long = "term", help = "Value of TERM inside the container")] term: Option<String>,
+) -> Result<ExecResponse, Error> {
+    let session = HashSessionToken::from_request_parts(req)?;
+    let status = crate::db::DatabaseModel::new()
+        .package_data()
+        .idx_model(&id)
+        .and_then(|pde| pde.installed())
+        .map(|i| i.status().main())
+        .get(&mut ctx.db.handle())
+        .await?;
+    if !matches!(&*status, Some(MainStatus::Running { .. })) {
+        return Err(Error::new(
+            eyre!("{} is not running", id),
+            crate::ErrorKind::InvalidRequest,
+        ));
+    }
+
+    let exec = ExecProcess::spawn(
+        &id,
+        command.as_deref(),
+        term.as_deref().unwrap_or("xterm-256color"),
+    )?;
+    let audit_command = command.unwrap_or_else(|| "sh".to_owned());
+    let audit_id = sqlx::query!(
+        "INSERT INTO exec_session (package, session, command) VALUES ($1, $2, $3) RETURNING id",
+        id.as_str(),
+        session.hashed(),
+        audit_command,
+    )
+    .fetch_one(&ctx.secret_store)
+    .await?
+    .id;
+    tracing::info!("Exec session {} opened into {}", audit_id, id);
+
+    let guid = RequestGuid::new();
+    let pending = PendingExec {
+        ctx: ctx.clone(),
+        audit_id,
+        exec: Some(exec),
+    };
+    ctx.add_continuation(
+        guid.clone(),
+        RpcContinuation::ws(
+            Box::new(move |ws_fut| {
+                let (ctx, audit_id, exec) = pending.open();
+                ws_handler(ctx, session, audit_id, exec, ws_fut).boxed()
+            }),
+            Duration::from_secs(30),
+        ),
+    )
+    .await;
+    Ok(ExecResponse { guid })
+}
+
+/// Puts the local terminal into raw mode for as long as it lives
+struct RawTerminal(Option<Termios>);
+impl RawTerminal {
+    fn new() -> Self {
+        let fd = std::io::stdin().as_raw_fd();
+        let original = tcgetattr(fd).ok();
+        if let Some(original) = &original {
+            let mut raw = original.clone();
+            cfmakeraw(&mut raw);
+            if tcsetattr(fd, SetArg::TCSANOW, &raw).is_err() {
+                return Self(None);
+            }
+        }
+        Self(original)
+    }
+}
+impl Drop for RawTerminal {
+    fn drop(&mut self) {
+        if let Some(original) = &self.0 {
+            tcsetattr(std::io::stdin().as_raw_fd(), SetArg::TCSANOW, original).ok();
+        }
+    }
+}
+
+async fn cli_exec(
+    ctx: CliContext,
+    id: PackageId,
+    command: Option<String>,
+    term: Option<String>,
+) -> Result<(), RpcError> {
+    let res = rpc_toolkit::command_helpers::call_remote(
+        ctx.clone(),
+        "package.exec",
+        serde_json::json!({
+            "id": id,
+            "command": command,
+            "term": term.or_else(|| std::env::var("TERM").ok()),
+        }),
+        PhantomData::<ExecResponse>,
+    )
+    .await?
+    .result?;
+
+    let mut base_url = ctx.base_url.clone();
+    let ws_scheme = match base_url.scheme() {
+        "https" => "wss",
+        "http" => "ws",
+        _ => {
+            return Err(Error::new(
+                eyre!("Cannot parse scheme from base URL"),
+                crate::ErrorKind::ParseUrl,
+            )
+            .into())
+        }
+    };
+    base_url.set_scheme(ws_scheme).or_else(|_| {
+        Err(Error::new(
+            eyre!("Cannot set URL scheme"),
+            crate::ErrorKind::ParseUrl,
+        ))
+    })?;
+    let (mut stream, _) =
+        // base_url is "http://127.0.0.1/", with a trailing slash, so we don't put a leading slash in this path:
+        tokio_tungstenite::connect_async(format!("{}ws/rpc/{}", base_url, res.guid)).await?;
+
+    let resize = || {
+        get_window_size(std::io::stdout().as_raw_fd()).map(|(rows, cols)| {
+            Message::Text(serde_json::to_string(&ExecControl::Resize { rows, cols }).unwrap())
+        })
+    };
+    if let Some(resize) = resize() {
+        stream.send(resize).await?;
+    }
+    let mut window_change =
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::window_change())?;
+    let mut stdin = tokio::io::stdin();
+    let mut stdout = tokio::io::stdout();
+    let mut buf = vec![0; crate::BUFFER_SIZE];
+    let mut code = None;
+    let _raw = RawTerminal::new();
+    loop {
+        tokio::select! {
+            _ = window_change.recv() => {
+                if let Some(resize) = resize() {
+                    stream.send(resize).await?;
+                }
+            }
+            n = stdin.read(&mut buf) => match n? {
+                0 => break,
+                n => stream.send(Message::Binary(buf[..n].to_vec())).await?,
+            },
+            message = stream.next() => match message.transpose()? {
+                Some(Message::Binary(data)) => {
+                    stdout.write_all(&data).await?;
+                    stdout.flush().await?;
+                }
+                Some(Message::Text(event)) => match serde_json::from_str(&event)? {
+                    ExecEvent::Exit { code: c } => code = c,
+                },
+                Some(Message::Close(_)) | None => break,
+                _ => (),
+            },
+        }
+    }
+    drop(_raw);
+
+    match code {
+        Some(0) | None => Ok(()),
+        Some(code) => Err(Error::new(
+            eyre!("Process exited with code {}", code),
+            crate::ErrorKind::Unknown,
+        )
+        .into()),
+    }
+}
@@ -384,6 +384,7 @@ pub async fn init(cfg: &RpcContextConfig) -> Result<InitResult, Error> {
                 updated: false,
                 update_progress: None,
                 backup_progress: None,
+                shutdown_progress: None,
             },
         )
         .await?;
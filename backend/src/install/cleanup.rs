@@ -3,6 +3,7 @@ use std::path::PathBuf;
 use std::sync::Arc;
 
 use bollard::image::{ListImagesOptions, RemoveImageOptions};
+use models::DependencyEvent;
 use patch_db::{DbHandle, LockReceipt, LockTargetId, LockType, PatchDbHandle, Verifier};
 use sqlx::{Executor, Postgres};
 use tracing::instrument;
@@ -15,7 +16,8 @@ use crate::db::model::{
     PackageDataEntry,
 };
 use crate::dependencies::{
-    reconfigure_dependents_with_live_pointers, DependencyErrors, TryHealReceipts,
+    reconfigure_dependents_with_live_pointers, resolve_provider, spawn_dependency_hooks,
+    Dependencies, DependencyErrors, TryHealReceipts,
 };
 use crate::error::ErrorCollection;
 use crate::s9pk::manifest::{Manifest, PackageId};
@@ -72,27 +74,36 @@ pub async fn update_dependency_errors_of_dependents<'a, Db: DbHandle>(
 ) -> Result<(), Error> {
     for dep in deps.0.keys() {
         if let Some(man) = receipts.manifest.get(db, dep).await? {
-            if let Err(e) = if let Some(info) = man.dependencies.0.get(id) {
-                info.satisfied(ctx, db, id, None, dep, &receipts.try_heal)
-                    .await?
-            } else {
-                Ok(())
-            } {
-                let mut errs = receipts
-                    .dependency_errors
-                    .get(db, dep)
-                    .await?
-                    .ok_or_else(not_found)?;
-                errs.0.insert(id.clone(), e);
-                receipts.dependency_errors.set(db, errs, dep).await?
-            } else {
-                let mut errs = receipts
-                    .dependency_errors
-                    .get(db, dep)
-                    .await?
-                    .ok_or_else(not_found)?;
-                errs.0.remove(id);
-                receipts.dependency_errors.set(db, errs, dep).await?
+            let mut dependencies = vec![id.clone()];
+            for dependency in man.dependencies.0.keys() {
+                // a dependency on a virtual package may resolve through `id`
+                if dependency != id && receipts.manifest.get(db, dependency).await?.is_none() {
+                    dependencies.push(dependency.clone());
+                }
+            }
+            for dependency in &dependencies {
+                if let Err(e) = if let Some(info) = man.dependencies.0.get(dependency) {
+                    info.satisfied(ctx, db, dependency, None, dep, &receipts.try_heal)
+                        .await?
+                } else {
+                    Ok(())
+                } {
+                    let mut errs = receipts
+                        .dependency_errors
+                        .get(db, dep)
+                        .await?
+                        .ok_or_else(not_found)?;
+                    errs.0.insert(dependency.clone(), e);
+                    receipts.dependency_errors.set(db, errs, dep).await?
+                } else {
+                    let mut errs = receipts
+                        .dependency_errors
+                        .get(db, dep)
+                        .await?
+                        .ok_or_else(not_found)?;
+                    errs.0.remove(dependency);
+                    receipts.dependency_errors.set(db, errs, dep).await?
+                }
             }
         }
     }
@@ -276,14 +287,20 @@ pub async fn cleanup_failed<Db: DbHandle>(
 pub async fn remove_from_current_dependents_lists<'a, Db: DbHandle>(
     db: &mut Db,
     id: &'a PackageId,
+    dependencies: &'a Dependencies,
     current_dependencies: &'a CurrentDependencies,
     current_dependent_receipt: &LockReceipt<CurrentDependents, String>,
 ) -> Result<(), Error> {
     for dep in current_dependencies.0.keys().chain(std::iter::once(id)) {
-        if let Some(mut current_dependents) = current_dependent_receipt.get(db, dep).await? {
+        let range = dependencies.version_range(dep);
+        let dep = match resolve_provider(db, dep, &range).await? {
+            Some((provider, _)) => provider,
+            None => continue,
+        };
+        if let Some(mut current_dependents) = current_dependent_receipt.get(db, &dep).await? {
             if current_dependents.0.remove(id).is_some() {
                 current_dependent_receipt
-                    .set(db, current_dependents, dep)
+                    .set(db, current_dependents, &dep)
                     .await?;
             }
         }
@@ -380,6 +397,7 @@ where
     remove_from_current_dependents_lists(
         &mut tx,
         &entry.manifest.id,
+        &entry.manifest.dependencies,
         &entry.current_dependencies,
         &receipts.current_dependents,
     )
@@ -400,7 +418,9 @@ where
     tracing::debug!("Cleaning up {:?} at {:?}", volumes, dependents_paths);
     cleanup_folder(volumes, Arc::new(dependents_paths)).await;
     remove_tor_keys(secrets, &entry.manifest.id).await?;
+    remove_package_store(secrets, &entry.manifest.id).await?;
     tx.commit().await?;
+    spawn_dependency_hooks(ctx, &entry.manifest.id, DependencyEvent::Uninstalled);
     Ok(())
 }
 
@@ -416,6 +436,18 @@ where
     Ok(())
 }
 
+#[instrument(skip(secrets))]
+pub async fn remove_package_store<Ex>(secrets: &mut Ex, id: &PackageId) -> Result<(), Error>
+where
+    for<'a> &'a mut Ex: Executor<'a, Database = Postgres>,
+{
+    let id_str = id.as_str();
+    sqlx::query!("DELETE FROM package_kv WHERE package = $1", id_str)
+        .execute(secrets)
+        .await?;
+    Ok(())
+}
+
 /// Needed to remove, without removing the folders that are mounted in the other docker containers
 pub fn cleanup_folder(
     path: PathBuf,
@@ -1,4 +1,4 @@
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
 use std::io::SeekFrom;
 use std::marker::PhantomData;
 use std::path::{Path, PathBuf};
@@ -14,6 +14,8 @@ use futures::{FutureExt, StreamExt, TryStreamExt};
 use http::header::CONTENT_LENGTH;
 use http::{Request, Response, StatusCode};
 use hyper::Body;
+use itertools::Itertools;
+use models::DependencyEvent;
 use patch_db::{DbHandle, LockType};
 use reqwest::Url;
 use rpc_toolkit::command;
@@ -25,7 +27,8 @@ use tokio_stream::wrappers::ReadDirStream;
 use tracing::instrument;
 
 use self::cleanup::{cleanup_failed, remove_from_current_dependents_lists};
-use crate::config::ConfigReceipts;
+use crate::config::history::ConfigSource;
+use crate::config::{Config, ConfigReceipts};
 use crate::context::{CliContext, RpcContext};
 use crate::core::rpc_continuations::{RequestGuid, RpcContinuation};
 use crate::db::model::{
@@ -34,8 +37,8 @@ use crate::db::model::{
 };
 use crate::dependencies::{
     add_dependent_to_current_dependents_lists, break_all_dependents_transitive,
-    reconfigure_dependents_with_live_pointers, BreakTransitiveReceipts, BreakageRes,
-    DependencyError, DependencyErrors,
+    reconfigure_dependents_with_live_pointers, spawn_dependency_hooks, BreakTransitiveReceipts,
+    BreakageRes, DependencyError, DependencyErrors,
 };
 use crate::install::cleanup::{cleanup, update_dependency_errors_of_dependents};
 use crate::install::progress::{InstallProgress, InstallProgressTracker};
@@ -111,6 +114,52 @@ impl std::fmt::Display for MinMax {
     }
 }
 
+/// The packages among `others` that cannot be installed alongside `manifest`, because either
+/// declares a conflict with the other, or with a virtual package the other provides
+pub fn find_conflicts<'a>(
+    manifest: &Manifest,
+    others: impl IntoIterator<Item = &'a Manifest>,
+) -> BTreeSet<PackageId> {
+    let names = |m: &'a Manifest| std::iter::once(&m.id).chain(m.provides.keys());
+    others
+        .into_iter()
+        .filter(|other| other.id != manifest.id)
+        .filter(|other| {
+            names(*other).any(|id| manifest.conflicts.contains(id))
+                || std::iter::once(&manifest.id)
+                    .chain(manifest.provides.keys())
+                    .any(|id| other.conflicts.contains(id))
+        })
+        .map(|other| other.id.clone())
+        .collect()
+}
+
+/// Refuses to install `manifest` while a package it conflicts with is installed
+#[instrument(skip(db, manifest))]
+pub async fn check_conflicts<Db: DbHandle>(db: &mut Db, manifest: &Manifest) -> Result<(), Error> {
+    let package_data = crate::db::DatabaseModel::new()
+        .package_data()
+        .get(db)
+        .await?
+        .into_owned();
+    let conflicts = find_conflicts(
+        manifest,
+        package_data.0.values().map(|pde| pde.manifest_borrow()),
+    );
+    if conflicts.is_empty() {
+        Ok(())
+    } else {
+        Err(Error::new(
+            eyre!(
+                "{} cannot be installed alongside {}",
+                manifest.title,
+                conflicts.iter().join(", ")
+            ),
+            ErrorKind::PackageConflict,
+        ))
+    }
+}
+
 #[command(
     custom_cli(cli_install(async, context(CliContext))),
     display(display_none),
@@ -171,6 +220,7 @@ pub async fn install(
             ErrorKind::Registry,
         ));
     }
+    check_conflicts(&mut ctx.db.handle(), &man).await?;
 
     let public_dir_path = ctx
         .datadir
@@ -332,6 +382,7 @@ pub async fn sideload(
     #[arg] manifest: Manifest,
     #[arg] icon: Option<String>,
 ) -> Result<RequestGuid, Error> {
+    check_conflicts(&mut ctx.db.handle(), &manifest).await?;
     let new_ctx = ctx.clone();
     let guid = RequestGuid::new();
     if let Some(icon) = icon {
@@ -482,6 +533,49 @@ pub async fn sideload(
     Ok(guid)
 }
 
+/// Installs an s9pk over the installed version of the package, and waits for the install to finish
+#[instrument(skip(ctx, manifest, s9pk))]
+pub async fn install_over(
+    ctx: &RpcContext,
+    manifest: &Manifest,
+    marketplace_url: Option<Url>,
+    content_length: Option<u64>,
+    s9pk: impl AsyncRead + Unpin,
+) -> Result<(), Error> {
+    let progress = InstallProgress::new(content_length);
+    let mut db = ctx.db.handle();
+    let mut tx = db.begin().await?;
+    let mut pde = crate::db::DatabaseModel::new()
+        .package_data()
+        .idx_model(&manifest.id)
+        .get_mut(&mut tx)
+        .await?;
+    match pde.take() {
+        Some(PackageDataEntry::Installed {
+            installed,
+            static_files,
+            ..
+        }) => {
+            *pde = Some(PackageDataEntry::Updating {
+                install_progress: progress.clone(),
+                installed,
+                manifest: manifest.clone(),
+                static_files,
+            })
+        }
+        _ => {
+            return Err(Error::new(
+                eyre!("{} is not installed", manifest.id),
+                crate::ErrorKind::InvalidRequest,
+            ))
+        }
+    }
+    pde.save(&mut tx).await?;
+    tx.commit().await?;
+
+    download_install_s9pk(ctx, manifest, marketplace_url, progress, s9pk).await
+}
+
 #[instrument(skip(ctx))]
 async fn cli_install(
     ctx: CliContext,
@@ -1170,19 +1264,22 @@ pub async fn install_s9pk<R: AsyncRead + AsyncSeek + Unpin + Send + Sync>(
                 };
             }
 
-            // search required dependencies
-            if let Some(dep) = crate::db::DatabaseModel::new()
-                .package_data()
-                .idx_model(&package)
-                .expect(&mut tx)
-                .await?
-                .installed()
-                .and_then(|i| i.current_dependencies().idx_model(pkg_id))
-                .get(&mut tx)
-                .await?
-                .to_owned()
-            {
-                deps.insert(package, dep);
+            // search required dependencies, including those on virtual packages this provides
+            for provided in std::iter::once(pkg_id).chain(manifest.provides.keys()) {
+                if let Some(dep) = crate::db::DatabaseModel::new()
+                    .package_data()
+                    .idx_model(&package)
+                    .expect(&mut tx)
+                    .await?
+                    .installed()
+                    .and_then(|i| i.current_dependencies().idx_model(provided))
+                    .get(&mut tx)
+                    .await?
+                    .to_owned()
+                {
+                    deps.insert(package.clone(), dep);
+                    break;
+                }
             }
         }
         CurrentDependents(deps)
@@ -1218,6 +1315,32 @@ pub async fn install_s9pk<R: AsyncRead + AsyncSeek + Unpin + Send + Sync>(
         current_dependents: current_dependents.clone(),
         current_dependencies: current_dependencies.clone(),
         interface_addresses,
+        autostart: match &*pde {
+            PackageDataEntry::Updating { installed, .. } => installed.autostart,
+            _ => true,
+        },
+        restart_policy: match &*pde {
+            PackageDataEntry::Updating { installed, .. } => installed.restart_policy,
+            _ => Default::default(),
+        },
+        scheduled_tasks: match &*pde {
+            PackageDataEntry::Updating { installed, .. } => installed
+                .scheduled_tasks
+                .iter()
+                .filter(|(id, _)| manifest.scheduled_tasks.0.contains_key(*id))
+                .map(|(id, status)| (id.clone(), status.clone()))
+                .collect(),
+            _ => BTreeMap::new(),
+        },
+        usage: None,
+        events: match &*pde {
+            PackageDataEntry::Updating { installed, .. } => installed.events.clone(),
+            _ => Vec::new(),
+        },
+        config_history: match &*pde {
+            PackageDataEntry::Updating { installed, .. } => installed.config_history.clone(),
+            _ => Vec::new(),
+        },
     };
 
     let prev = std::mem::replace(
@@ -1251,6 +1374,7 @@ pub async fn install_s9pk<R: AsyncRead + AsyncSeek + Unpin + Send + Sync>(
     .await?;
     dep_errs.save(&mut tx).await?;
 
+    let is_update = matches!(prev, PackageDataEntry::Updating { .. });
     if let PackageDataEntry::Updating {
         installed: prev, ..
     } = prev
@@ -1285,17 +1409,58 @@ pub async fn install_s9pk<R: AsyncRead + AsyncSeek + Unpin + Send + Sync>(
             migration.or(prev_migration)
         };
 
-        let configured = if let Some(f) = viable_migration {
+        // read the stored config through the version that wrote it, before any migration touches
+        // the data, so the update plan preview sees the same input
+        let stored_config = match (&prev.manifest.config, &manifest.config) {
+            (Some(prev_actions), Some(actions))
+                if prev_is_configured && actions.spec_version > prev_actions.spec_version =>
+            {
+                prev_actions
+                    .get(ctx, pkg_id, &prev.manifest.version, &prev.manifest.volumes)
+                    .await
+                    .map(|res| res.config)
+            }
+            _ => Ok(None),
+        };
+
+        let mut configured = if let Some(f) = viable_migration {
             f.await?.configured && prev_is_configured
         } else {
             false
         };
+        let mut config = None;
+        if let Some(actions) = manifest.config.as_ref().filter(|_| configured) {
+            let prev_spec_version = prev.manifest.config.as_ref().map_or(0, |a| a.spec_version);
+            if actions.spec_version > prev_spec_version {
+                let migrated = match stored_config {
+                    Ok(Some(stored)) => {
+                        migrate_config(ctx, pkg_id, version, &manifest, prev_spec_version, stored)
+                            .await
+                    }
+                    res => res,
+                };
+                match migrated {
+                    Ok(migrated) => config = migrated,
+                    Err(e) => {
+                        tracing::warn!(
+                            "Failed to migrate config of {} to spec version {}: {}",
+                            pkg_id,
+                            actions.spec_version,
+                            e
+                        );
+                        tracing::debug!("{:?}", e);
+                        configured = false;
+                    }
+                }
+            }
+        }
         if configured && manifest.config.is_some() {
             crate::config::configure(
                 ctx,
                 &mut tx,
                 pkg_id,
-                None,
+                config,
+                ConfigSource::Update,
                 &None,
                 false,
                 &mut BTreeMap::new(),
@@ -1307,6 +1472,7 @@ pub async fn install_s9pk<R: AsyncRead + AsyncSeek + Unpin + Send + Sync>(
             remove_from_current_dependents_lists(
                 &mut tx,
                 pkg_id,
+                &prev.manifest.dependencies,
                 &prev.current_dependencies,
                 &receipts.config.current_dependents,
             )
@@ -1314,6 +1480,7 @@ pub async fn install_s9pk<R: AsyncRead + AsyncSeek + Unpin + Send + Sync>(
             add_dependent_to_current_dependents_lists(
                 &mut tx,
                 pkg_id,
+                &manifest.dependencies,
                 &current_dependencies,
                 &receipts.config.current_dependents,
             )
@@ -1365,6 +1532,7 @@ pub async fn install_s9pk<R: AsyncRead + AsyncSeek + Unpin + Send + Sync>(
         add_dependent_to_current_dependents_lists(
             &mut tx,
             pkg_id,
+            &manifest.dependencies,
             &current_dependencies,
             &receipts.config.current_dependents,
         )
@@ -1381,6 +1549,7 @@ pub async fn install_s9pk<R: AsyncRead + AsyncSeek + Unpin + Send + Sync>(
         add_dependent_to_current_dependents_lists(
             &mut tx,
             pkg_id,
+            &manifest.dependencies,
             &current_dependencies,
             &receipts.config.current_dependents,
         )
@@ -1403,11 +1572,46 @@ pub async fn install_s9pk<R: AsyncRead + AsyncSeek + Unpin + Send + Sync>(
     sql_tx.commit().await?;
     tx.commit().await?;
 
+    if !is_update {
+        spawn_dependency_hooks(ctx, pkg_id, DependencyEvent::Installed);
+    }
+
     tracing::info!("Install {}@{}: Complete", pkg_id, version);
 
     Ok(())
 }
 
+/// Brings `config`, stored by `pkg_id` under config spec version `from`, up to the one in `manifest`
+#[instrument(skip(ctx, manifest, config))]
+async fn migrate_config(
+    ctx: &RpcContext,
+    pkg_id: &PackageId,
+    version: &Version,
+    manifest: &Manifest,
+    from: u32,
+    config: Config,
+) -> Result<Option<Config>, Error> {
+    let actions = if let Some(actions) = &manifest.config {
+        actions
+    } else {
+        return Ok(None);
+    };
+    actions
+        .migrations
+        .migrate(
+            &manifest.containers,
+            ctx,
+            pkg_id,
+            version,
+            &manifest.volumes,
+            from,
+            actions.spec_version,
+            config,
+        )
+        .await
+        .map(Some)
+}
+
 #[instrument(skip(datadir))]
 pub fn load_images<'a, P: AsRef<Path> + 'a + Send + Sync>(
     datadir: P,
@@ -1503,3 +1707,45 @@ fn ssl_port_status(manifests: &Vec<Manifest>) -> BTreeMap<Port, (bool, PackageId
     }
     ret
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::s9pk::manifest::test::manifest;
+
+    #[test]
+    fn conflicts() {
+        let electrs = manifest(
+            "electrs",
+            "0.9.0",
+            serde_json::json!({ "conflicts": ["electrum-server"] }),
+        );
+        let fulcrum = manifest(
+            "fulcrum",
+            "1.8.0",
+            serde_json::json!({ "provides": { "electrum-server": "1.4.0" } }),
+        );
+        let bitcoind = manifest(
+            "bitcoind",
+            "0.22.0",
+            serde_json::json!({ "conflicts": ["bitcoin-knots"] }),
+        );
+        let knots = manifest("bitcoin-knots", "0.22.0", serde_json::json!({}));
+        let lnd = manifest("lnd", "0.15.0", serde_json::json!({}));
+        let installed = [&electrs, &fulcrum, &bitcoind, &knots, &lnd];
+        let conflicts = |manifest: &Manifest| {
+            find_conflicts(manifest, installed)
+                .into_iter()
+                .map(|id| id.to_string())
+                .collect::<Vec<_>>()
+        };
+        // declared by the package being installed
+        assert_eq!(conflicts(&bitcoind), vec!["bitcoin-knots"]);
+        // declared by the installed package
+        assert_eq!(conflicts(&knots), vec!["bitcoind"]);
+        // declared against a virtual package, from either side
+        assert_eq!(conflicts(&electrs), vec!["fulcrum"]);
+        assert_eq!(conflicts(&fulcrum), vec!["electrs"]);
+        assert!(conflicts(&lnd).is_empty());
+    }
+}
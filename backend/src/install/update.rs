@@ -1,24 +1,39 @@
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
 
+use clap::ArgMatches;
+use color_eyre::eyre::eyre;
+use emver::VersionRange;
+use itertools::Itertools;
 use patch_db::{DbHandle, LockReceipt, LockTargetId, LockType, Verifier};
+use reqwest::Url;
 use rpc_toolkit::command;
+use serde::{Deserialize, Serialize};
+use tokio::fs::File;
 use tracing::instrument;
 
+use super::{check_conflicts, find_conflicts, install_over, MinMax, PKG_ARCHIVE_DIR};
+use crate::config::migration::ConfigMigrationPreview;
 use crate::config::not_found;
 use crate::context::RpcContext;
-use crate::db::model::CurrentDependents;
+use crate::db::model::{CurrentDependents, InstalledPackageDataEntry};
 use crate::dependencies::{
-    break_transitive, BreakTransitiveReceipts, BreakageRes, DependencyError,
+    break_transitive, declared_dependency, select_provider, BreakTransitiveReceipts, BreakageRes,
+    DepInfo, DependencyError, TaggedDependencyError,
 };
-use crate::s9pk::manifest::PackageId;
-use crate::util::serde::display_serializable;
+use crate::notifications::NotificationLevel;
+use crate::s9pk::manifest::{Manifest, PackageId};
+use crate::util::io::response_to_reader;
+use crate::util::serde::{display_serializable, IoFormat};
 use crate::util::Version;
-use crate::Error;
+use crate::version::{Current, VersionT};
+use crate::{Error, ResultExt};
 
 pub struct UpdateReceipts {
     break_receipts: BreakTransitiveReceipts,
     current_dependents: LockReceipt<CurrentDependents, String>,
     dependency: LockReceipt<crate::dependencies::DepInfo, (String, String)>,
+    manifest: LockReceipt<Manifest, String>,
 }
 
 impl UpdateReceipts {
@@ -45,32 +60,52 @@ impl UpdateReceipts {
             .map(|x| x.manifest().dependencies().star())
             .make_locker(LockType::Write)
             .add_to_keys(locks);
+        let manifest = crate::db::DatabaseModel::new()
+            .package_data()
+            .star()
+            .installed()
+            .map(|x| x.manifest())
+            .make_locker(LockType::Read)
+            .add_to_keys(locks);
         move |skeleton_key| {
             Ok(Self {
                 break_receipts: break_receipts(skeleton_key)?,
                 current_dependents: current_dependents.verify(skeleton_key)?,
                 dependency: dependency.verify(skeleton_key)?,
+                manifest: manifest.verify(skeleton_key)?,
             })
         }
     }
 }
 
-#[command(subcommands(dry))]
+#[command(subcommands(dry, plan, execute))]
 pub async fn update() -> Result<(), Error> {
     Ok(())
 }
 
+/// Fails naming the installed packages the new version conflicts with, since install would refuse it
 #[instrument(skip(ctx))]
 #[command(display(display_serializable))]
 pub async fn dry(
     #[context] ctx: RpcContext,
     #[arg] id: PackageId,
     #[arg] version: Version,
+    #[arg(short = 'm', long = "marketplace-url", rename = "marketplace-url")]
+    marketplace_url: Option<Url>,
 ) -> Result<BreakageRes, Error> {
+    let marketplace_url =
+        marketplace_url.unwrap_or_else(|| crate::DEFAULT_MARKETPLACE.parse().unwrap());
+    let manifest = fetch_manifest(&marketplace_url, &id, &format!("={}", version).parse()?).await?;
     let mut db = ctx.db.handle();
     let mut tx = db.begin().await?;
+    check_conflicts(&mut tx, &manifest).await?;
     let mut breakages = BTreeMap::new();
     let receipts = UpdateReceipts::new(&mut tx).await?;
+    let current = receipts
+        .manifest
+        .get(&mut tx, &id)
+        .await?
+        .ok_or_else(not_found)?;
 
     for dependent in receipts
         .current_dependents
@@ -82,24 +117,774 @@ pub async fn dry(
         .into_iter()
         .filter(|dependent| &&id != dependent)
     {
-        if let Some(dep_info) = receipts.dependency.get(&mut tx, (&dependent, &id)).await? {
-            let version_req = dep_info.version;
-            if !version.satisfies(&version_req) {
-                break_transitive(
-                    &mut tx,
-                    &dependent,
-                    &id,
+        let dependent_manifest = match receipts.manifest.get(&mut tx, dependent).await? {
+            Some(a) => a,
+            None => continue,
+        };
+        // a dependent on a virtual package gets the version of it the new one provides, if any
+        let declared = declared_dependency(&dependent_manifest, &current);
+        let received = if declared == &id {
+            Some(&manifest.version)
+        } else {
+            manifest.provides.get(declared)
+        };
+        if let Some(dep_info) = receipts
+            .dependency
+            .get(&mut tx, (&dependent, declared))
+            .await?
+        {
+            let error = match received {
+                None => DependencyError::NotInstalled,
+                Some(received) if !received.satisfies(&dep_info.version) => {
                     DependencyError::IncorrectVersion {
-                        expected: version_req,
+                        expected: dep_info.version,
+                        received: received.clone(),
+                    }
+                }
+                Some(_) => continue,
+            };
+            break_transitive(
+                &mut tx,
+                &dependent,
+                declared,
+                error,
+                &mut breakages,
+                &receipts.break_receipts,
+            )
+            .await?;
+        }
+    }
+    tx.abort().await?;
+    Ok(BreakageRes(breakages))
+}
+
+/// Where the archives of the versions being replaced by an update plan are kept until it finishes
+pub const PKG_ROLLBACK_DIR: &str = "package-data/rollback";
+
+/// The versions of the steps of a reviewed plan, as id@version
+fn parse_versions(arg: &str, _: &ArgMatches) -> Result<BTreeMap<PackageId, Version>, Error> {
+    arg.split(',')
+        .map(|step| {
+            let (id, version) = step.trim().split_once('@').ok_or_else(|| {
+                Error::new(
+                    eyre!("{} must be of the form id@version", step.trim()),
+                    crate::ErrorKind::ParseVersion,
+                )
+            })?;
+            Ok((id.parse()?, version.parse()?))
+        })
+        .collect()
+}
+
+fn parse_targets(arg: &str, _: &ArgMatches) -> Result<BTreeMap<PackageId, VersionRange>, Error> {
+    arg.split(',')
+        .map(|target| {
+            let (id, spec) = target
+                .trim()
+                .split_once('@')
+                .unwrap_or((target.trim(), "*"));
+            Ok((id.parse()?, spec.parse()?))
+        })
+        .collect()
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct UpdateStep {
+    pub id: PackageId,
+    pub from: Version,
+    pub to: Version,
+    #[serde(skip)]
+    manifest: Option<Manifest>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Reconfiguration {
+    pub dependent: PackageId,
+    pub dependency: PackageId,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct UpdatePlan {
+    /// In the order they will be installed, dependencies first
+    pub steps: Vec<UpdateStep>,
+    /// The dependency errors each dependent would have afterwards
+    pub breakages: BTreeMap<PackageId, Vec<TaggedDependencyError>>,
+    /// Dependencies whose config rules have to be checked again, and auto configured if they fail
+    pub reconfigure: Vec<Reconfiguration>,
+    /// The installed packages each new version cannot be installed alongside
+    pub conflicts: BTreeMap<PackageId, BTreeSet<PackageId>>,
+    /// How the stored config of each package would be migrated to its new config spec version
+    pub config_migrations: BTreeMap<PackageId, ConfigMigrationPreview>,
+}
+
+async fn fetch_manifest(
+    marketplace_url: &Url,
+    id: &PackageId,
+    spec: &VersionRange,
+) -> Result<Manifest, Error> {
+    let man: Manifest = reqwest::get(format!(
+        "{}/package/v0/manifest/{}?spec={}&version-priority={}&eos-version-compat={}&arch={}",
+        marketplace_url,
+        id,
+        spec,
+        MinMax::Max,
+        Current::new().compat(),
+        &*crate::ARCH,
+    ))
+    .await
+    .with_kind(crate::ErrorKind::Registry)?
+    .error_for_status()
+    .with_kind(crate::ErrorKind::Registry)?
+    .json()
+    .await
+    .with_kind(crate::ErrorKind::Registry)?;
+    if &man.id != id || !man.version.satisfies(spec) {
+        return Err(Error::new(
+            eyre!("Fetched package does not match requested id and version"),
+            crate::ErrorKind::Registry,
+        ));
+    }
+    Ok(man)
+}
+
+/// Works out what updating `targets` to the given manifests does to the rest of the installed packages
+fn plan_update(
+    installed: &BTreeMap<PackageId, InstalledPackageDataEntry>,
+    targets: BTreeMap<PackageId, Manifest>,
+) -> UpdatePlan {
+    // the manifests of the installed packages once the plan is done
+    let manifests = installed
+        .iter()
+        .map(|(id, pkg)| targets.get(id).unwrap_or(&pkg.manifest))
+        .collect::<Vec<_>>();
+    let provider = |id: &PackageId, info: &DepInfo| {
+        select_provider(id, &info.version, manifests.iter().copied())
+    };
+    // what each package depends on once the plan is done: the id it declares,
+    // the package providing it with the version it provides, and the declaration
+    let mut depends_on: BTreeMap<
+        &PackageId,
+        Vec<(&PackageId, Option<(PackageId, Version)>, &DepInfo)>,
+    > = BTreeMap::new();
+    for (id, pkg) in installed {
+        let manifest = targets.get(id).unwrap_or(&pkg.manifest);
+        depends_on.insert(
+            id,
+            manifest
+                .dependencies
+                .0
+                .iter()
+                .filter(|(dep_id, info)| {
+                    info.requirement.required() || pkg.current_dependencies.0.contains_key(*dep_id)
+                })
+                .map(|(dep_id, info)| (dep_id, provider(dep_id, info), info))
+                .collect(),
+        );
+    }
+
+    // errors a dependent already has aren't caused by the plan, but a different version mismatch is.
+    // DependencyError has no PartialEq, so they are compared by their serialized form
+    let already_has = |dependent: &PackageId, dependency: &PackageId, error: &DependencyError| {
+        installed
+            .get(dependent)
+            .and_then(|pkg| pkg.status.dependency_errors.0.get(dependency))
+            .map_or(false, |current| {
+                serde_json::to_value(current).ok() == serde_json::to_value(error).ok()
+            })
+    };
+
+    let mut breakages: BTreeMap<PackageId, Vec<TaggedDependencyError>> = BTreeMap::new();
+    let mut reconfigure = Vec::new();
+    for (dependent, deps) in &depends_on {
+        for (dependency, provider, info) in deps {
+            let error = match provider {
+                None => Some(DependencyError::NotInstalled),
+                Some((_, version)) if !version.satisfies(&info.version) => {
+                    Some(DependencyError::IncorrectVersion {
+                        expected: info.version.clone(),
                         received: version.clone(),
-                    },
-                    &mut breakages,
-                    &receipts.break_receipts,
+                    })
+                }
+                Some(_) => None,
+            };
+            if let Some(error) = error {
+                if already_has(*dependent, *dependency, &error) {
+                    continue;
+                }
+                breakages
+                    .entry((*dependent).clone())
+                    .or_default()
+                    .push(TaggedDependencyError {
+                        dependency: (*dependency).clone(),
+                        error,
+                    });
+            } else if info.config.is_some()
+                && (targets.contains_key(*dependent)
+                    || provider
+                        .as_ref()
+                        .map_or(false, |(id, _)| targets.contains_key(id)))
+            {
+                reconfigure.push(Reconfiguration {
+                    dependent: (*dependent).clone(),
+                    dependency: (*dependency).clone(),
+                });
+            }
+        }
+    }
+    loop {
+        let transitive = depends_on
+            .iter()
+            .filter(|(dependent, _)| !breakages.contains_key(**dependent))
+            .filter_map(|(dependent, deps)| {
+                deps.iter()
+                    .find(|(dependency, provider, _)| {
+                        provider
+                            .as_ref()
+                            .map_or(false, |(id, _)| breakages.contains_key(id))
+                            && !already_has(*dependent, *dependency, &DependencyError::Transitive)
+                    })
+                    .map(|(dependency, _, _)| ((*dependent).clone(), (*dependency).clone()))
+            })
+            .collect::<Vec<_>>();
+        if transitive.is_empty() {
+            break;
+        }
+        for (dependent, dependency) in transitive {
+            breakages.insert(
+                dependent,
+                vec![TaggedDependencyError {
+                    dependency,
+                    error: DependencyError::Transitive,
+                }],
+            );
+        }
+    }
+
+    let conflicts = targets
+        .values()
+        .filter_map(|manifest| {
+            let conflicts = find_conflicts(manifest, manifests.iter().copied());
+            (!conflicts.is_empty()).then(|| (manifest.id.clone(), conflicts))
+        })
+        .collect();
+
+    let order = crate::manager::dependency_layers(
+        &targets
+            .iter()
+            .map(|(id, manifest)| {
+                (
+                    id.clone(),
+                    manifest
+                        .dependencies
+                        .0
+                        .iter()
+                        .filter_map(|(dep_id, info)| Some(provider(dep_id, info)?.0))
+                        .collect(),
+                )
+            })
+            .collect(),
+    );
+    let mut targets = targets;
+    let steps = order
+        .into_iter()
+        .flatten()
+        .filter_map(|id| {
+            let manifest = targets.remove(&id)?;
+            Some(UpdateStep {
+                from: installed.get(&id)?.manifest.version.clone(),
+                to: manifest.version.clone(),
+                id,
+                manifest: Some(manifest),
+            })
+        })
+        .collect();
+
+    UpdatePlan {
+        steps,
+        breakages,
+        reconfigure,
+        conflicts,
+        config_migrations: BTreeMap::new(),
+    }
+}
+
+async fn load_plan(
+    ctx: &RpcContext,
+    targets: BTreeMap<PackageId, VersionRange>,
+    marketplace_url: &Url,
+) -> Result<UpdatePlan, Error> {
+    let installed = crate::db::DatabaseModel::new()
+        .package_data()
+        .get(&mut ctx.db.handle())
+        .await?
+        .into_owned()
+        .0
+        .into_iter()
+        .filter_map(|(id, pde)| Some((id, pde.into_installed()?)))
+        .collect::<BTreeMap<_, _>>();
+    let mut manifests = BTreeMap::new();
+    let mut config_migrations = BTreeMap::new();
+    for (id, spec) in targets {
+        let current = installed.get(&id).ok_or_else(|| {
+            Error::new(eyre!("{} is not installed", id), crate::ErrorKind::NotFound)
+        })?;
+        let manifest = fetch_manifest(marketplace_url, &id, &spec).await?;
+        if manifest.version == current.manifest.version {
+            continue;
+        }
+        if let (Some(prev), Some(next)) = (&current.manifest.config, &manifest.config) {
+            if current.status.configured && next.spec_version > prev.spec_version {
+                let config = prev
+                    .get(
+                        ctx,
+                        &id,
+                        &current.manifest.version,
+                        &current.manifest.volumes,
+                    )
+                    .await?
+                    .config;
+                if let Some(config) = config {
+                    config_migrations.insert(
+                        id.clone(),
+                        next.migrations
+                            .preview(prev.spec_version, next.spec_version, &config)?,
+                    );
+                }
+            }
+        }
+        manifests.insert(id, manifest);
+    }
+    Ok(UpdatePlan {
+        config_migrations,
+        ..plan_update(&installed, manifests)
+    })
+}
+
+/// Computes an upgrade of several packages at once that keeps their dependents consistent
+#[command(display(display_serializable))]
+#[instrument(skip(ctx))]
+pub async fn plan(
+    #[context] ctx: RpcContext,
+    #[arg(parse(parse_targets))] targets: BTreeMap<PackageId, VersionRange>,
+    #[arg(short = 'm', long = "marketplace-url", rename = "marketplace-url")]
+    marketplace_url: Option<Url>,
+    #[allow(unused_variables)]
+    #[arg(long = "format")]
+    format: Option<IoFormat>,
+) -> Result<UpdatePlan, Error> {
+    let marketplace_url =
+        marketplace_url.unwrap_or_else(|| crate::DEFAULT_MARKETPLACE.parse().unwrap());
+    load_plan(&ctx, targets, &marketplace_url).await
+}
+
+fn rollback_archive(ctx: &RpcContext, id: &PackageId, version: &Version) -> PathBuf {
+    ctx.datadir
+        .join(PKG_ROLLBACK_DIR)
+        .join(id)
+        .join(format!("{}.s9pk", version))
+}
+
+async fn save_rollback_archive(
+    ctx: &RpcContext,
+    id: &PackageId,
+    version: &Version,
+) -> Result<(), Error> {
+    let archive = ctx
+        .datadir
+        .join(PKG_ARCHIVE_DIR)
+        .join(id)
+        .join(version.as_str())
+        .join(AsRef::<Path>::as_ref(id).with_extension("s9pk"));
+    let dst = rollback_archive(ctx, id, version);
+    if let Some(parent) = dst.parent() {
+        tokio::fs::create_dir_all(parent)
+            .await
+            .with_ctx(|_| (crate::ErrorKind::Filesystem, parent.display().to_string()))?;
+    }
+    tokio::fs::copy(&archive, &dst)
+        .await
+        .with_ctx(|_| (crate::ErrorKind::Filesystem, archive.display().to_string()))?;
+    Ok(())
+}
+
+async fn execute_plan(
+    ctx: &RpcContext,
+    plan: &UpdatePlan,
+    marketplace_url: &Url,
+    previous: &BTreeMap<PackageId, (Manifest, Option<Url>)>,
+) -> Result<(), Error> {
+    let mut done = Vec::new();
+    let res = async {
+        for step in &plan.steps {
+            let manifest = step.manifest.as_ref().ok_or_else(not_found)?;
+            tracing::info!("Update Plan: Installing {}@{}", step.id, step.to);
+            let s9pk = reqwest::get(format!(
+                "{}/package/v0/{}.s9pk?spec=={}&eos-version-compat={}&arch={}",
+                marketplace_url,
+                step.id,
+                step.to,
+                Current::new().compat(),
+                &*crate::ARCH,
+            ))
+            .await
+            .with_kind(crate::ErrorKind::Registry)?
+            .error_for_status()
+            .with_kind(crate::ErrorKind::Registry)?;
+            install_over(
+                ctx,
+                manifest,
+                Some(marketplace_url.clone()),
+                s9pk.content_length(),
+                response_to_reader(s9pk),
+            )
+            .await?;
+            done.push(step);
+        }
+        let mut db = ctx.db.handle();
+        for Reconfiguration {
+            dependent,
+            dependency,
+        } in &plan.reconfigure
+        {
+            let unsatisfied = crate::db::DatabaseModel::new()
+                .package_data()
+                .idx_model(dependent)
+                .and_then(|pde| pde.installed())
+                .map(|i| i.status().dependency_errors())
+                .get(&mut db)
+                .await?
+                .into_owned()
+                .and_then(|errors| errors.0.get(dependency).cloned());
+            if let Some(DependencyError::ConfigUnsatisfied { .. }) = unsatisfied {
+                tracing::info!("Update Plan: Configuring {} for {}", dependency, dependent);
+                crate::dependencies::configure_impl(
+                    ctx.clone(),
+                    (dependent.clone(), dependency.clone()),
                 )
                 .await?;
             }
         }
+        Ok::<_, Error>(())
+    }
+    .await;
+    if let Err(e) = &res {
+        tracing::error!("Update Plan failed, rolling back: {}", e);
+        tracing::debug!("{:?}", e);
+        for step in done.into_iter().rev() {
+            tracing::info!("Update Plan: Rolling back {} to {}", step.id, step.from);
+            let rolled_back = async {
+                let (manifest, marketplace_url) = previous.get(&step.id).ok_or_else(not_found)?;
+                let archive = File::open(rollback_archive(ctx, &step.id, &step.from)).await?;
+                install_over(ctx, manifest, marketplace_url.clone(), None, archive).await
+            }
+            .await;
+            if let Err(e) = rolled_back {
+                tracing::error!("Failed to roll back {} to {}: {}", step.id, step.from, e);
+                tracing::debug!("{:?}", e);
+            }
+        }
+    }
+    res
+}
+
+/// Carries out a reviewed update plan in the background, given the versions of its steps.
+/// If any step fails, the packages already updated are put back on their previous versions.
+#[command(display(display_serializable))]
+#[instrument(skip(ctx))]
+pub async fn execute(
+    #[context] ctx: RpcContext,
+    #[arg(parse(parse_versions))] versions: BTreeMap<PackageId, Version>,
+    #[arg(short = 'm', long = "marketplace-url", rename = "marketplace-url")]
+    marketplace_url: Option<Url>,
+    #[arg(long = "force")] force: bool,
+    #[allow(unused_variables)]
+    #[arg(long = "format")]
+    format: Option<IoFormat>,
+) -> Result<UpdatePlan, Error> {
+    let marketplace_url =
+        marketplace_url.unwrap_or_else(|| crate::DEFAULT_MARKETPLACE.parse().unwrap());
+    // pinned, so that what gets installed is what was reviewed, even if the marketplace has something newer by now
+    let targets = versions
+        .into_iter()
+        .map(|(id, version)| Ok((id, format!("={}", version).parse()?)))
+        .collect::<Result<_, Error>>()?;
+    let plan = load_plan(&ctx, targets, &marketplace_url).await?;
+    if !plan.conflicts.is_empty() {
+        return Err(Error::new(
+            eyre!(
+                "Update would install {} alongside conflicting packages",
+                plan.conflicts.keys().join(", ")
+            ),
+            crate::ErrorKind::PackageConflict,
+        ));
+    }
+    if !plan.breakages.is_empty() && !force {
+        return Err(Error::new(
+            eyre!(
+                "Update would break {}, pass --force to update anyway",
+                plan.breakages.keys().join(", ")
+            ),
+            crate::ErrorKind::InvalidRequest,
+        ));
+    }
+
+    let mut previous = BTreeMap::new();
+    let mut db = ctx.db.handle();
+    for step in &plan.steps {
+        let installed = crate::db::DatabaseModel::new()
+            .package_data()
+            .idx_model(&step.id)
+            .and_then(|pde| pde.installed())
+            .get(&mut db)
+            .await?
+            .into_owned()
+            .ok_or_else(not_found)?;
+        save_rollback_archive(&ctx, &step.id, &step.from).await?;
+        previous.insert(
+            step.id.clone(),
+            (installed.manifest, installed.marketplace_url),
+        );
+    }
+    drop(db);
+
+    let res = plan.clone();
+    tokio::spawn(async move {
+        let result = execute_plan(&ctx, &plan, &marketplace_url, &previous).await;
+        for step in &plan.steps {
+            let archive = rollback_archive(&ctx, &step.id, &step.from);
+            if let Err(e) = tokio::fs::remove_file(&archive).await {
+                tracing::warn!("Failed to remove {}: {}", archive.display(), e);
+            }
+        }
+        let (level, title, message) = match result {
+            Ok(()) => (
+                NotificationLevel::Success,
+                "Update Complete",
+                format!(
+                    "Updated {}",
+                    plan.steps
+                        .iter()
+                        .map(|step| format!("{} to {}", step.id, step.to))
+                        .join(", ")
+                ),
+            ),
+            Err(e) => (
+                NotificationLevel::Error,
+                "Update Failed",
+                format!("Update was rolled back: {}", e),
+            ),
+        };
+        if let Err(e) = ctx
+            .notification_manager
+            .notify(
+                &mut ctx.db.handle(),
+                None,
+                level,
+                title.to_owned(),
+                message,
+                (),
+                None,
+            )
+            .await
+        {
+            tracing::error!("Failed to issue Notification: {}", e);
+            tracing::debug!("{:?}", e);
+        }
+    });
+
+    Ok(res)
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use super::*;
+    use crate::db::model::test::installed_entry;
+    use crate::s9pk::manifest::test::manifest;
+
+    fn depends_on(deps: &[(&str, &str, bool)]) -> serde_json::Value {
+        let procedure = json!({ "type": "docker", "image": "main", "entrypoint": "config" });
+        json!({
+            "dependencies": deps
+                .iter()
+                .map(|(id, range, config)| {
+                    let mut info = json!({
+                        "version": range,
+                        "requirement": { "type": "required" },
+                        "description": null,
+                    });
+                    if *config {
+                        info["config"] = json!({ "check": procedure, "auto-configure": procedure });
+                    }
+                    (id.to_string(), info)
+                })
+                .collect::<serde_json::Map<_, _>>()
+        })
+    }
+
+    #[test]
+    fn plan_order_and_breakages() {
+        let installed = [
+            installed_entry(manifest("bitcoind", "0.21.0", json!({})), json!({})),
+            installed_entry(
+                manifest(
+                    "lnd",
+                    "0.15.0",
+                    depends_on(&[("bitcoind", ">=0.21.0", true)]),
+                ),
+                json!({}),
+            ),
+            installed_entry(
+                manifest(
+                    "electrs",
+                    "0.9.0",
+                    depends_on(&[("bitcoind", "<0.22.0", false)]),
+                ),
+                json!({}),
+            ),
+            installed_entry(
+                manifest("mempool", "2.4.0", depends_on(&[("electrs", "*", false)])),
+                json!({}),
+            ),
+            // already broken before the update
+            installed_entry(
+                manifest("nextcloud", "25.0.0", depends_on(&[("tor", "*", false)])),
+                json!({ "tor": { "type": "not-installed" } }),
+            ),
+            // already broken, but the update changes how
+            installed_entry(
+                manifest(
+                    "specter",
+                    "1.13.0",
+                    depends_on(&[("bitcoind", "<0.21.0", false)]),
+                ),
+                json!({ "bitcoind": {
+                    "type": "incorrect-version",
+                    "expected": "<0.21.0",
+                    "received": "0.21.0",
+                } }),
+            ),
+        ]
+        .into_iter()
+        .map(|pkg| (pkg.manifest.id.clone(), pkg))
+        .collect();
+        let targets = [
+            manifest(
+                "lnd",
+                "0.16.0",
+                depends_on(&[("bitcoind", ">=0.22.0", true)]),
+            ),
+            manifest("bitcoind", "0.22.0", json!({})),
+        ]
+        .into_iter()
+        .map(|manifest| (manifest.id.clone(), manifest))
+        .collect();
+
+        let plan = plan_update(&installed, targets);
+        let id = |id: &str| id.parse::<PackageId>().unwrap();
+        assert_eq!(
+            plan.steps
+                .iter()
+                .map(|step| step.id.as_str())
+                .collect::<Vec<_>>(),
+            vec!["bitcoind", "lnd"]
+        );
+        assert_eq!(
+            plan.breakages
+                .keys()
+                .map(|id| id.as_str())
+                .collect::<Vec<_>>(),
+            vec!["electrs", "mempool", "specter"]
+        );
+        assert!(matches!(
+            plan.breakages[&id("electrs")][..],
+            [TaggedDependencyError {
+                error: DependencyError::IncorrectVersion { .. },
+                ..
+            }]
+        ));
+        assert!(matches!(
+            plan.breakages[&id("mempool")][..],
+            [TaggedDependencyError {
+                error: DependencyError::Transitive,
+                ..
+            }]
+        ));
+        assert_eq!(
+            plan.breakages[&id("mempool")][0].dependency.as_str(),
+            "electrs"
+        );
+        assert!(matches!(
+            &plan.breakages[&id("specter")][..],
+            [TaggedDependencyError {
+                error: DependencyError::IncorrectVersion { received, .. },
+                ..
+            }] if received.as_str() == "0.22.0"
+        ));
+        assert_eq!(
+            plan.reconfigure
+                .iter()
+                .map(|r| (r.dependent.as_str(), r.dependency.as_str()))
+                .collect::<Vec<_>>(),
+            vec![("lnd", "bitcoind")]
+        );
+        assert!(plan.conflicts.is_empty());
+    }
+
+    #[test]
+    fn plan_through_providers() {
+        let provides = |version: &str| json!({ "provides": { "bitcoin-node": version } });
+        let installed = [
+            installed_entry(
+                manifest("bitcoind", "0.21.0", provides("0.21.0")),
+                json!({}),
+            ),
+            installed_entry(
+                manifest(
+                    "electrs",
+                    "0.9.0",
+                    depends_on(&[("bitcoin-node", ">=0.21.0 <0.23.0", false)]),
+                ),
+                json!({}),
+            ),
+            installed_entry(
+                manifest("mempool", "2.4.0", depends_on(&[("electrs", "*", false)])),
+                json!({}),
+            ),
+        ]
+        .into_iter()
+        .map(|pkg| (pkg.manifest.id.clone(), pkg))
+        .collect::<BTreeMap<_, _>>();
+        let update = |version: &str, provided: &str| {
+            plan_update(
+                &installed,
+                [manifest("bitcoind", version, provides(provided))]
+                    .into_iter()
+                    .map(|manifest| (manifest.id.clone(), manifest))
+                    .collect(),
+            )
+        };
+
+        // still provided within range, so nothing breaks
+        assert!(update("0.22.0", "0.22.0").breakages.is_empty());
+
+        let plan = update("0.23.0", "0.23.0");
+        assert_eq!(
+            plan.breakages
+                .keys()
+                .map(|id| id.as_str())
+                .collect::<Vec<_>>(),
+            vec!["electrs", "mempool"]
+        );
+        let electrs = &plan.breakages[&"electrs".parse::<PackageId>().unwrap()];
+        assert_eq!(electrs[0].dependency.as_str(), "bitcoin-node");
+        assert!(matches!(
+            &electrs[0].error,
+            DependencyError::IncorrectVersion { received, .. } if received.as_str() == "0.23.0"
+        ));
     }
-    tx.abort().await?;
-    Ok(BreakageRes(breakages))
 }
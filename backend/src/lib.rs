@@ -28,6 +28,7 @@ pub mod developer;
 pub mod diagnostic;
 pub mod disk;
 pub mod error;
+pub mod exec;
 pub mod hostname;
 pub mod id;
 pub mod init;
@@ -44,6 +45,7 @@ pub mod os_install;
 pub mod procedure;
 pub mod properties;
 pub mod s9pk;
+pub mod scheduled_task;
 pub mod setup;
 pub mod shutdown;
 pub mod sound;
@@ -110,9 +112,17 @@ pub fn server() -> Result<(), RpcError> {
     control::start,
     control::stop,
     control::restart,
+    control::pause,
+    control::resume,
+    manager::metrics::metrics,
+    manager::health::health_history,
+    control::reset_crash_loop,
+    control::autostart,
+    control::restart_policy,
     logs::logs,
     properties::properties,
     dependencies::dependency,
+    exec::exec,
     backup::package_backup,
 ))]
 pub fn package() -> Result<(), RpcError> {
@@ -124,6 +134,7 @@ pub fn package() -> Result<(), RpcError> {
     s9pk::pack,
     developer::verify,
     developer::init,
+    developer::run_procedure,
     inspect::inspect
 ))]
 pub fn portable_api() -> Result<(), RpcError> {
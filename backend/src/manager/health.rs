@@ -1,15 +1,21 @@
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
 use std::sync::atomic::{AtomicBool, Ordering};
 
+use chrono::Utc;
+use color_eyre::eyre::eyre;
 use patch_db::{DbHandle, LockReceipt, LockType};
+use rpc_toolkit::command;
+use tokio::sync::Mutex;
 use tracing::instrument;
 
+use crate::config::not_found;
 use crate::context::RpcContext;
 use crate::db::model::CurrentDependents;
 use crate::dependencies::{break_transitive, heal_transitive, DependencyError};
 use crate::s9pk::manifest::{Manifest, PackageId};
-use crate::status::health_check::{HealthCheckId, HealthCheckResult};
+use crate::status::health_check::{HealthCheckHistory, HealthCheckId, HealthCheckResult};
 use crate::status::MainStatus;
+use crate::util::serde::{display_serializable, IoFormat};
 use crate::Error;
 
 struct HealthCheckPreInformationReceipt {
@@ -90,12 +96,15 @@ impl HealthCheckStatusReceipt {
     }
 }
 
-#[instrument(skip(ctx, db))]
+/// Runs the health checks in `due`, and merges what they report into the health of the running service
+#[instrument(skip(ctx, db, history))]
 pub async fn check<Db: DbHandle>(
     ctx: &RpcContext,
     db: &mut Db,
     id: &PackageId,
     should_commit: &AtomicBool,
+    due: &BTreeSet<HealthCheckId>,
+    history: &Mutex<BTreeMap<HealthCheckId, HealthCheckHistory>>,
 ) -> Result<(), Error> {
     let mut tx = db.begin().await?;
     let (manifest, started) = {
@@ -110,31 +119,67 @@ pub async fn check<Db: DbHandle>(
         (manifest, started)
     };
 
-    let health_results = if let Some(started) = started {
-        manifest
-            .health_checks
-            .check_all(
-                ctx,
-                &manifest.containers,
-                started,
-                id,
-                &manifest.version,
-                &manifest.volumes,
-            )
-            .await?
+    let started = if let Some(started) = started {
+        started
     } else {
         return Ok(());
     };
+    let results = manifest
+        .health_checks
+        .check_some(
+            ctx,
+            &manifest.containers,
+            started,
+            id,
+            &manifest.version,
+            &manifest.volumes,
+            due,
+        )
+        .await?;
+    let now = Utc::now();
+    let reported = {
+        let mut history = history.lock().await;
+        results
+            .into_iter()
+            .map(|(check_id, result)| {
+                let reported = history.entry(check_id.clone()).or_default().record(
+                    &manifest.health_checks.0[&check_id],
+                    started,
+                    now,
+                    result,
+                );
+                (check_id, reported)
+            })
+            .collect::<Vec<_>>()
+    };
 
     if !should_commit.load(Ordering::SeqCst) {
         return Ok(());
     }
-    let current_dependents = {
+    let (health_results, current_dependents) = {
         let mut checkpoint = tx.begin().await?;
         let receipts = HealthCheckStatusReceipt::new(&mut checkpoint, id).await?;
 
         let status = receipts.status.get(&mut checkpoint).await?;
 
+        let mut health_results = match &status {
+            MainStatus::Running { health, .. } => health.clone(),
+            _ => BTreeMap::new(),
+        };
+        health_results.retain(|check_id, _| manifest.health_checks.0.contains_key(check_id));
+        for (check_id, result) in reported {
+            match result {
+                Some(result) => {
+                    health_results.insert(check_id, result);
+                }
+                None => {
+                    health_results
+                        .entry(check_id)
+                        .or_insert(HealthCheckResult::Starting);
+                }
+            }
+        }
+
         if let MainStatus::Running { health: _, started } = status {
             receipts
                 .status
@@ -150,7 +195,7 @@ pub async fn check<Db: DbHandle>(
         let current_dependents = receipts.current_dependents.get(&mut checkpoint).await?;
 
         checkpoint.save().await?;
-        current_dependents
+        (health_results, current_dependents)
     };
 
     tracing::debug!("Checking health of {}", id);
@@ -184,3 +229,39 @@ pub async fn check<Db: DbHandle>(
 
     Ok(())
 }
+
+#[command(rename = "health-history", display(display_serializable))]
+pub async fn health_history(
+    #[context] ctx: RpcContext,
+    #[arg] id: PackageId,
+    #[arg(long = "check")] check: Option<HealthCheckId>,
+    #[allow(unused_variables)]
+    #[arg(long = "format")]
+    format: Option<IoFormat>,
+) -> Result<BTreeMap<HealthCheckId, HealthCheckHistory>, Error> {
+    let version = crate::db::DatabaseModel::new()
+        .package_data()
+        .idx_model(&id)
+        .and_then(|pde| pde.installed())
+        .map(|installed| installed.manifest().version())
+        .get(&mut ctx.db.handle())
+        .await?
+        .into_owned()
+        .ok_or_else(not_found)?;
+    let manager = ctx
+        .managers
+        .get(&(id.clone(), version))
+        .await
+        .ok_or_else(|| Error::new(eyre!("Manager not found"), crate::ErrorKind::InvalidRequest))?;
+    let mut history = manager.health_history().await;
+    if let Some(check) = check {
+        history.retain(|check_id, _| check_id == &check);
+        if history.is_empty() {
+            return Err(Error::new(
+                eyre!("No history for health check {} of {}", check, id),
+                crate::ErrorKind::NotFound,
+            ));
+        }
+    }
+    Ok(history)
+}
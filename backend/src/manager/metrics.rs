@@ -0,0 +1,289 @@
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use bollard::container::{MemoryStatsStats, Stats, StatsOptions};
+use chrono::{DateTime, Utc};
+use color_eyre::eyre::eyre;
+use futures::StreamExt;
+use rpc_toolkit::command;
+use serde::{Deserialize, Serialize};
+use tracing::instrument;
+
+use super::ManagerSharedState;
+use crate::context::RpcContext;
+use crate::s9pk::manifest::PackageId;
+use crate::util::io::dir_size;
+use crate::util::serde::{display_serializable, IoFormat};
+use crate::volume::{data_dir, Volume, VolumeId};
+use crate::Error;
+
+pub const PACKAGE_METRICS_INTERVAL_SECONDS: u64 = 10;
+/// Volume sizes are expensive to walk, so they are only refreshed every this many samples
+const VOLUME_USAGE_EVERY: usize = 6;
+/// The summary in the db is only refreshed every this many samples, so clients aren't sent a patch every sample
+const DB_USAGE_EVERY: usize = 6;
+const MIB: u64 = 1024 * 1024;
+
+/// Resource usage of a running service, as seen by its container's cgroups
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct PackageMetrics {
+    pub time: DateTime<Utc>,
+    /// Percentage of the CPU time of the whole machine
+    pub cpu: f64,
+    /// Bytes of anonymous memory, not counting the page cache
+    pub memory_rss: u64,
+    pub memory_limit: Option<u64>,
+    pub network_rx: u64,
+    pub network_tx: u64,
+    pub block_read: u64,
+    pub block_write: u64,
+    /// Bytes used by each data volume
+    pub volumes: BTreeMap<VolumeId, u64>,
+}
+impl PackageMetrics {
+    fn from_stats(stats: Stats, volumes: BTreeMap<VolumeId, u64>) -> Self {
+        let cpu_delta = stats
+            .cpu_stats
+            .cpu_usage
+            .total_usage
+            .saturating_sub(stats.precpu_stats.cpu_usage.total_usage);
+        let system_delta = stats
+            .cpu_stats
+            .system_cpu_usage
+            .unwrap_or_default()
+            .saturating_sub(stats.precpu_stats.system_cpu_usage.unwrap_or_default());
+        let memory_rss = match &stats.memory_stats.stats {
+            Some(MemoryStatsStats::V1(v1)) => v1.rss,
+            Some(MemoryStatsStats::V2(v2)) => v2.anon,
+            None => stats.memory_stats.usage.unwrap_or_default(),
+        };
+        let (network_rx, network_tx) = stats
+            .networks
+            .iter()
+            .flat_map(|networks| networks.values())
+            .fold((0, 0), |(rx, tx), net| {
+                (rx + net.rx_bytes, tx + net.tx_bytes)
+            });
+        let (block_read, block_write) = stats
+            .blkio_stats
+            .io_service_bytes_recursive
+            .iter()
+            .flatten()
+            .fold((0, 0), |(read, write), entry| {
+                match entry.op.to_ascii_lowercase().as_str() {
+                    "read" => (read + entry.value, write),
+                    "write" => (read, write + entry.value),
+                    _ => (read, write),
+                }
+            });
+        PackageMetrics {
+            time: Utc::now(),
+            cpu: if system_delta > 0 {
+                100.0 * cpu_delta as f64 / system_delta as f64
+            } else {
+                0.0
+            },
+            memory_rss,
+            memory_limit: stats.memory_stats.limit,
+            network_rx,
+            network_tx,
+            block_read,
+            block_write,
+            volumes,
+        }
+    }
+}
+
+/// A coarse summary of [PackageMetrics], kept in the db so the UI can show which services use the most resources
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct PackageUsage {
+    /// Whole percentage of the CPU time of the whole machine
+    pub cpu: u32,
+    /// MiB of anonymous memory
+    pub memory: u64,
+    /// MiB used by the data volumes
+    pub disk: u64,
+}
+impl From<&PackageMetrics> for PackageUsage {
+    fn from(metrics: &PackageMetrics) -> Self {
+        PackageUsage {
+            cpu: metrics.cpu.round() as u32,
+            memory: metrics.memory_rss / MIB,
+            disk: metrics.volumes.values().sum::<u64>() / MIB,
+        }
+    }
+}
+
+async fn volume_usage(shared: &ManagerSharedState) -> BTreeMap<VolumeId, u64> {
+    let manifest = &shared.seed.manifest;
+    let mut res = BTreeMap::new();
+    for (volume_id, volume) in manifest.volumes.iter() {
+        if let Volume::Data { .. } = volume {
+            let path = data_dir(&shared.seed.ctx.datadir, &manifest.id, volume_id);
+            match dir_size(&path).await {
+                Ok(size) => {
+                    res.insert(volume_id.clone(), size);
+                }
+                Err(e) => {
+                    tracing::warn!("Could not measure {}: {}", path.display(), e);
+                }
+            }
+        }
+    }
+    res
+}
+
+#[instrument(skip(shared))]
+async fn get_metrics(
+    shared: &ManagerSharedState,
+    volumes: BTreeMap<VolumeId, u64>,
+) -> Result<PackageMetrics, Error> {
+    let stats = shared
+        .seed
+        .ctx
+        .docker
+        .stats(
+            &shared.seed.container_name,
+            Some(StatsOptions {
+                stream: false,
+                one_shot: false,
+            }),
+        )
+        .next()
+        .await
+        .ok_or_else(|| {
+            Error::new(
+                eyre!("No stats for {}", shared.seed.container_name),
+                crate::ErrorKind::Docker,
+            )
+        })??;
+    Ok(PackageMetrics::from_stats(stats, volumes))
+}
+
+async fn save_usage(shared: &ManagerSharedState, usage: Option<PackageUsage>) -> Result<(), Error> {
+    let mut db = shared.seed.ctx.db.handle();
+    crate::db::DatabaseModel::new()
+        .package_data()
+        .idx_model(&shared.seed.manifest.id)
+        .and_then(|pde| pde.installed())
+        .expect(&mut db)
+        .await?
+        .usage()
+        .put(&mut db, &usage)
+        .await?;
+    Ok(())
+}
+
+/// Samples the resource usage of the service while it runs, into the metrics cache.
+/// Only a coarse [PackageUsage] goes in the db, and only when it changes, since each write sends a patch to every client.
+pub async fn package_metrics_daemon(shared: &ManagerSharedState) {
+    let id = &shared.seed.manifest.id;
+    let mut volumes = BTreeMap::new();
+    let mut saved_usage = None;
+    for sample in 0.. {
+        if sample % VOLUME_USAGE_EVERY == 0 {
+            volumes = volume_usage(shared).await;
+        }
+        match get_metrics(shared, volumes.clone()).await {
+            Ok(metrics) => {
+                let usage = Some(PackageUsage::from(&metrics));
+                if sample % DB_USAGE_EVERY == 0 && usage != saved_usage {
+                    match save_usage(shared, usage.clone()).await {
+                        Ok(()) => saved_usage = usage,
+                        Err(e) => {
+                            tracing::error!("Could not save usage of {}: {}", id, e);
+                            tracing::debug!("{:?}", e);
+                        }
+                    }
+                }
+                shared
+                    .seed
+                    .ctx
+                    .package_metrics_cache
+                    .write()
+                    .await
+                    .insert(id.clone(), metrics);
+            }
+            Err(e) => {
+                tracing::error!("Could not get metrics for {}: {}", id, e);
+                tracing::debug!("{:?}", e);
+            }
+        }
+        tokio::time::sleep(Duration::from_secs(PACKAGE_METRICS_INTERVAL_SECONDS)).await;
+    }
+}
+
+/// Forgets the metrics of a service that stopped running, so they aren't shown as current
+pub async fn clear_metrics(shared: &ManagerSharedState) {
+    let id = &shared.seed.manifest.id;
+    shared
+        .seed
+        .ctx
+        .package_metrics_cache
+        .write()
+        .await
+        .remove(id);
+    if let Err(e) = save_usage(shared, None).await {
+        tracing::error!("Could not clear usage of {}: {}", id, e);
+        tracing::debug!("{:?}", e);
+    }
+}
+
+#[command(display(display_serializable))]
+pub async fn metrics(
+    #[context] ctx: RpcContext,
+    #[arg] id: PackageId,
+    #[allow(unused_variables)]
+    #[arg(long = "format")]
+    format: Option<IoFormat>,
+) -> Result<PackageMetrics, Error> {
+    ctx.package_metrics_cache
+        .read()
+        .await
+        .get(&id)
+        .cloned()
+        .ok_or_else(|| {
+            Error::new(
+                eyre!("No metrics found for {}", id),
+                crate::ErrorKind::NotFound,
+            )
+        })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn usage_is_coarse() {
+        let metrics = |cpu: f64, memory_rss: u64| PackageMetrics {
+            time: Utc::now(),
+            cpu,
+            memory_rss,
+            memory_limit: None,
+            network_rx: 1,
+            network_tx: 2,
+            block_read: 3,
+            block_write: 4,
+            volumes: [
+                (serde_json::from_str("\"main\"").unwrap(), 3 * MIB),
+                (serde_json::from_str("\"cache\"").unwrap(), MIB / 2),
+            ]
+            .into_iter()
+            .collect(),
+        };
+        let usage = PackageUsage::from(&metrics(12.4, 200 * MIB + 1234));
+        assert_eq!(
+            usage,
+            PackageUsage {
+                cpu: 12,
+                memory: 200,
+                disk: 3,
+            }
+        );
+        assert_eq!(usage, PackageUsage::from(&metrics(11.6, 200 * MIB + 4321)));
+        assert_ne!(usage, PackageUsage::from(&metrics(13.0, 200 * MIB)));
+    }
+}
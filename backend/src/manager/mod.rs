@@ -1,41 +1,62 @@
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
 use std::future::Future;
 use std::net::Ipv4Addr;
 use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
 use std::sync::Arc;
 use std::task::Poll;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use bollard::container::{KillContainerOptions, StopContainerOptions};
+use chrono::{DateTime, Utc};
 use color_eyre::eyre::eyre;
 use embassy_container_init::{ProcessGroupId, SignalGroupParams};
+use futures::FutureExt;
 use helpers::UnixRpcClient;
 use nix::sys::signal::Signal;
 use patch_db::DbHandle;
 use sqlx::{Executor, Postgres};
 use tokio::sync::watch::error::RecvError;
 use tokio::sync::watch::{channel, Receiver, Sender};
-use tokio::sync::{oneshot, Notify, RwLock};
+use tokio::sync::{oneshot, Mutex, Notify, RwLock, Semaphore};
 use torut::onion::TorSecretKeyV3;
 use tracing::instrument;
 
 use crate::context::RpcContext;
+use crate::control::RestartPolicy;
+use crate::db::model::ShutdownProgress;
+use crate::dependencies::spawn_dependency_hooks;
 use crate::manager::sync::synchronizer;
 use crate::net::interface::InterfaceId;
 use crate::net::GeneratedCertificateMountPoint;
+use crate::notifications::NotificationLevel;
 use crate::procedure::docker::{DockerContainer, DockerProcedure, LongRunning};
 #[cfg(feature = "js_engine")]
-use crate::procedure::js_scripts::JsProcedure;
+use crate::procedure::js_scripts::{JsProcedure, PackageOsApi};
 use crate::procedure::{NoOutput, PackageProcedure, ProcedureName};
 use crate::s9pk::manifest::{Manifest, PackageId};
+use crate::status::health_check::{HealthCheckHistory, HealthCheckId};
+use crate::status::MainStatus;
 use crate::util::{ApplyRef, Container, NonDetachingJoinHandle, Version};
 use crate::Error;
 
 pub mod health;
+pub mod metrics;
+mod remediation;
+mod schedule;
 mod sync;
 
 pub const HEALTH_CHECK_COOLDOWN_SECONDS: u64 = 15;
 pub const HEALTH_CHECK_GRACE_PERIOD_SECONDS: u64 = 5;
+/// This many failures within the window and the service is considered crash looping
+pub const CRASH_LOOP_THRESHOLD: usize = 5;
+pub const CRASH_LOOP_WINDOW_SECONDS: u64 = 600;
+pub const RESTART_BACKOFF_SECONDS: u64 = 5;
+pub const RESTART_BACKOFF_MAX_SECONDS: u64 = 300;
+const CRASH_LOOP_STDERR_LINES: usize = 20;
+/// How long boot waits for a service to start before moving on to its dependents anyway
+pub const BOOT_START_TIMEOUT_SECONDS: u64 = 300;
+/// Services still running this long into a system shutdown are killed
+pub const SHUTDOWN_KILL_DEADLINE_SECONDS: u64 = 180;
 
 #[derive(Default)]
 pub struct ManagerMap(RwLock<BTreeMap<(PackageId, Version), Arc<Manager>>>);
@@ -51,32 +72,53 @@ impl ManagerMap {
         for<'a> &'a mut Ex: Executor<'a, Database = Postgres>,
     {
         let mut res = BTreeMap::new();
+        let mut to_start = BTreeMap::new();
         for package in crate::db::DatabaseModel::new()
             .package_data()
             .keys(db)
             .await?
         {
-            let man: Manifest = if let Some(manifest) = crate::db::DatabaseModel::new()
+            let installed = if let Some(installed) = crate::db::DatabaseModel::new()
                 .package_data()
                 .idx_model(&package)
                 .and_then(|pkg| pkg.installed())
-                .map(|m| m.manifest())
                 .get(db)
                 .await?
-                .to_owned()
+                .into_owned()
             {
-                manifest
+                installed
             } else {
                 continue;
             };
+            let start = start_on_boot(installed.autostart, &installed.status.main);
+            if installed.status.main.running() && !start {
+                tracing::info!("Not starting {} on boot: autostart is off", package);
+                crate::control::stop_common(db, &package, &mut BTreeMap::new()).await?;
+            }
+            let man: Manifest = installed.manifest;
 
             let tor_keys = man.interfaces.tor_keys(secrets, &package).await?;
+            let manager = Arc::new(Manager::create(ctx.clone(), man, tor_keys, start).await?);
+            if start {
+                to_start.insert(
+                    package.clone(),
+                    (
+                        manager.clone(),
+                        installed
+                            .current_dependencies
+                            .0
+                            .into_keys()
+                            .collect::<BTreeSet<_>>(),
+                    ),
+                );
+            }
             res.insert(
-                (package, man.version.clone()),
-                Arc::new(Manager::create(ctx.clone(), man, tor_keys).await?),
+                (package, manager.shared.seed.manifest.version.clone()),
+                manager,
             );
         }
         *self.0.write().await = res;
+        tokio::spawn(boot(to_start, ctx.startup_parallelism));
         Ok(())
     }
 
@@ -91,12 +133,12 @@ impl ManagerMap {
         let id = (manifest.id.clone(), manifest.version.clone());
         if let Some(man) = lock.remove(&id) {
             if !man.thread.is_empty().await {
-                man.exit().await?;
+                man.exit(None).await?;
             }
         }
         lock.insert(
             id,
-            Arc::new(Manager::create(ctx, manifest, tor_keys).await?),
+            Arc::new(Manager::create(ctx, manifest, tor_keys, false).await?),
         );
         Ok(())
     }
@@ -104,33 +146,89 @@ impl ManagerMap {
     #[instrument(skip(self))]
     pub async fn remove(&self, id: &(PackageId, Version)) {
         if let Some(man) = self.0.write().await.remove(id) {
-            if let Err(e) = man.exit().await {
+            if let Err(e) = man.exit(None).await {
                 tracing::error!("Error shutting down manager: {}", e);
                 tracing::debug!("{:?}", e);
             }
+            man.shared
+                .seed
+                .ctx
+                .package_metrics_cache
+                .write()
+                .await
+                .remove(&id.0);
         }
     }
 
-    #[instrument(skip(self))]
-    pub async fn empty(&self) -> Result<(), Error> {
-        let res =
-            futures::future::join_all(std::mem::take(&mut *self.0.write().await).into_iter().map(
-                |((id, version), man)| async move {
-                    tracing::debug!("Manager for {}@{} shutting down", id, version);
-                    man.exit().await?;
-                    tracing::debug!("Manager for {}@{} is shutdown", id, version);
-                    if let Err(e) = Arc::try_unwrap(man) {
-                        tracing::trace!(
-                            "Manager for {}@{} still has {} other open references",
-                            id,
-                            version,
-                            Arc::strong_count(&e) - 1
-                        );
-                    }
-                    Ok::<_, Error>(())
-                },
-            ))
-            .await;
+    /// Shuts down every manager, dependents before their dependencies.
+    /// Each service gets its `sigterm-timeout` to stop, but anything still running after [SHUTDOWN_KILL_DEADLINE_SECONDS] is killed.
+    #[instrument(skip(self, ctx))]
+    pub async fn empty(&self, ctx: &RpcContext) -> Result<(), Error> {
+        let mut db = ctx.db.handle();
+        let mut managers = BTreeMap::new();
+        let mut deps = BTreeMap::new();
+        for ((id, version), man) in std::mem::take(&mut *self.0.write().await) {
+            let current_dependencies = match crate::db::DatabaseModel::new()
+                .package_data()
+                .idx_model(&id)
+                .and_then(|pde| pde.installed())
+                .map(|installed| installed.current_dependencies())
+                .get(&mut db)
+                .await
+            {
+                Ok(a) => a.into_owned().map(|a| a.0.into_keys().collect()),
+                Err(e) => {
+                    tracing::error!("Failed to read dependencies of {}: {}", id, e);
+                    tracing::debug!("{:?}", e);
+                    None
+                }
+            };
+            deps.insert(id.clone(), current_dependencies.unwrap_or_default());
+            managers.insert(id, (version, man));
+        }
+        let deadline = Instant::now() + Duration::from_secs(SHUTDOWN_KILL_DEADLINE_SECONDS);
+        let progress = Mutex::new(ShutdownProgress {
+            stopping: BTreeSet::new(),
+            stopped: 0,
+            total: managers.len(),
+            kill_at: Utc::now() + chrono::Duration::seconds(SHUTDOWN_KILL_DEADLINE_SECONDS as i64),
+        });
+        let mut res = Vec::with_capacity(managers.len());
+        for layer in dependency_layers(&deps).into_iter().rev() {
+            {
+                let mut progress = progress.lock().await;
+                progress.stopping = layer.iter().cloned().collect();
+                publish_shutdown_progress(ctx, &progress).await;
+            }
+            let progress = &progress;
+            res.extend(
+                futures::future::join_all(layer.into_iter().filter_map(|id| {
+                    let (version, man) = managers.remove(&id)?;
+                    Some(async move {
+                        tracing::debug!("Manager for {}@{} shutting down", id, version);
+                        let exited = man.exit(Some(deadline)).await;
+                        {
+                            let mut progress = progress.lock().await;
+                            progress.stopping.remove(&id);
+                            progress.stopped += 1;
+                            publish_shutdown_progress(ctx, &progress).await;
+                        }
+                        exited?;
+                        tracing::debug!("Manager for {}@{} is shutdown", id, version);
+                        if let Err(e) = Arc::try_unwrap(man) {
+                            tracing::trace!(
+                                "Manager for {}@{} still has {} other open references",
+                                id,
+                                version,
+                                Arc::strong_count(&e) - 1
+                            );
+                        }
+                        Ok::<_, Error>(())
+                    })
+                }))
+                .await,
+            );
+        }
         res.into_iter().fold(Ok(()), |res, x| match (res, x) {
             (Ok(()), x) => x,
             (Err(e), Ok(())) => Err(e),
@@ -174,10 +272,147 @@ pub struct ManagerSharedState {
     synchronized: Notify,
     synchronize_now: Notify,
     commit_health_check_results: AtomicBool,
+    health_history: Mutex<BTreeMap<HealthCheckId, HealthCheckHistory>>,
+    /// When each health check was last remediated
+    remediations: Mutex<BTreeMap<HealthCheckId, DateTime<Utc>>>,
+    /// Held back from starting until boot gets to it
+    held: AtomicBool,
     next_gid: AtomicU32,
     main_gid: (Sender<ProcessGroupId>, Receiver<ProcessGroupId>),
 }
 
+/// Whether a package should be started on boot: it has to have been running at shutdown, and still have autostart on.
+pub fn start_on_boot(autostart: bool, status: &MainStatus) -> bool {
+    autostart && status.running()
+}
+
+/// Groups packages so that every package comes after all of its dependencies.
+/// Dependencies that aren't in `deps` are ignored, and any packages left in a cycle go in the last group together.
+pub fn dependency_layers(deps: &BTreeMap<PackageId, BTreeSet<PackageId>>) -> Vec<Vec<PackageId>> {
+    let mut remaining: BTreeMap<&PackageId, BTreeSet<&PackageId>> = deps
+        .iter()
+        .map(|(id, deps_of)| {
+            (
+                id,
+                deps_of
+                    .iter()
+                    .filter(|dep| *dep != id && deps.contains_key(*dep))
+                    .collect(),
+            )
+        })
+        .collect();
+    let mut layers = Vec::new();
+    while !remaining.is_empty() {
+        let layer: Vec<&PackageId> = remaining
+            .iter()
+            .filter(|(_, deps_of)| deps_of.is_empty())
+            .map(|(id, _)| *id)
+            .collect();
+        if layer.is_empty() {
+            tracing::warn!(
+                "Dependency cycle between {}",
+                remaining
+                    .keys()
+                    .map(|id| id.as_ref())
+                    .collect::<Vec<&str>>()
+                    .join(", ")
+            );
+            layers.push(remaining.into_keys().cloned().collect());
+            break;
+        }
+        for id in &layer {
+            remaining.remove(id);
+        }
+        for deps_of in remaining.values_mut() {
+            for id in &layer {
+                deps_of.remove(id);
+            }
+        }
+        layers.push(layer.into_iter().cloned().collect());
+    }
+    layers
+}
+
+/// Starts the services that were running before boot, each one only after its dependencies have started.
+/// At most `parallelism` services are starting at any one time.
+#[instrument(skip(to_start))]
+async fn boot(
+    mut to_start: BTreeMap<PackageId, (Arc<Manager>, BTreeSet<PackageId>)>,
+    parallelism: usize,
+) {
+    let deps = to_start
+        .iter()
+        .map(|(id, (_, deps))| (id.clone(), deps.clone()))
+        .collect();
+    let semaphore = Semaphore::new(parallelism.max(1));
+    let mut started = BTreeMap::new();
+    for id in dependency_layers(&deps).into_iter().flatten() {
+        let (manager, deps) = match to_start.remove(&id) {
+            Some(a) => a,
+            None => continue,
+        };
+        let deps_started = deps
+            .iter()
+            .filter_map(|dep| started.get(dep).cloned())
+            .collect::<Vec<_>>();
+        let semaphore = &semaphore;
+        let pkg_id = id.clone();
+        let fut = async move {
+            futures::future::join_all(deps_started).await;
+            let _permit = semaphore.acquire().await;
+            tracing::info!("Starting {}", pkg_id);
+            manager.shared.held.store(false, Ordering::SeqCst);
+            if let Err(e) = start(&manager.shared).await {
+                tracing::error!("Failed to start {}: {}", pkg_id, e);
+                tracing::debug!("{:?}", e);
+                return;
+            }
+            if tokio::time::timeout(
+                Duration::from_secs(BOOT_START_TIMEOUT_SECONDS),
+                wait_for_start(&manager.shared),
+            )
+            .await
+            .is_err()
+            {
+                tracing::warn!(
+                    "{} is taking a long time to start, starting its dependents",
+                    pkg_id
+                );
+            }
+        }
+        .boxed()
+        .shared();
+        started.insert(id, fut);
+    }
+    futures::future::join_all(started.into_values()).await;
+}
+
+async fn publish_shutdown_progress(ctx: &RpcContext, progress: &ShutdownProgress) {
+    if let Err(e) = crate::db::DatabaseModel::new()
+        .server_info()
+        .status_info()
+        .shutdown_progress()
+        .put(&mut ctx.db.handle(), progress)
+        .await
+    {
+        tracing::error!("Failed to publish shutdown progress: {}", e);
+        tracing::debug!("{:?}", e);
+    }
+}
+
+/// Waits for the service to leave [Status::Starting], whether it made it to running or not
+async fn wait_for_start(shared: &ManagerSharedState) {
+    let mut recv = shared.status.0.subscribe();
+    loop {
+        if *recv.borrow() != Status::Starting {
+            return;
+        }
+        if recv.changed().await.is_err() {
+            return;
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy)]
 pub enum OnStop {
     Restart,
@@ -212,12 +447,17 @@ async fn run_main(
 
     set_commit_health_true(state);
     let health = main_health_check_daemon(state.clone());
+    let scheduled_tasks = schedule::run_scheduled_tasks(&state.seed.ctx, &state.seed.manifest);
+    let metrics = metrics::package_metrics_daemon(state);
     fetch_starting_to_running(state);
     let res = tokio::select! {
         a = runtime => a.map_err(|_| Error::new(eyre!("Manager runtime panicked!"), crate::ErrorKind::Docker)).and_then(|a| a),
         _ = health => Err(Error::new(eyre!("Health check daemon exited!"), crate::ErrorKind::Unknown)),
+        _ = scheduled_tasks => Err(Error::new(eyre!("Scheduled task daemon exited!"), crate::ErrorKind::Unknown)),
+        _ = metrics => Err(Error::new(eyre!("Metrics daemon exited!"), crate::ErrorKind::Unknown)),
         _ = state.killer.notified() => Ok(Err((137, "Killed".to_string())))
     };
+    metrics::clear_metrics(state).await;
     if let Some(ip) = ip {
         remove_network_for_main(&*state.seed, ip).await?;
     }
@@ -252,6 +492,7 @@ impl Manager {
         ctx: RpcContext,
         manifest: Manifest,
         tor_keys: BTreeMap<InterfaceId, TorSecretKeyV3>,
+        held: bool,
     ) -> Result<Self, Error> {
         let (on_stop, recv) = channel(OnStop::Sleep);
         let seed = Arc::new(ManagerSeed {
@@ -261,6 +502,16 @@ impl Manager {
             tor_keys,
         });
         let persistent_container = PersistentContainer::init(&seed).await?;
+        // so a reboot does not reset the cooldown of a remediation
+        let events = crate::db::DatabaseModel::new()
+            .package_data()
+            .idx_model(&seed.manifest.id)
+            .and_then(|pde| pde.installed())
+            .map(|i| i.events())
+            .get(&mut seed.ctx.db.handle())
+            .await?
+            .into_owned()
+            .unwrap_or_default();
         let shared = Arc::new(ManagerSharedState {
             seed,
             persistent_container,
@@ -270,6 +521,9 @@ impl Manager {
             synchronized: Notify::new(),
             synchronize_now: Notify::new(),
             commit_health_check_results: AtomicBool::new(true),
+            health_history: Mutex::new(BTreeMap::new()),
+            remediations: Mutex::new(remediation::last_remediations(&events)),
+            held: AtomicBool::new(held),
             next_gid: AtomicU32::new(1),
             main_gid: channel(ProcessGroupId(0)),
         });
@@ -291,8 +545,18 @@ impl Manager {
         send_signal(&self.shared, signal).await
     }
 
+    pub async fn health_history(&self) -> BTreeMap<HealthCheckId, HealthCheckHistory> {
+        self.shared.health_history.lock().await.clone()
+    }
+
+    /// Stops the service and the manager thread. The service gets its `sigterm-timeout` to stop, but no later than `kill_at`.
     #[instrument(skip(self))]
-    async fn exit(&self) -> Result<(), Error> {
+    async fn exit(&self, kill_at: Option<Instant>) -> Result<(), Error> {
+        let mut timeout =
+            sigterm_timeout(&self.shared.seed.manifest).unwrap_or(Duration::from_secs(30));
+        if let Some(kill_at) = kill_at {
+            timeout = timeout.min(kill_at.saturating_duration_since(Instant::now()));
+        }
         self.shared
             .commit_health_check_results
             .store(false, Ordering::SeqCst);
@@ -306,9 +570,7 @@ impl Manager {
             .stop_container(
                 &self.shared.seed.container_name,
                 Some(StopContainerOptions {
-                    t: sigterm_timeout(&self.shared.seed.manifest)
-                        .map(|d| d.as_secs())
-                        .unwrap_or(30) as i64,
+                    t: timeout.as_secs() as i64,
                 }),
             )
             .await
@@ -367,7 +629,32 @@ impl Manager {
     }
 }
 
+/// Recent failures of the main procedure, to back off restarts and notice a crash loop
+#[derive(Default)]
+struct CrashTracker(VecDeque<Instant>);
+impl CrashTracker {
+    /// Returns how many failures there have been within the window, including this one
+    fn record(&mut self) -> usize {
+        let now = Instant::now();
+        while self.0.front().map_or(false, |failure| {
+            now.duration_since(*failure) > Duration::from_secs(CRASH_LOOP_WINDOW_SECONDS)
+        }) {
+            self.0.pop_front();
+        }
+        self.0.push_back(now);
+        self.0.len()
+    }
+    fn backoff(failures: usize) -> Duration {
+        Duration::from_secs(
+            RESTART_BACKOFF_SECONDS
+                .saturating_mul(1 << failures.saturating_sub(1).min(16))
+                .min(RESTART_BACKOFF_MAX_SECONDS),
+        )
+    }
+}
+
 async fn manager_thread_loop(mut recv: Receiver<OnStop>, thread_shared: &Arc<ManagerSharedState>) {
+    let mut crashes = CrashTracker::default();
     loop {
         fn handle_stop_action<'a>(
             recv: &'a mut Receiver<OnStop>,
@@ -385,6 +672,7 @@ async fn manager_thread_loop(mut recv: Receiver<OnStop>, thread_shared: &Arc<Man
         match stop_action {
             OnStop::Sleep => {
                 if let Some(fut) = fut {
+                    crashes = CrashTracker::default();
                     let _ = thread_shared.status.0.send(Status::Stopped);
                     fut.await.unwrap();
                     continue;
@@ -398,13 +686,11 @@ async fn manager_thread_loop(mut recv: Receiver<OnStop>, thread_shared: &Arc<Man
                 let _ = thread_shared.status.0.send(Status::Running);
             }
         }
-        match run_main(thread_shared).await {
-            Ok(Ok(NoOutput)) => (), // restart
+        let failure = match run_main(thread_shared).await {
+            Ok(Ok(NoOutput)) => None, // restart
             Ok(Err(e)) => {
                 #[cfg(feature = "unstable")]
                 {
-                    use crate::notifications::NotificationLevel;
-                    use crate::status::MainStatus;
                     let mut db = thread_shared.seed.ctx.db.handle();
                     let started = crate::db::DatabaseModel::new()
                         .package_data()
@@ -437,16 +723,163 @@ async fn manager_thread_loop(mut recv: Receiver<OnStop>, thread_shared: &Arc<Man
                     }
                 }
                 tracing::error!("service crashed: {}: {}", e.0, e.1);
-                tokio::time::sleep(Duration::from_secs(15)).await;
+                Some((Some(e.0), e.1))
             }
             Err(e) => {
                 tracing::error!("failed to start service: {}", e);
                 tracing::debug!("{:?}", e);
+                Some((None, e.to_string()))
+            }
+        };
+        // exiting because it was asked to stop is not a crash
+        let on_stop = *thread_shared.on_stop.borrow();
+        if !matches!(on_stop, OnStop::Restart) {
+            continue;
+        }
+        if !restart_policy(thread_shared)
+            .await
+            .restarts(failure.is_none())
+        {
+            leave_stopped(thread_shared).await;
+            continue;
+        }
+        if let Some((exit_code, stderr)) = failure {
+            let failures = crashes.record();
+            if failures >= CRASH_LOOP_THRESHOLD {
+                crash_loop(thread_shared, failures, exit_code, &stderr).await;
+                continue;
+            }
+            let backoff = CrashTracker::backoff(failures);
+            tracing::info!(
+                "restarting {} in {}s",
+                thread_shared.seed.manifest.id,
+                backoff.as_secs()
+            );
+            let mut on_stop = thread_shared.on_stop.subscribe();
+            tokio::select! {
+                _ = tokio::time::sleep(backoff) => (),
+                _ = on_stop.changed() => (),
             }
         }
     }
 }
 
+async fn restart_policy(shared: &ManagerSharedState) -> RestartPolicy {
+    let mut db = shared.seed.ctx.db.handle();
+    match crate::db::DatabaseModel::new()
+        .package_data()
+        .idx_model(&shared.seed.manifest.id)
+        .and_then(|pde| pde.installed())
+        .get(&mut db)
+        .await
+    {
+        Ok(installed) => installed
+            .as_ref()
+            .map(|installed| installed.restart_policy)
+            .unwrap_or_default(),
+        Err(e) => {
+            tracing::error!("Failed to read restart policy: {}", e);
+            tracing::debug!("{:?}", e);
+            RestartPolicy::default()
+        }
+    }
+}
+
+/// The service exited, and its restart policy says to leave it that way
+#[instrument(skip(shared))]
+async fn leave_stopped(shared: &ManagerSharedState) {
+    shared.on_stop.send_modify(|status| {
+        if matches!(*status, OnStop::Restart) {
+            *status = OnStop::Sleep;
+        }
+    });
+    let id = &shared.seed.manifest.id;
+    tracing::info!("{} exited, not restarting it due to its restart policy", id);
+    if let Err(e) = async {
+        let mut db = shared.seed.ctx.db.handle();
+        let mut tx = db.begin().await?;
+        let last_status = crate::control::exit_common(&mut tx, id, MainStatus::Stopped).await?;
+        tx.commit().await?;
+        if let Some(event) = last_status.and_then(|s| s.transition(&MainStatus::Stopped)) {
+            spawn_dependency_hooks(&shared.seed.ctx, id, event);
+        }
+        Ok::<_, Error>(())
+    }
+    .await
+    {
+        tracing::error!("Failed to record exit of {}: {}", id, e);
+        tracing::debug!("{:?}", e);
+    }
+}
+
+/// Stops restarting the service, and tells the user about it
+#[instrument(skip(shared, stderr))]
+async fn crash_loop(
+    shared: &ManagerSharedState,
+    failures: usize,
+    exit_code: Option<i32>,
+    stderr: &str,
+) {
+    shared.on_stop.send_modify(|status| {
+        if matches!(*status, OnStop::Restart) {
+            *status = OnStop::Sleep;
+        }
+    });
+    let id = &shared.seed.manifest.id;
+    tracing::error!(
+        "{} failed {} times within {}s, no longer restarting it",
+        id,
+        failures,
+        CRASH_LOOP_WINDOW_SECONDS
+    );
+    let lines = stderr.lines().collect::<Vec<_>>();
+    let stderr = lines[lines.len().saturating_sub(CRASH_LOOP_STDERR_LINES)..].join("\n");
+    if let Err(e) = async {
+        let mut db = shared.seed.ctx.db.handle();
+        let mut tx = db.begin().await?;
+        let crash_looping = MainStatus::CrashLooping {
+            since: Utc::now(),
+            failures: failures as u32,
+            exit_code,
+            stderr: stderr.clone(),
+        };
+        let last_status =
+            crate::control::exit_common(&mut tx, id, crash_looping.clone()).await?;
+        tx.commit().await?;
+        if let Some(last_status) = last_status {
+            if let Some(event) = last_status.transition(&crash_looping) {
+                spawn_dependency_hooks(&shared.seed.ctx, id, event);
+            }
+            shared
+                .seed
+                .ctx
+                .notification_manager
+                .notify(
+                    &mut db,
+                    Some(id.clone()),
+                    NotificationLevel::Error,
+                    String::from("Service Crash Looping"),
+                    format!(
+                        "The service {} failed {} times in a row and will not be restarted until it is started again.\nExit code: {}\nDetails: {}",
+                        id,
+                        failures,
+                        exit_code.map_or_else(|| "none".to_owned(), |c| c.to_string()),
+                        stderr
+                    ),
+                    (),
+                    None,
+                )
+                .await?;
+        }
+        Ok::<_, Error>(())
+    }
+    .await
+    {
+        tracing::error!("Failed to record crash loop of {}: {}", id, e);
+        tracing::debug!("{:?}", e);
+    }
+}
+
 pub struct PersistentContainer {
     _running_docker: NonDetachingJoinHandle<()>,
     rpc_client: Receiver<Arc<UnixRpcClient>>,
@@ -560,18 +993,48 @@ fn fetch_starting_to_running(state: &Arc<ManagerSharedState>) {
     });
 }
 
+/// Runs each health check on its own interval while the service runs
 async fn main_health_check_daemon(state: Arc<ManagerSharedState>) {
+    let checks = &state.seed.manifest.health_checks.0;
+    for history in state.health_history.lock().await.values_mut() {
+        history.consecutive_failures = 0;
+    }
     tokio::time::sleep(Duration::from_secs(HEALTH_CHECK_GRACE_PERIOD_SECONDS)).await;
+    let start = Instant::now();
+    let mut schedule: BTreeMap<&HealthCheckId, Instant> =
+        checks.keys().map(|check_id| (check_id, start)).collect();
     loop {
+        let next = match schedule.values().min() {
+            Some(next) => *next,
+            None => return futures::future::pending::<()>().await,
+        };
+        tokio::time::sleep_until(next.into()).await;
+        let now = Instant::now();
+        let due = schedule
+            .iter()
+            .filter(|(_, at)| **at <= now)
+            .map(|(check_id, _)| (*check_id).clone())
+            .collect::<BTreeSet<_>>();
         let mut db = state.seed.ctx.db.handle();
-        if let Err(e) = health::check(
+        let res = health::check(
             &state.seed.ctx,
             &mut db,
             &state.seed.manifest.id,
             &state.commit_health_check_results,
+            &due,
+            &state.health_history,
         )
-        .await
-        {
+        .await;
+        if res.is_ok() {
+            remediation::remediate(&state, &due).await;
+        }
+        let now = Instant::now();
+        for check_id in &due {
+            if let Some((check_id, check)) = checks.get_key_value(check_id) {
+                schedule.insert(check_id, now + check.interval());
+            }
+        }
+        if let Err(e) = res {
             tracing::error!(
                 "Failed to run health check for {}: {}",
                 &state.seed.manifest.id,
@@ -579,7 +1042,6 @@ async fn main_health_check_daemon(state: Arc<ManagerSharedState>) {
             );
             tracing::debug!("{:?}", e);
         }
-        tokio::time::sleep(Duration::from_secs(HEALTH_CHECK_COOLDOWN_SECONDS)).await;
     }
 }
 
@@ -833,6 +1295,10 @@ async fn pause(shared: &ManagerSharedState) -> Result<(), Error> {
         tracing::debug!("{:?}", e);
         return stop(shared).await;
     }
+    // a frozen container fails its health checks, which says nothing about it
+    shared
+        .commit_health_check_results
+        .store(false, Ordering::SeqCst);
     let _ = shared.status.0.send(Status::Paused);
     Ok(())
 }
@@ -845,6 +1311,9 @@ async fn resume(shared: &ManagerSharedState) -> Result<(), Error> {
         .docker
         .unpause_container(&shared.seed.container_name)
         .await?;
+    shared
+        .commit_health_check_results
+        .store(true, Ordering::SeqCst);
     let _ = shared.status.0.send(Status::Running);
     Ok(())
 }
@@ -879,6 +1348,10 @@ async fn send_signal(shared: &ManagerSharedState, signal: &Signal) -> Result<(),
                         .fetch_add(1, std::sync::atomic::Ordering::SeqCst),
                 ),
                 Some(rpc_client),
+                Some(Arc::new(PackageOsApi::new(
+                    shared.seed.ctx.clone(),
+                    shared.seed.manifest.id.clone(),
+                ))),
             )
             .await?
         {
@@ -918,3 +1391,48 @@ async fn send_signal(shared: &ManagerSharedState, signal: &Signal) -> Result<(),
 
     Ok(())
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn dependency_layers_order() {
+        let id = |s: &str| s.parse::<PackageId>().unwrap();
+        let deps = [
+            ("btcpay", vec!["bitcoind", "lnd"]),
+            ("lnd", vec!["bitcoind"]),
+            ("bitcoind", vec![]),
+            ("nextcloud", vec!["not-installed"]),
+        ]
+        .into_iter()
+        .map(|(pkg, deps)| (id(pkg), deps.into_iter().map(id).collect()))
+        .collect();
+        assert_eq!(
+            dependency_layers(&deps),
+            vec![
+                vec![id("bitcoind"), id("nextcloud")],
+                vec![id("lnd")],
+                vec![id("btcpay")],
+            ]
+        );
+
+        let cycle = [("a", vec!["b"]), ("b", vec!["a"]), ("c", vec![])]
+            .into_iter()
+            .map(|(pkg, deps)| (id(pkg), deps.into_iter().map(id).collect()))
+            .collect();
+        assert_eq!(
+            dependency_layers(&cycle),
+            vec![vec![id("c")], vec![id("a"), id("b")]]
+        );
+    }
+
+    #[test]
+    fn start_on_boot_respects_autostart() {
+        let running = MainStatus::Starting { restarting: false };
+        assert!(start_on_boot(true, &running));
+        assert!(!start_on_boot(false, &running));
+        assert!(!start_on_boot(true, &MainStatus::Stopped));
+        assert!(!start_on_boot(false, &MainStatus::Stopped));
+    }
+}
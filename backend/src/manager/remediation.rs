@@ -0,0 +1,293 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::atomic::Ordering;
+
+use chrono::{DateTime, Utc};
+use color_eyre::eyre::eyre;
+use patch_db::DbHandle;
+use tracing::instrument;
+
+use super::ManagerSharedState;
+use crate::db::model::{PackageEvent, PackageEventKind};
+use crate::notifications::NotificationLevel;
+use crate::status::health_check::{
+    HealthCheckHistory, HealthCheckId, HealthChecks, Remediation, RemediationKind,
+};
+use crate::status::MainStatus;
+use crate::Error;
+
+/// Carries out the remediation of each health check in `checked` that has failed enough times in a row,
+/// unless that check was already remediated within its cooldown.
+#[instrument(skip(shared))]
+pub async fn remediate(shared: &ManagerSharedState, checked: &BTreeSet<HealthCheckId>) {
+    let manifest = &shared.seed.manifest;
+    let due = {
+        let history = shared.health_history.lock().await;
+        let mut remediations = shared.remediations.lock().await;
+        let now = Utc::now();
+        let due = select(
+            shared.commit_health_check_results.load(Ordering::SeqCst),
+            &manifest.health_checks,
+            &history,
+            &remediations,
+            checked,
+            now,
+        );
+        for (check_id, _, _) in &due {
+            remediations.insert((*check_id).clone(), now);
+        }
+        due
+    };
+    for (check_id, remediation, failures) in due {
+        tracing::warn!(
+            "Health check {} of {} failed {} times in a row, remediating",
+            check_id,
+            manifest.id,
+            failures
+        );
+        if let Err(e) = execute(shared, check_id, remediation, failures).await {
+            tracing::error!(
+                "Failed to remediate health check {} of {}: {}",
+                check_id,
+                manifest.id,
+                e
+            );
+            tracing::debug!("{:?}", e);
+        }
+    }
+}
+
+/// The health checks among `checked` that are due for remediation at `now`, with their remediation and
+/// how many times in a row they failed. Nothing is due while health check results are not being committed.
+pub fn select<'a>(
+    committing: bool,
+    health_checks: &'a HealthChecks,
+    history: &BTreeMap<HealthCheckId, HealthCheckHistory>,
+    remediations: &BTreeMap<HealthCheckId, DateTime<Utc>>,
+    checked: &'a BTreeSet<HealthCheckId>,
+    now: DateTime<Utc>,
+) -> Vec<(&'a HealthCheckId, &'a Remediation, u32)> {
+    if !committing {
+        return Vec::new();
+    }
+    checked
+        .iter()
+        .filter_map(|check_id| {
+            let remediation = health_checks.0.get(check_id)?.remediation.as_ref()?;
+            let failures = history.get(check_id)?.consecutive_failures;
+            if failures < remediation.after {
+                return None;
+            }
+            if let Some(last) = remediations.get(check_id) {
+                // a clock that went backwards counts as still cooling down
+                if now
+                    .signed_duration_since(*last)
+                    .to_std()
+                    .map_or(true, |elapsed| elapsed < remediation.cooldown())
+                {
+                    return None;
+                }
+            }
+            Some((check_id, remediation, failures))
+        })
+        .collect()
+}
+
+/// When each health check was last remediated, according to the recorded `events`
+pub fn last_remediations(events: &[PackageEvent]) -> BTreeMap<HealthCheckId, DateTime<Utc>> {
+    events
+        .iter()
+        .filter_map(|event| match &event.kind {
+            PackageEventKind::Remediation { health_check, .. } => {
+                Some((health_check.clone(), event.time))
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+async fn execute(
+    shared: &ManagerSharedState,
+    check_id: &HealthCheckId,
+    remediation: &Remediation,
+    failures: u32,
+) -> Result<(), Error> {
+    let ctx = &shared.seed.ctx;
+    let manifest = &shared.seed.manifest;
+    let mut db = ctx.db.handle();
+    let event = |error: Option<String>| PackageEvent {
+        time: Utc::now(),
+        kind: PackageEventKind::Remediation {
+            health_check: check_id.clone(),
+            failures,
+            remediation: remediation.kind.clone(),
+            error,
+        },
+    };
+    let res = match &remediation.kind {
+        RemediationKind::Restart => {
+            // the restart tears down this daemon, so the event has to be recorded first
+            let mut tx = db.begin().await?;
+            event(None).record(&mut tx, &manifest.id).await?;
+            let mut status = crate::db::DatabaseModel::new()
+                .package_data()
+                .idx_model(&manifest.id)
+                .and_then(|pde| pde.installed())
+                .map(|i| i.status().main())
+                .get_mut(&mut tx)
+                .await?;
+            if matches!(&*status, Some(MainStatus::Running { .. })) {
+                *status = Some(MainStatus::Restarting);
+                status.save(&mut tx).await?;
+            }
+            tx.commit().await?;
+            shared.synchronize_now.notify_one();
+            return Ok(());
+        }
+        RemediationKind::Action { action } => match manifest.actions.0.get(action) {
+            Some(a) => a
+                .execute(
+                    ctx,
+                    &manifest.id,
+                    &manifest.version,
+                    action,
+                    &manifest.volumes,
+                    None,
+                )
+                .await
+                .map(|_| ()),
+            None => Err(Error::new(
+                eyre!("Action {} not found in manifest", action),
+                crate::ErrorKind::NotFound,
+            )),
+        },
+        RemediationKind::Notify { message } => {
+            ctx.notification_manager
+                .notify(
+                    &mut db,
+                    Some(manifest.id.clone()),
+                    NotificationLevel::Warning,
+                    format!(
+                        "Health Check Failing: {}",
+                        manifest.health_checks.0[check_id].name
+                    ),
+                    message.clone(),
+                    (),
+                    None,
+                )
+                .await
+        }
+    };
+    event(res.as_ref().err().map(|e| e.source.to_string()))
+        .record(&mut db, &manifest.id)
+        .await?;
+    res
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn select_due() {
+        let health_checks: HealthChecks = serde_json::from_value(serde_json::json!({
+            "web": {
+                "name": "Web",
+                "type": "docker",
+                "image": "main",
+                "entrypoint": "check-web",
+                "remediation": { "type": "restart", "after": 3 },
+            },
+            "sync": {
+                "name": "Sync",
+                "type": "docker",
+                "image": "main",
+                "entrypoint": "check-sync",
+            },
+        }))
+        .unwrap();
+        let web: HealthCheckId = "web".parse().unwrap();
+        let sync: HealthCheckId = "sync".parse().unwrap();
+        let checked: BTreeSet<_> = [web.clone(), sync.clone()].into_iter().collect();
+        let failing = |failures| {
+            [&web, &sync]
+                .into_iter()
+                .map(|id| {
+                    (
+                        id.clone(),
+                        HealthCheckHistory {
+                            consecutive_failures: failures,
+                            ..Default::default()
+                        },
+                    )
+                })
+                .collect::<BTreeMap<_, _>>()
+        };
+        let now = Utc::now();
+        let due = |committing, history: &_, remediations: &_| {
+            select(
+                committing,
+                &health_checks,
+                history,
+                remediations,
+                &checked,
+                now,
+            )
+            .into_iter()
+            .map(|(id, _, failures)| (id.to_string(), failures))
+            .collect::<Vec<_>>()
+        };
+        let never = BTreeMap::new();
+        assert!(due(true, &failing(2), &never).is_empty());
+        assert_eq!(due(true, &failing(3), &never), vec![("web".to_owned(), 3)]);
+        assert!(due(false, &failing(3), &never).is_empty());
+
+        // the default cooldown is 10 minutes
+        let remediated = |minutes_ago| {
+            [(web.clone(), now - chrono::Duration::minutes(minutes_ago))]
+                .into_iter()
+                .collect::<BTreeMap<_, _>>()
+        };
+        assert!(due(true, &failing(4), &remediated(5)).is_empty());
+        assert_eq!(
+            due(true, &failing(4), &remediated(11)),
+            vec![("web".to_owned(), 4)]
+        );
+    }
+
+    #[test]
+    fn last_remediations_from_events() {
+        let start = Utc::now();
+        let event = |minutes, health_check: &str| PackageEvent {
+            time: start + chrono::Duration::minutes(minutes),
+            kind: PackageEventKind::Remediation {
+                health_check: health_check.parse().unwrap(),
+                failures: 3,
+                remediation: RemediationKind::Restart,
+                error: None,
+            },
+        };
+        let events = vec![
+            event(0, "web"),
+            event(1, "sync"),
+            PackageEvent {
+                time: start + chrono::Duration::minutes(2),
+                kind: PackageEventKind::DependencyHook {
+                    dependency: "bitcoind".parse().unwrap(),
+                    event: models::DependencyEvent::Started,
+                    error: None,
+                },
+            },
+            event(3, "web"),
+        ];
+        let last = last_remediations(&events);
+        assert_eq!(last.len(), 2);
+        assert_eq!(
+            last[&"web".parse::<HealthCheckId>().unwrap()],
+            start + chrono::Duration::minutes(3)
+        );
+        assert_eq!(
+            last[&"sync".parse::<HealthCheckId>().unwrap()],
+            start + chrono::Duration::minutes(1)
+        );
+    }
+}
@@ -0,0 +1,153 @@
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, Utc};
+use patch_db::DbHandle;
+use tracing::instrument;
+
+use crate::context::RpcContext;
+use crate::notifications::NotificationLevel;
+use crate::s9pk::manifest::{Manifest, PackageId};
+use crate::scheduled_task::{ScheduledTaskId, ScheduledTaskResult, ScheduledTaskStatus};
+use crate::Error;
+
+async fn load_statuses<Db: DbHandle>(
+    db: &mut Db,
+    id: &PackageId,
+) -> Result<BTreeMap<ScheduledTaskId, ScheduledTaskStatus>, Error> {
+    Ok(crate::db::DatabaseModel::new()
+        .package_data()
+        .idx_model(id)
+        .and_then(|pde| pde.installed())
+        .get(db)
+        .await?
+        .into_owned()
+        .map(|installed| installed.scheduled_tasks)
+        .unwrap_or_default())
+}
+
+async fn save_statuses<Db: DbHandle>(
+    db: &mut Db,
+    id: &PackageId,
+    statuses: &BTreeMap<ScheduledTaskId, ScheduledTaskStatus>,
+) -> Result<(), Error> {
+    crate::db::DatabaseModel::new()
+        .package_data()
+        .idx_model(id)
+        .and_then(|pde| pde.installed())
+        .expect(db)
+        .await?
+        .scheduled_tasks()
+        .put(db, statuses)
+        .await?;
+    Ok(())
+}
+
+/// Runs the scheduled tasks of the package whenever they come due. This only runs while the service is running,
+/// and runs that were missed while it was stopped are skipped rather than caught up on.
+#[instrument(skip(ctx, manifest))]
+pub async fn run_scheduled_tasks(ctx: &RpcContext, manifest: &Manifest) {
+    let id = &manifest.id;
+    let mut db = ctx.db.handle();
+    let mut statuses = match load_statuses(&mut db, id).await {
+        Ok(a) => a,
+        Err(e) => {
+            tracing::error!("Failed to load scheduled tasks for {}: {}", id, e);
+            tracing::debug!("{:?}", e);
+            BTreeMap::new()
+        }
+    };
+    statuses.retain(|task_id, _| manifest.scheduled_tasks.0.contains_key(task_id));
+    let mut schedule: BTreeMap<ScheduledTaskId, DateTime<Utc>> = BTreeMap::new();
+    let now = Utc::now();
+    for (task_id, task) in &manifest.scheduled_tasks.0 {
+        if let Some(next) = task.schedule.next_after(now) {
+            schedule.insert(task_id.clone(), next);
+        } else {
+            tracing::warn!(
+                "Scheduled task {} of {} never comes due: {}",
+                task_id,
+                id,
+                task.schedule
+            );
+        }
+    }
+    loop {
+        for task_id in manifest.scheduled_tasks.0.keys() {
+            statuses.entry(task_id.clone()).or_default().next_run = schedule.get(task_id).copied();
+        }
+        if let Err(e) = save_statuses(&mut db, id, &statuses).await {
+            tracing::error!("Failed to save scheduled tasks for {}: {}", id, e);
+            tracing::debug!("{:?}", e);
+        }
+
+        let next = match schedule.values().min() {
+            Some(next) => *next,
+            None => return futures::future::pending::<()>().await,
+        };
+        tokio::time::sleep((next - Utc::now()).to_std().unwrap_or_default()).await;
+
+        let now = Utc::now();
+        let due = schedule
+            .iter()
+            .filter(|(_, at)| **at <= now)
+            .map(|(task_id, _)| task_id.clone())
+            .collect::<Vec<_>>();
+        let results = futures::future::join_all(due.iter().map(|task_id| async move {
+            let task = &manifest.scheduled_tasks.0[task_id];
+            tracing::info!("Running scheduled task {} of {}", task_id, id);
+            task.execute(ctx, id, &manifest.version, task_id, &manifest.volumes)
+                .await
+                .unwrap_or_else(|e| {
+                    tracing::debug!("{:?}", e);
+                    ScheduledTaskResult::Failure {
+                        code: e.kind as i32,
+                        error: e.source.to_string(),
+                    }
+                })
+        }))
+        .await;
+
+        for (task_id, result) in due.into_iter().zip(results) {
+            let task = &manifest.scheduled_tasks.0[&task_id];
+            if let ScheduledTaskResult::Failure { code, error } = &result {
+                tracing::error!(
+                    "Scheduled task {} of {} failed with code {}: {}",
+                    task_id,
+                    id,
+                    code,
+                    error
+                );
+                if let Err(e) = ctx
+                    .notification_manager
+                    .notify(
+                        &mut db,
+                        Some(id.clone()),
+                        NotificationLevel::Warning,
+                        String::from("Scheduled Task Failed"),
+                        format!(
+                            "The scheduled task \"{}\" of {} failed with exit code {}.\nDetails: {}",
+                            task.name, id, code, error
+                        ),
+                        (),
+                        Some(3600), // 1 hour
+                    )
+                    .await
+                {
+                    tracing::error!("Failed to issue notification: {}", e);
+                    tracing::debug!("{:?}", e);
+                }
+            }
+            let status = statuses.entry(task_id.clone()).or_default();
+            status.last_run = Some(now);
+            status.result = Some(result);
+            match task.schedule.next_after(Utc::now()) {
+                Some(next) => {
+                    schedule.insert(task_id, next);
+                }
+                None => {
+                    schedule.remove(&task_id);
+                }
+            }
+        }
+    }
+}
@@ -1,9 +1,11 @@
 use std::collections::BTreeMap;
+use std::sync::atomic::Ordering;
 use std::time::Duration;
 
 use chrono::Utc;
 
 use super::{pause, resume, start, stop, ManagerSharedState, Status};
+use crate::dependencies::spawn_dependency_hooks;
 use crate::status::MainStatus;
 use crate::Error;
 
@@ -23,6 +25,7 @@ async fn synchronize_once(shared: &ManagerSharedState) -> Result<Status, Error>
         .get_mut(&mut db)
         .await?;
     let manager_status = *shared.status.1.borrow();
+    let before = (*status).clone();
     match manager_status {
         Status::Stopped => match &mut *status {
             MainStatus::Stopped => (),
@@ -32,26 +35,38 @@ async fn synchronize_once(shared: &ManagerSharedState) -> Result<Status, Error>
             MainStatus::Restarting => {
                 *status = MainStatus::Starting { restarting: true };
             }
+            MainStatus::Starting { .. }
+            | MainStatus::Running { .. }
+            | MainStatus::Paused { .. }
+                if shared.held.load(Ordering::SeqCst) => {}
             MainStatus::Starting { .. } => {
                 start(shared).await?;
             }
-            MainStatus::Running { started, .. } => {
+            MainStatus::Running { started, .. } | MainStatus::Paused { started } => {
                 *started = Utc::now();
                 start(shared).await?;
             }
-            MainStatus::BackingUp { .. } => (),
+            MainStatus::BackingUp { .. } | MainStatus::CrashLooping { .. } => (),
         },
         Status::Starting => match *status {
-            MainStatus::Stopped | MainStatus::Stopping | MainStatus::Restarting => {
+            MainStatus::Stopped
+            | MainStatus::Stopping
+            | MainStatus::Restarting
+            | MainStatus::CrashLooping { .. } => {
                 stop(shared).await?;
             }
-            MainStatus::Starting { .. } | MainStatus::Running { .. } => (),
+            MainStatus::Starting { .. }
+            | MainStatus::Running { .. }
+            | MainStatus::Paused { .. } => (),
             MainStatus::BackingUp { .. } => {
                 pause(shared).await?;
             }
         },
         Status::Running => match *status {
-            MainStatus::Stopped | MainStatus::Stopping | MainStatus::Restarting => {
+            MainStatus::Stopped
+            | MainStatus::Stopping
+            | MainStatus::Restarting
+            | MainStatus::CrashLooping { .. } => {
                 stop(shared).await?;
             }
             MainStatus::Starting { .. } => {
@@ -61,22 +76,29 @@ async fn synchronize_once(shared: &ManagerSharedState) -> Result<Status, Error>
                 };
             }
             MainStatus::Running { .. } => (),
-            MainStatus::BackingUp { .. } => {
+            MainStatus::BackingUp { .. } | MainStatus::Paused { .. } => {
                 pause(shared).await?;
             }
         },
         Status::Paused => match *status {
-            MainStatus::Stopped | MainStatus::Stopping | MainStatus::Restarting => {
+            MainStatus::Stopped
+            | MainStatus::Stopping
+            | MainStatus::Restarting
+            | MainStatus::CrashLooping { .. } => {
                 stop(shared).await?;
             }
             MainStatus::Starting { .. } | MainStatus::Running { .. } => {
                 resume(shared).await?;
             }
-            MainStatus::BackingUp { .. } => (),
+            MainStatus::BackingUp { .. } | MainStatus::Paused { .. } => (),
         },
         Status::Shutdown => (),
     }
+    let event = before.transition(&status);
     status.save(&mut db).await?;
+    if let Some(event) = event {
+        spawn_dependency_hooks(&shared.seed.ctx, &shared.seed.manifest.id, event);
+    }
     Ok(manager_status)
 }
 
@@ -1,3 +1,4 @@
+use std::collections::BTreeMap;
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use std::time::Duration;
@@ -6,18 +7,22 @@ use color_eyre::eyre::eyre;
 use embassy_container_init::{ProcessGroupId, SignalGroup, SignalGroupParams};
 use helpers::UnixRpcClient;
 pub use js_engine::JsError;
-use js_engine::{JsExecutionEnvironment, PathForVolumeId};
+use js_engine::{JsExecutionEnvironment, OsApi, PathForVolumeId};
 use models::{ErrorKind, VolumeId};
+use patch_db::DbHandle;
 use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 use tracing::instrument;
 
 use super::ProcedureName;
+use crate::config::spec::{ConfigPointerReceipts, PackagePointerSpec, ValueSpecPointer};
+use crate::config::ConfigurationError;
 use crate::context::RpcContext;
-use crate::s9pk::manifest::PackageId;
+use crate::properties::fetch_properties;
+use crate::s9pk::manifest::{Manifest, PackageId};
 use crate::util::{GeneralGuard, Version};
 use crate::volume::Volumes;
-use crate::Error;
+use crate::{Error, ResultExt};
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 #[serde(rename_all = "kebab-case")]
@@ -45,6 +50,279 @@ impl PathForVolumeId for Volumes {
     }
 }
 
+/// Host side of the script effects that reach beyond the package's own volumes
+#[derive(Clone)]
+pub struct PackageOsApi {
+    ctx: RpcContext,
+    package_id: PackageId,
+}
+impl PackageOsApi {
+    pub fn new(ctx: RpcContext, package_id: PackageId) -> Self {
+        Self { ctx, package_id }
+    }
+
+    async fn manifest<Db: DbHandle>(&self, db: &mut Db) -> Result<Manifest, Error> {
+        crate::db::DatabaseModel::new()
+            .package_data()
+            .idx_model(&self.package_id)
+            .map(|p| p.manifest())
+            .get(db)
+            .await?
+            .to_owned()
+            .ok_or_else(|| {
+                Error::new(
+                    eyre!("{} is not installed", self.package_id),
+                    ErrorKind::NotFound,
+                )
+            })
+    }
+}
+
+/// Scripts may only look at themselves and the packages they declare as dependencies
+fn may_read(manifest: &Manifest, id: &PackageId) -> bool {
+    &manifest.id == id || manifest.dependencies.0.contains_key(id)
+}
+
+#[async_trait::async_trait]
+impl OsApi for PackageOsApi {
+    async fn store_get(&self, key: &str) -> Result<Option<serde_json::Value>, Error> {
+        let package = self.package_id.as_str();
+        sqlx::query!(
+            "SELECT value FROM package_kv WHERE package = $1 AND key = $2",
+            package,
+            key
+        )
+        .fetch_optional(&self.ctx.secret_store)
+        .await?
+        .map(|row| serde_json::from_str(&row.value).with_kind(ErrorKind::Deserialization))
+        .transpose()
+    }
+
+    async fn store_set(&self, key: &str, value: serde_json::Value) -> Result<(), Error> {
+        let package = self.package_id.as_str();
+        let value = serde_json::to_string(&value).with_kind(ErrorKind::Serialization)?;
+        sqlx::query!(
+            "INSERT INTO package_kv (package, key, value) VALUES ($1, $2, $3) ON CONFLICT (package, key) DO UPDATE SET value = $3",
+            package,
+            key,
+            value
+        )
+        .execute(&self.ctx.secret_store)
+        .await?;
+        Ok(())
+    }
+
+    async fn store_delete(&self, key: &str) -> Result<bool, Error> {
+        let package = self.package_id.as_str();
+        let res = sqlx::query!(
+            "DELETE FROM package_kv WHERE package = $1 AND key = $2",
+            package,
+            key
+        )
+        .execute(&self.ctx.secret_store)
+        .await?;
+        Ok(res.rows_affected() > 0)
+    }
+
+    async fn store_list(&self, prefix: &str) -> Result<BTreeMap<String, serde_json::Value>, Error> {
+        let package = self.package_id.as_str();
+        sqlx::query!(
+            "SELECT key, value FROM package_kv WHERE package = $1 AND starts_with(key, $2)",
+            package,
+            prefix
+        )
+        .fetch_all(&self.ctx.secret_store)
+        .await?
+        .into_iter()
+        .map(|row| {
+            Ok((
+                row.key,
+                serde_json::from_str(&row.value).with_kind(ErrorKind::Deserialization)?,
+            ))
+        })
+        .collect()
+    }
+
+    async fn store_compare_and_swap(
+        &self,
+        key: &str,
+        expected: Option<serde_json::Value>,
+        new: Option<serde_json::Value>,
+    ) -> Result<bool, Error> {
+        let package = self.package_id.as_str();
+        // each case is a single conditional statement, so a concurrent write between the
+        // comparison and the swap makes it affect no rows instead of being overwritten
+        let res = match (expected, new) {
+            (None, None) => return Ok(self.store_get(key).await?.is_none()),
+            (None, Some(new)) => {
+                let new = serde_json::to_string(&new).with_kind(ErrorKind::Serialization)?;
+                sqlx::query!(
+                    "INSERT INTO package_kv (package, key, value) VALUES ($1, $2, $3) ON CONFLICT (package, key) DO NOTHING",
+                    package,
+                    key,
+                    new
+                )
+                .execute(&self.ctx.secret_store)
+                .await?
+            }
+            (Some(expected), Some(new)) => {
+                let expected =
+                    serde_json::to_string(&expected).with_kind(ErrorKind::Serialization)?;
+                let new = serde_json::to_string(&new).with_kind(ErrorKind::Serialization)?;
+                sqlx::query!(
+                    "UPDATE package_kv SET value = $4 WHERE package = $1 AND key = $2 AND value::jsonb = $3::text::jsonb",
+                    package,
+                    key,
+                    expected,
+                    new
+                )
+                .execute(&self.ctx.secret_store)
+                .await?
+            }
+            (Some(expected), None) => {
+                let expected =
+                    serde_json::to_string(&expected).with_kind(ErrorKind::Serialization)?;
+                sqlx::query!(
+                    "DELETE FROM package_kv WHERE package = $1 AND key = $2 AND value::jsonb = $3::text::jsonb",
+                    package,
+                    key,
+                    expected
+                )
+                .execute(&self.ctx.secret_store)
+                .await?
+            }
+        };
+        Ok(res.rows_affected() == 1)
+    }
+
+    async fn read_pointer(&self, pointer: serde_json::Value) -> Result<serde_json::Value, Error> {
+        let pointer: PackagePointerSpec =
+            serde_json::from_value(pointer).with_kind(ErrorKind::Deserialization)?;
+        let mut db = self.ctx.db.handle();
+        let manifest = self.manifest(&mut db).await?;
+        if !may_read(&manifest, pointer.package_id()) {
+            return Err(
+                ConfigurationError::PermissionDenied(ValueSpecPointer::Package(pointer)).into(),
+            );
+        }
+        let receipts = ConfigPointerReceipts::new(&mut db).await?;
+        Ok(pointer
+            .deref(&self.ctx, &mut db, &manifest, &BTreeMap::new(), &receipts)
+            .await?)
+    }
+
+    async fn dependency_properties(
+        &self,
+        package_id: &PackageId,
+    ) -> Result<serde_json::Value, Error> {
+        let manifest = self.manifest(&mut self.ctx.db.handle()).await?;
+        if !may_read(&manifest, package_id) {
+            return Err(Error::new(
+                eyre!("{} is not a dependency of {}", package_id, self.package_id),
+                ErrorKind::Authorization,
+            ));
+        }
+        fetch_properties(self.ctx.clone(), package_id.clone()).await
+    }
+}
+
+/// Volumes mapped straight to directories on the local machine, for running scripts outside of a server
+pub struct LocalVolumes(BTreeMap<VolumeId, (PathBuf, bool)>);
+impl LocalVolumes {
+    pub fn new() -> Self {
+        LocalVolumes(BTreeMap::new())
+    }
+    pub fn mount(&mut self, volume_id: VolumeId, path: PathBuf, readonly: bool) {
+        self.0.insert(volume_id, (path, readonly));
+    }
+}
+impl PathForVolumeId for LocalVolumes {
+    fn path_for(
+        &self,
+        _data_dir: &Path,
+        _package_id: &PackageId,
+        _version: &Version,
+        volume_id: &VolumeId,
+    ) -> Option<PathBuf> {
+        self.0.get(volume_id).map(|(path, _)| path.clone())
+    }
+
+    fn readonly(&self, volume_id: &VolumeId) -> bool {
+        self.0
+            .get(volume_id)
+            .map(|(_, readonly)| *readonly)
+            .unwrap_or(false)
+    }
+}
+
+/// An in-memory store for running scripts outside of a server, with no other packages to read from
+#[derive(Default)]
+pub struct LocalOsApi {
+    store: std::sync::Mutex<BTreeMap<String, serde_json::Value>>,
+}
+
+#[async_trait::async_trait]
+impl OsApi for LocalOsApi {
+    async fn store_get(&self, key: &str) -> Result<Option<serde_json::Value>, Error> {
+        Ok(self.store.lock().unwrap().get(key).cloned())
+    }
+
+    async fn store_set(&self, key: &str, value: serde_json::Value) -> Result<(), Error> {
+        self.store.lock().unwrap().insert(key.to_owned(), value);
+        Ok(())
+    }
+
+    async fn store_delete(&self, key: &str) -> Result<bool, Error> {
+        Ok(self.store.lock().unwrap().remove(key).is_some())
+    }
+
+    async fn store_list(&self, prefix: &str) -> Result<BTreeMap<String, serde_json::Value>, Error> {
+        Ok(self
+            .store
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(k, _)| k.starts_with(prefix))
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect())
+    }
+
+    async fn store_compare_and_swap(
+        &self,
+        key: &str,
+        expected: Option<serde_json::Value>,
+        new: Option<serde_json::Value>,
+    ) -> Result<bool, Error> {
+        let mut store = self.store.lock().unwrap();
+        if store.get(key) != expected.as_ref() {
+            return Ok(false);
+        }
+        if let Some(new) = new {
+            store.insert(key.to_owned(), new);
+        } else {
+            store.remove(key);
+        }
+        Ok(true)
+    }
+
+    async fn read_pointer(&self, _pointer: serde_json::Value) -> Result<serde_json::Value, Error> {
+        Err(Error::new(
+            eyre!("Other packages are not available when running locally"),
+            ErrorKind::NotFound,
+        ))
+    }
+
+    async fn dependency_properties(
+        &self,
+        _package_id: &PackageId,
+    ) -> Result<serde_json::Value, Error> {
+        Err(Error::new(
+            eyre!("Other packages are not available when running locally"),
+            ErrorKind::NotFound,
+        ))
+    }
+}
+
 #[derive(Clone, Debug, Default, Deserialize, Serialize)]
 #[serde(rename_all = "kebab-case")]
 pub struct JsProcedure {
@@ -57,7 +335,7 @@ impl JsProcedure {
         Ok(())
     }
 
-    #[instrument(skip(directory, input, rpc_client))]
+    #[instrument(skip(directory, input, rpc_client, os))]
     pub async fn execute<I: Serialize, O: DeserializeOwned>(
         &self,
         directory: &PathBuf,
@@ -69,6 +347,7 @@ impl JsProcedure {
         timeout: Option<Duration>,
         gid: ProcessGroupId,
         rpc_client: Option<Arc<UnixRpcClient>>,
+        os: Option<Arc<dyn OsApi>>,
     ) -> Result<Result<O, (i32, String)>, Error> {
         let cleaner_client = rpc_client.clone();
         let cleaner = GeneralGuard::new(move || {
@@ -86,7 +365,7 @@ impl JsProcedure {
             })
         });
         let res = async move {
-            let running_action = JsExecutionEnvironment::load_from_package(
+            let mut environment = JsExecutionEnvironment::load_from_package(
                 directory,
                 pkg_id,
                 pkg_version,
@@ -94,8 +373,11 @@ impl JsProcedure {
                 gid,
                 rpc_client,
             )
-            .await?
-            .run_action(name, input, self.args.clone());
+            .await?;
+            if let Some(os) = os {
+                environment = environment.with_os_api(os);
+            }
+            let running_action = environment.run_action(name, input, self.args.clone());
             let output: Option<ErrorValue> = match timeout {
                 Some(timeout_duration) => tokio::time::timeout(timeout_duration, running_action)
                     .await
@@ -111,6 +393,28 @@ impl JsProcedure {
         Ok(res)
     }
 
+    /// Runs against an environment the caller has already set up, eg. one loaded from a package's source tree
+    pub async fn run<I: Serialize, O: DeserializeOwned>(
+        &self,
+        environment: JsExecutionEnvironment,
+        name: ProcedureName,
+        input: Option<I>,
+        timeout: Option<Duration>,
+    ) -> Result<O, (i32, String)> {
+        async move {
+            let running_action = environment.run_action(name, input, self.args.clone());
+            let output: Option<ErrorValue> = match timeout {
+                Some(timeout_duration) => tokio::time::timeout(timeout_duration, running_action)
+                    .await
+                    .map_err(|_| (JsError::Timeout, "Timed out. Retrying soon...".to_owned()))??,
+                None => running_action.await?,
+            };
+            unwrap_known_error(output)
+        }
+        .await
+        .map_err(|(error, message)| (error.as_code_num(), message))
+    }
+
     #[instrument(skip(ctx, input))]
     pub async fn sandboxed<I: Serialize, O: DeserializeOwned>(
         &self,
@@ -133,6 +437,7 @@ impl JsProcedure {
             )
             .await?
             .read_only_effects()
+            .with_os_api(Arc::new(PackageOsApi::new(ctx.clone(), pkg_id.clone())))
             .run_action(name, input, self.args.clone());
             let output: Option<ErrorValue> = match timeout {
                 Some(timeout_duration) => tokio::time::timeout(timeout_duration, running_action)
@@ -212,6 +517,7 @@ async fn js_action_execute() {
             timeout,
             ProcessGroupId(0),
             None,
+            None,
         )
         .await
         .unwrap()
@@ -269,6 +575,7 @@ async fn js_action_execute_error() {
             timeout,
             ProcessGroupId(0),
             None,
+            None,
         )
         .await
         .unwrap();
@@ -315,6 +622,7 @@ async fn js_action_fetch() {
             timeout,
             ProcessGroupId(0),
             None,
+            None,
         )
         .await
         .unwrap()
@@ -412,6 +720,7 @@ async fn js_action_var_arg() {
             timeout,
             ProcessGroupId(0),
             None,
+            None,
         )
         .await
         .unwrap()
@@ -458,6 +767,7 @@ async fn js_action_test_rename() {
             timeout,
             ProcessGroupId(0),
             None,
+            None,
         )
         .await
         .unwrap()
@@ -504,6 +814,7 @@ async fn js_action_test_deep_dir() {
             timeout,
             ProcessGroupId(0),
             None,
+            None,
         )
         .await
         .unwrap()
@@ -549,6 +860,7 @@ async fn js_action_test_deep_dir_escape() {
             timeout,
             ProcessGroupId(0),
             None,
+            None,
         )
         .await
         .unwrap()
@@ -594,6 +906,7 @@ async fn js_action_test_zero_dir() {
             timeout,
             ProcessGroupId(0),
             None,
+            None,
         )
         .await
         .unwrap()
@@ -639,6 +952,7 @@ async fn js_action_test_read_dir() {
             timeout,
             ProcessGroupId(0),
             None,
+            None,
         )
         .await
         .unwrap()
@@ -685,8 +999,151 @@ async fn js_rsync() {
             timeout,
             ProcessGroupId(0),
             None,
+            None,
+        )
+        .await
+        .unwrap()
+        .unwrap();
+}
+
+#[tokio::test]
+async fn js_action_test_crypto() {
+    let js_action = JsProcedure { args: vec![] };
+    let path: PathBuf = "test/js_action_execute/"
+        .parse::<PathBuf>()
+        .unwrap()
+        .canonicalize()
+        .unwrap();
+    let package_id = "test-package".parse().unwrap();
+    let package_version: Version = "0.3.0.3".parse().unwrap();
+    let name = ProcedureName::Action("test-crypto".parse().unwrap());
+    let volumes: Volumes = serde_json::from_value(serde_json::json!({
+        "main": {
+            "type": "data"
+        },
+        "compat": {
+            "type": "assets"
+        },
+        "filebrowser" :{
+            "package-id": "filebrowser",
+            "path": "data",
+            "readonly": true,
+            "type": "pointer",
+            "volume-id": "main",
+        }
+    }))
+    .unwrap();
+    let input: Option<serde_json::Value> = None;
+    let timeout = Some(Duration::from_secs(10));
+    js_action
+        .execute::<serde_json::Value, serde_json::Value>(
+            &path,
+            &package_id,
+            &package_version,
+            name,
+            &volumes,
+            input,
+            timeout,
+            ProcessGroupId(0),
+            None,
+            None,
         )
         .await
         .unwrap()
         .unwrap();
 }
+
+#[tokio::test]
+async fn js_action_fetch_denied_locally() {
+    let js_action = JsProcedure { args: vec![] };
+    let path: PathBuf = "test/js_action_execute/"
+        .parse::<PathBuf>()
+        .unwrap()
+        .canonicalize()
+        .unwrap();
+    let package_id = "test-package".parse().unwrap();
+    let package_version: Version = "0.3.0.3".parse().unwrap();
+    let name = ProcedureName::Action("fetch".parse().unwrap());
+    let mut volumes = LocalVolumes::new();
+    volumes.mount(
+        "main".parse().unwrap(),
+        path.join("package-data/volumes/test-package/data/main"),
+        false,
+    );
+    let environment = JsExecutionEnvironment::load_from_script(
+        path.join("package-data/scripts/test-package/0.3.0.3/embassy.js"),
+        &path,
+        &package_id,
+        &package_version,
+        Box::new(volumes),
+        ProcessGroupId(0),
+        None,
+    )
+    .await
+    .unwrap()
+    .with_os_api(Arc::new(LocalOsApi::default()))
+    .deny_fetch();
+    let input: Option<serde_json::Value> = None;
+    let timeout = Some(Duration::from_secs(10));
+    let (_, message) = js_action
+        .run::<serde_json::Value, serde_json::Value>(environment, name, input, timeout)
+        .await
+        .unwrap_err();
+    assert!(
+        message.contains("Fetch is not allowed in this environment"),
+        "{}",
+        message
+    );
+}
+
+#[tokio::test]
+async fn local_store() {
+    use serde_json::json;
+
+    let store = LocalOsApi::default();
+    assert_eq!(store.store_get("a").await.unwrap(), None);
+    assert!(!store.store_delete("a").await.unwrap());
+    store.store_set("a", json!(1)).await.unwrap();
+    store.store_set("a/b", json!({ "c": true })).await.unwrap();
+    assert_eq!(store.store_get("a").await.unwrap(), Some(json!(1)));
+    assert_eq!(store.store_list("a/").await.unwrap().len(), 1);
+    assert!(store.store_delete("a").await.unwrap());
+    assert_eq!(store.store_get("a").await.unwrap(), None);
+
+    // absent key
+    assert!(!store
+        .store_compare_and_swap("x", Some(json!(1)), Some(json!(2)))
+        .await
+        .unwrap());
+    assert!(!store
+        .store_compare_and_swap("x", Some(json!(1)), None)
+        .await
+        .unwrap());
+    assert!(store.store_compare_and_swap("x", None, None).await.unwrap());
+    assert!(store
+        .store_compare_and_swap("x", None, Some(json!(1)))
+        .await
+        .unwrap());
+    assert_eq!(store.store_get("x").await.unwrap(), Some(json!(1)));
+
+    // present key
+    assert!(!store
+        .store_compare_and_swap("x", None, Some(json!(3)))
+        .await
+        .unwrap());
+    assert!(!store
+        .store_compare_and_swap("x", Some(json!(2)), Some(json!(3)))
+        .await
+        .unwrap());
+    assert!(!store.store_compare_and_swap("x", None, None).await.unwrap());
+    assert!(store
+        .store_compare_and_swap("x", Some(json!(1)), Some(json!(2)))
+        .await
+        .unwrap());
+    assert_eq!(store.store_get("x").await.unwrap(), Some(json!(2)));
+    assert!(store
+        .store_compare_and_swap("x", Some(json!(2)), None)
+        .await
+        .unwrap());
+    assert_eq!(store.store_get("x").await.unwrap(), None);
+}
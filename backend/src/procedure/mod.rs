@@ -115,6 +115,10 @@ impl PackageProcedure {
                         timeout,
                         gid,
                         rpc_client,
+                        Some(std::sync::Arc::new(js_scripts::PackageOsApi::new(
+                            ctx.clone(),
+                            pkg_id.clone(),
+                        ))),
                     )
                     .await
             }
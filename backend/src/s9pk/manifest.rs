@@ -1,3 +1,4 @@
+use std::collections::{BTreeMap, BTreeSet};
 use std::path::{Path, PathBuf};
 
 use color_eyre::eyre::eyre;
@@ -10,11 +11,13 @@ use super::git_hash::GitHash;
 use crate::action::Actions;
 use crate::backup::BackupActions;
 use crate::config::action::ConfigActions;
+use crate::config::migration::ConfigMigration;
 use crate::dependencies::Dependencies;
 use crate::migration::Migrations;
 use crate::net::interface::Interfaces;
 use crate::procedure::docker::DockerContainers;
 use crate::procedure::PackageProcedure;
+use crate::scheduled_task::ScheduledTasks;
 use crate::status::health_check::HealthChecks;
 use crate::util::Version;
 use crate::version::{Current, VersionT};
@@ -69,11 +72,19 @@ pub struct Manifest {
     pub migrations: Migrations,
     #[serde(default)]
     pub actions: Actions,
+    #[serde(default)]
+    pub scheduled_tasks: ScheduledTasks,
     // #[serde(default)]
     // pub permissions: Permissions,
     #[serde(default)]
     #[model]
     pub dependencies: Dependencies,
+    /// Virtual packages this package stands in for (ie. any bitcoin full node), with the version of each it provides
+    #[serde(default)]
+    pub provides: BTreeMap<PackageId, Version>,
+    /// Packages, or virtual packages, that cannot be installed alongside this one
+    #[serde(default)]
+    pub conflicts: BTreeSet<PackageId>,
     #[model]
     pub containers: Option<DockerContainers>,
 
@@ -87,6 +98,14 @@ impl Manifest {
         let main = once(&self.main);
         let cfg_get = self.config.as_ref().map(|a| &a.get).into_iter();
         let cfg_set = self.config.as_ref().map(|a| &a.set).into_iter();
+        let cfg_migrations = self
+            .config
+            .iter()
+            .flat_map(|a| a.migrations.0.values())
+            .filter_map(|m| match m {
+                ConfigMigration::Procedure(procedure) => Some(procedure),
+                ConfigMigration::Rules(_) => None,
+            });
         let props = self.properties.iter();
         let backups = vec![&self.backup.create, &self.backup.restore].into_iter();
         let migrations = self
@@ -95,12 +114,21 @@ impl Manifest {
             .values()
             .chain(self.migrations.from.values());
         let actions = self.actions.0.values().map(|a| &a.implementation);
+        let scheduled_tasks = self.scheduled_tasks.0.values().map(|t| &t.implementation);
+        let dependency_hooks = self
+            .dependencies
+            .0
+            .values()
+            .flat_map(|d| d.hooks.iter().map(|(_, hook)| hook));
         main.chain(cfg_get)
             .chain(cfg_set)
+            .chain(cfg_migrations)
             .chain(props)
             .chain(backups)
             .chain(migrations)
             .chain(actions)
+            .chain(scheduled_tasks)
+            .chain(dependency_hooks)
     }
 
     pub fn with_git_hash(mut self, git_hash: GitHash) -> Self {
@@ -203,3 +231,33 @@ pub struct Alerts {
     pub start: Option<String>,
     pub stop: Option<String>,
 }
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+
+    /// A manifest for tests that only care about its id, version, and the given `fields`
+    pub fn manifest(id: &str, version: &str, fields: serde_json::Value) -> Manifest {
+        let procedure =
+            serde_json::json!({ "type": "docker", "image": "main", "entrypoint": "main" });
+        let mut manifest = serde_json::json!({
+            "id": id,
+            "title": id,
+            "version": version,
+            "description": { "short": "", "long": "" },
+            "release-notes": "",
+            "license": "mit",
+            "wrapper-repo": "https://example.com",
+            "upstream-repo": "https://example.com",
+            "main": procedure,
+            "health-checks": {},
+            "volumes": {},
+            "interfaces": {},
+            "backup": { "create": procedure, "restore": procedure },
+        });
+        if let serde_json::Value::Object(fields) = fields {
+            manifest.as_object_mut().unwrap().extend(fields);
+        }
+        serde_json::from_value(manifest).unwrap()
+    }
+}
@@ -1,5 +1,5 @@
 use std::ffi::OsStr;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use color_eyre::eyre::eyre;
 use futures::TryStreamExt;
@@ -40,24 +40,7 @@ pub async fn pack(#[context] ctx: SdkContext, #[arg] path: Option<PathBuf>) -> R
     } else {
         std::env::current_dir()?
     };
-    let manifest_value: Value = if path.join("manifest.toml").exists() {
-        IoFormat::Toml
-            .from_async_reader(File::open(path.join("manifest.toml")).await?)
-            .await?
-    } else if path.join("manifest.yaml").exists() {
-        IoFormat::Yaml
-            .from_async_reader(File::open(path.join("manifest.yaml")).await?)
-            .await?
-    } else if path.join("manifest.json").exists() {
-        IoFormat::Json
-            .from_async_reader(File::open(path.join("manifest.json")).await?)
-            .await?
-    } else {
-        return Err(Error::new(
-            eyre!("manifest not found"),
-            crate::ErrorKind::Pack,
-        ));
-    };
+    let manifest_value = read_manifest_value(&path).await?;
 
     let manifest: Manifest = serde_json::from_value::<Manifest>(manifest_value.clone())
         .with_kind(crate::ErrorKind::Deserialization)?
@@ -195,6 +178,30 @@ pub async fn pack(#[context] ctx: SdkContext, #[arg] path: Option<PathBuf>) -> R
     Ok(())
 }
 
+/// Reads the manifest from a package's source directory, in whichever format it was written
+pub async fn read_manifest_value(path: &Path) -> Result<Value, Error> {
+    use tokio::fs::File;
+
+    if path.join("manifest.toml").exists() {
+        IoFormat::Toml
+            .from_async_reader(File::open(path.join("manifest.toml")).await?)
+            .await
+    } else if path.join("manifest.yaml").exists() {
+        IoFormat::Yaml
+            .from_async_reader(File::open(path.join("manifest.yaml")).await?)
+            .await
+    } else if path.join("manifest.json").exists() {
+        IoFormat::Json
+            .from_async_reader(File::open(path.join("manifest.json")).await?)
+            .await
+    } else {
+        Err(Error::new(
+            eyre!("manifest not found"),
+            crate::ErrorKind::Pack,
+        ))
+    }
+}
+
 #[command(rename = "s9pk", cli_only, display(display_none))]
 pub async fn verify(#[arg] path: PathBuf) -> Result<(), Error> {
     let mut s9pk = S9pkReader::open(path, true).await?;
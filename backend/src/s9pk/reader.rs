@@ -216,6 +216,14 @@ impl<R: AsyncRead + AsyncSeek + Unpin + Send + Sync> S9pkReader<R> {
             &man.eos_version,
             &man.volumes,
             &validated_image_ids,
+            &man.interfaces,
+            &man.actions,
+        )?;
+        man.scheduled_tasks.validate(
+            containers,
+            &man.eos_version,
+            &man.volumes,
+            &validated_image_ids,
         )?;
         man.interfaces.validate()?;
         man.main
@@ -233,6 +241,12 @@ impl<R: AsyncRead + AsyncSeek + Unpin + Send + Sync> S9pkReader<R> {
             &man.volumes,
             &validated_image_ids,
         )?;
+        man.dependencies.validate(
+            containers,
+            &man.eos_version,
+            &man.volumes,
+            &validated_image_ids,
+        )?;
 
         #[cfg(feature = "js_engine")]
         if man.containers.is_some()
@@ -256,6 +270,18 @@ impl<R: AsyncRead + AsyncSeek + Unpin + Send + Sync> S9pkReader<R> {
                 crate::ErrorKind::ValidateS9pk,
             ));
         }
+        if man.provides.contains_key(&man.id) {
+            return Err(Error::new(
+                eyre!("A package cannot provide itself"),
+                crate::ErrorKind::ValidateS9pk,
+            ));
+        }
+        if man.conflicts.contains(&man.id) {
+            return Err(Error::new(
+                eyre!("A package cannot conflict with itself"),
+                crate::ErrorKind::ValidateS9pk,
+            ));
+        }
         if man.title.len() >= MAX_TITLE_LEN {
             return Err(Error::new(
                 eyre!("Cannot have more than a length of {MAX_TITLE_LEN} for title"),
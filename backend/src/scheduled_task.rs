@@ -0,0 +1,318 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::str::FromStr;
+
+use chrono::{DateTime, Datelike, NaiveDate, NaiveDateTime, Timelike, Utc};
+use color_eyre::eyre::eyre;
+pub use models::ScheduledTaskId;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use tracing::instrument;
+
+use crate::context::RpcContext;
+use crate::id::ImageId;
+use crate::procedure::docker::DockerContainers;
+use crate::procedure::{NoOutput, PackageProcedure, ProcedureName};
+use crate::s9pk::manifest::PackageId;
+use crate::util::serde::Duration;
+use crate::util::Version;
+use crate::volume::Volumes;
+use crate::{Error, ResultExt};
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct ScheduledTasks(pub BTreeMap<ScheduledTaskId, ScheduledTask>);
+impl ScheduledTasks {
+    #[instrument]
+    pub fn validate(
+        &self,
+        container: &Option<DockerContainers>,
+        eos_version: &Version,
+        volumes: &Volumes,
+        image_ids: &BTreeSet<ImageId>,
+    ) -> Result<(), Error> {
+        for (_, task) in &self.0 {
+            task.implementation
+                .validate(container, eos_version, volumes, image_ids, true)
+                .with_ctx(|_| {
+                    (
+                        crate::ErrorKind::ValidateS9pk,
+                        format!("Scheduled Task {}", task.name),
+                    )
+                })?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ScheduledTask {
+    pub name: String,
+    #[serde(default)]
+    pub description: Option<String>,
+    pub schedule: Cron,
+    pub implementation: PackageProcedure,
+    pub timeout: Option<Duration>,
+}
+impl ScheduledTask {
+    #[instrument(skip(ctx))]
+    pub async fn execute(
+        &self,
+        ctx: &RpcContext,
+        pkg_id: &PackageId,
+        pkg_version: &Version,
+        task_id: &ScheduledTaskId,
+        volumes: &Volumes,
+    ) -> Result<ScheduledTaskResult, Error> {
+        let res = self
+            .implementation
+            .execute::<(), NoOutput>(
+                ctx,
+                pkg_id,
+                pkg_version,
+                ProcedureName::ScheduledTask(task_id.clone()),
+                volumes,
+                None,
+                self.timeout.map(|d| *d),
+            )
+            .await?;
+        Ok(match res {
+            Ok(NoOutput) => ScheduledTaskResult::Success,
+            Err((code, error)) => ScheduledTaskResult::Failure { code, error },
+        })
+    }
+}
+
+/// What we know about a scheduled task of an installed package
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ScheduledTaskStatus {
+    pub last_run: Option<DateTime<Utc>>,
+    pub next_run: Option<DateTime<Utc>>,
+    pub result: Option<ScheduledTaskResult>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+#[serde(tag = "result")]
+pub enum ScheduledTaskResult {
+    Success,
+    Failure { code: i32, error: String },
+}
+
+/// A standard 5 field cron expression (minute, hour, day of month, month, day of week), evaluated in UTC.
+///
+/// Fields accept `*`, numbers, ranges (`1-5`), lists (`1,15`) and steps (`*/10`, `0-30/5`).
+/// Months and days of the week may also be given by their three letter names, and both 0 and 7 mean Sunday.
+/// The `@yearly`, `@monthly`, `@weekly`, `@daily` and `@hourly` shorthands are supported as well.
+/// As in Vixie cron, if both the day of month and the day of week are restricted, a day matching either one fires.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Cron {
+    expr: String,
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    any_day: bool,
+    any_weekday: bool,
+}
+impl Cron {
+    /// The first time strictly after `after` (rounded down to the minute) that matches the expression.
+    /// Returns `None` if nothing matches in the next few years, e.g. for `0 0 30 2 *`.
+    pub fn next_after(&self, after: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let mut t =
+            after.naive_utc().with_second(0)?.with_nanosecond(0)? + chrono::Duration::minutes(1);
+        let limit = t + chrono::Duration::days(366 * 5);
+        while t < limit {
+            if !bit(self.months, t.month()) {
+                let (year, month) = if t.month() == 12 {
+                    (t.year() + 1, 1)
+                } else {
+                    (t.year(), t.month() + 1)
+                };
+                t = NaiveDate::from_ymd_opt(year, month, 1)?.and_hms_opt(0, 0, 0)?;
+            } else if !self.day_matches(&t) {
+                t = t.date().succ_opt()?.and_hms_opt(0, 0, 0)?;
+            } else if !bit(self.hours, t.hour()) {
+                t = t.date().and_hms_opt(t.hour(), 0, 0)? + chrono::Duration::hours(1);
+            } else if !bit(self.minutes, t.minute()) {
+                t += chrono::Duration::minutes(1);
+            } else {
+                return Some(DateTime::from_utc(t, Utc));
+            }
+        }
+        None
+    }
+
+    fn day_matches(&self, t: &NaiveDateTime) -> bool {
+        let day = bit(self.days, t.day());
+        let weekday = bit(self.weekdays, t.weekday().num_days_from_sunday());
+        match (self.any_day, self.any_weekday) {
+            (false, false) => day || weekday,
+            _ => day && weekday,
+        }
+    }
+}
+impl FromStr for Cron {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let expanded = match s.trim() {
+            "@yearly" | "@annually" => "0 0 1 1 *",
+            "@monthly" => "0 0 1 * *",
+            "@weekly" => "0 0 * * 0",
+            "@daily" | "@midnight" => "0 0 * * *",
+            "@hourly" => "0 * * * *",
+            a => a,
+        };
+        let fields = expanded.split_whitespace().collect::<Vec<_>>();
+        if fields.len() != 5 {
+            return Err(Error::new(
+                eyre!("Cron expression must have 5 fields: {}", s),
+                crate::ErrorKind::ParseTimestamp,
+            ));
+        }
+        const MONTHS: &[&str] = &[
+            "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+        ];
+        const WEEKDAYS: &[&str] = &["sun", "mon", "tue", "wed", "thu", "fri", "sat"];
+        let mut weekdays = parse_field(fields[4], 0, 7, WEEKDAYS)?;
+        if bit(weekdays, 7) {
+            weekdays |= 1;
+        }
+        Ok(Cron {
+            expr: s.to_owned(),
+            minutes: parse_field(fields[0], 0, 59, &[])?,
+            hours: parse_field(fields[1], 0, 23, &[])?,
+            days: parse_field(fields[2], 1, 31, &[])?,
+            months: parse_field(fields[3], 1, 12, MONTHS)?,
+            weekdays,
+            any_day: fields[2].starts_with('*'),
+            any_weekday: fields[4].starts_with('*'),
+        })
+    }
+}
+impl std::fmt::Display for Cron {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.expr)
+    }
+}
+impl Serialize for Cron {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.expr)
+    }
+}
+impl<'de> Deserialize<'de> for Cron {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+fn bit(mask: u64, n: u32) -> bool {
+    mask & (1 << n) != 0
+}
+
+/// Parses one cron field into a bitmask. `names` are the names of the values starting at `min`
+fn parse_field(field: &str, min: u32, max: u32, names: &[&str]) -> Result<u64, Error> {
+    let invalid = |reason: &str, value: &str| {
+        Error::new(
+            eyre!("{} in cron field {}: {}", reason, field, value),
+            crate::ErrorKind::ParseTimestamp,
+        )
+    };
+    let value = |s: &str| -> Result<u32, Error> {
+        let n = if let Some(idx) = names.iter().position(|n| n.eq_ignore_ascii_case(s)) {
+            idx as u32 + min
+        } else {
+            s.parse().map_err(|_| invalid("Invalid value", s))?
+        };
+        if n < min || n > max {
+            return Err(invalid("Value out of range", s));
+        }
+        Ok(n)
+    };
+    let mut mask = 0;
+    for item in field.split(',') {
+        let (range, step) = match item.split_once('/') {
+            Some((range, step)) => (
+                range,
+                step.parse::<u32>()
+                    .ok()
+                    .filter(|s| *s > 0)
+                    .ok_or_else(|| invalid("Invalid step", step))?,
+            ),
+            None => (item, 1),
+        };
+        let (start, end) = if range == "*" {
+            (min, max)
+        } else if let Some((start, end)) = range.split_once('-') {
+            (value(start)?, value(end)?)
+        } else if item.contains('/') {
+            (value(range)?, max)
+        } else {
+            let n = value(range)?;
+            (n, n)
+        };
+        if start > end {
+            return Err(invalid("Invalid range", range));
+        }
+        for n in (start..=end).step_by(step as usize) {
+            mask |= 1 << n;
+        }
+    }
+    Ok(mask)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn next(expr: &str, after: &str) -> Option<String> {
+        let cron: Cron = expr.parse().unwrap();
+        cron.next_after(
+            DateTime::parse_from_rfc3339(after)
+                .unwrap()
+                .with_timezone(&Utc),
+        )
+        .map(|t| t.to_rfc3339())
+    }
+
+    #[test]
+    fn cron_next_after() {
+        assert_eq!(
+            next("*/15 * * * *", "2023-03-01T10:07:30Z").as_deref(),
+            Some("2023-03-01T10:15:00+00:00")
+        );
+        assert_eq!(
+            next("@daily", "2023-12-31T23:59:00Z").as_deref(),
+            Some("2024-01-01T00:00:00+00:00")
+        );
+        assert_eq!(
+            next("30 4 * * mon-fri", "2023-03-03T05:00:00Z").as_deref(),
+            Some("2023-03-06T04:30:00+00:00")
+        );
+        assert_eq!(
+            next("0 0 29 feb *", "2023-01-01T00:00:00Z").as_deref(),
+            Some("2024-02-29T00:00:00+00:00")
+        );
+        // day of month or day of week
+        assert_eq!(
+            next("0 12 15 * 7", "2023-03-06T00:00:00Z").as_deref(),
+            Some("2023-03-12T12:00:00+00:00")
+        );
+        assert_eq!(next("0 0 30 2 *", "2023-01-01T00:00:00Z"), None);
+    }
+
+    #[test]
+    fn cron_invalid() {
+        for expr in [
+            "* * * *",
+            "60 * * * *",
+            "* * 0 * *",
+            "*/0 * * * *",
+            "5-1 * * * *",
+            "@often",
+        ] {
+            assert!(expr.parse::<Cron>().is_err(), "{}", expr);
+        }
+    }
+}
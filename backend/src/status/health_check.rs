@@ -1,15 +1,19 @@
-use std::collections::{BTreeMap, BTreeSet};
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
 
 use chrono::{DateTime, Utc};
+use color_eyre::eyre::eyre;
 pub use models::HealthCheckId;
 use serde::{Deserialize, Serialize};
 use tracing::instrument;
 
+use crate::action::{ActionId, Actions, DockerStatus};
 use crate::context::RpcContext;
 use crate::id::ImageId;
+use crate::net::interface::Interfaces;
 use crate::procedure::docker::DockerContainers;
 use crate::procedure::{NoOutput, PackageProcedure, ProcedureName};
 use crate::s9pk::manifest::PackageId;
+use crate::status::native_health_check::NativeHealthCheck;
 use crate::util::serde::Duration;
 use crate::util::Version;
 use crate::volume::Volumes;
@@ -25,21 +29,52 @@ impl HealthChecks {
         eos_version: &Version,
         volumes: &Volumes,
         image_ids: &BTreeSet<ImageId>,
+        interfaces: &Interfaces,
+        actions: &Actions,
     ) -> Result<(), Error> {
         for (_, check) in &self.0 {
-            check
-                .implementation
-                .validate(container, eos_version, &volumes, image_ids, false)
-                .with_ctx(|_| {
-                    (
-                        crate::ErrorKind::ValidateS9pk,
-                        format!("Health Check {}", check.name),
-                    )
-                })?;
+            if let Some(Remediation {
+                kind: RemediationKind::Action { action },
+                ..
+            }) = &check.remediation
+            {
+                match actions.0.get(action) {
+                    Some(a) if a.allowed_statuses.contains(&DockerStatus::Running) => (),
+                    Some(_) => {
+                        return Err(Error::new(
+                            eyre!(
+                                "Health Check {}: action {} is not allowed while running",
+                                check.name,
+                                action
+                            ),
+                            crate::ErrorKind::ValidateS9pk,
+                        ))
+                    }
+                    None => {
+                        return Err(Error::new(
+                            eyre!("Health Check {}: unknown action {}", check.name, action),
+                            crate::ErrorKind::ValidateS9pk,
+                        ))
+                    }
+                }
+            }
+            match &check.implementation {
+                HealthCheckImplementation::Procedure(procedure) => {
+                    procedure.validate(container, eos_version, &volumes, image_ids, false)
+                }
+                HealthCheckImplementation::Native(native) => native.validate(interfaces, volumes),
+            }
+            .with_ctx(|_| {
+                (
+                    crate::ErrorKind::ValidateS9pk,
+                    format!("Health Check {}", check.name),
+                )
+            })?;
         }
         Ok(())
     }
-    pub async fn check_all(
+    /// Runs the checks in `ids`
+    pub async fn check_some(
         &self,
         ctx: &RpcContext,
         container: &Option<DockerContainers>,
@@ -47,16 +82,20 @@ impl HealthChecks {
         pkg_id: &PackageId,
         pkg_version: &Version,
         volumes: &Volumes,
+        ids: &BTreeSet<HealthCheckId>,
     ) -> Result<BTreeMap<HealthCheckId, HealthCheckResult>, Error> {
-        let res = futures::future::try_join_all(self.0.iter().map(|(id, check)| async move {
-            Ok::<_, Error>((
-                id.clone(),
-                check
-                    .check(ctx, container, id, started, pkg_id, pkg_version, volumes)
-                    .await?,
+        let res =
+            futures::future::try_join_all(self.0.iter().filter(|(id, _)| ids.contains(*id)).map(
+                |(id, check)| async move {
+                    Ok::<_, Error>((
+                        id.clone(),
+                        check
+                            .check(ctx, container, id, started, pkg_id, pkg_version, volumes)
+                            .await?,
+                    ))
+                },
             ))
-        }))
-        .await?;
+            .await?;
         Ok(res.into_iter().collect())
     }
 }
@@ -67,10 +106,77 @@ pub struct HealthCheck {
     pub name: String,
     pub success_message: Option<String>,
     #[serde(flatten)]
-    implementation: PackageProcedure,
+    implementation: HealthCheckImplementation,
     pub timeout: Option<Duration>,
+    /// How often to run the check
+    #[serde(default)]
+    pub interval: Option<Duration>,
+    /// Failures within this long of the service starting are reported as still starting, and do not count towards the threshold
+    #[serde(default)]
+    pub start_period: Option<Duration>,
+    /// How many failures in a row it takes before the check is reported as failing
+    #[serde(default = "default_failure_threshold")]
+    pub failure_threshold: u32,
+    /// What to do when the check keeps failing
+    #[serde(default)]
+    pub remediation: Option<Remediation>,
+}
+/// Either a procedure run in the service, or a check embassyd evaluates itself
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum HealthCheckImplementation {
+    Native(NativeHealthCheck),
+    Procedure(PackageProcedure),
+}
+
+fn default_failure_threshold() -> u32 {
+    1
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Remediation {
+    #[serde(flatten)]
+    pub kind: RemediationKind,
+    /// How many failures in a row it takes to remediate
+    #[serde(default = "default_remediate_after")]
+    pub after: u32,
+    /// The least time between two remediations of the same check
+    #[serde(default)]
+    pub cooldown: Option<Duration>,
+}
+fn default_remediate_after() -> u32 {
+    3
+}
+impl Remediation {
+    pub fn cooldown(&self) -> std::time::Duration {
+        self.cooldown
+            .map_or(std::time::Duration::from_secs(600), |d| *d)
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+#[serde(tag = "type")]
+pub enum RemediationKind {
+    Restart,
+    #[serde(rename_all = "kebab-case")]
+    Action {
+        action: ActionId,
+    },
+    #[serde(rename_all = "kebab-case")]
+    Notify {
+        message: String,
+    },
 }
 impl HealthCheck {
+    pub fn interval(&self) -> std::time::Duration {
+        self.interval.map_or(
+            std::time::Duration::from_secs(crate::manager::HEALTH_CHECK_COOLDOWN_SECONDS),
+            |d| *d,
+        )
+    }
+
     #[instrument(skip(ctx))]
     pub async fn check(
         &self,
@@ -82,8 +188,18 @@ impl HealthCheck {
         pkg_version: &Version,
         volumes: &Volumes,
     ) -> Result<HealthCheckResult, Error> {
-        let res = self
-            .implementation
+        let timeout = self
+            .timeout
+            .map_or(std::time::Duration::from_secs(30), |d| *d);
+        let procedure = match &self.implementation {
+            HealthCheckImplementation::Procedure(procedure) => procedure,
+            HealthCheckImplementation::Native(native) => {
+                return native
+                    .check(ctx, pkg_id, pkg_version, volumes, timeout)
+                    .await
+            }
+        };
+        let res = procedure
             .execute(
                 ctx,
                 pkg_id,
@@ -91,10 +207,7 @@ impl HealthCheck {
                 ProcedureName::Health(id.clone()),
                 volumes,
                 Some(Utc::now().signed_duration_since(started).num_milliseconds()),
-                Some(
-                    self.timeout
-                        .map_or(std::time::Duration::from_secs(30), |d| *d),
-                ),
+                Some(timeout),
             )
             .await?;
         Ok(match res {
@@ -117,6 +230,63 @@ pub enum HealthCheckResult {
     Loading { message: String },
     Failure { error: String },
 }
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct HealthCheckRecord {
+    pub time: DateTime<Utc>,
+    #[serde(flatten)]
+    pub result: HealthCheckResult,
+}
+
+/// The most recent results of a health check, oldest first
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct HealthCheckHistory {
+    pub consecutive_failures: u32,
+    pub records: VecDeque<HealthCheckRecord>,
+}
+impl HealthCheckHistory {
+    pub const LENGTH: usize = 50;
+
+    /// Records a result, and returns what should be reported for the check,
+    /// or None if the failure should be ignored for now and the last reported result kept.
+    pub fn record(
+        &mut self,
+        check: &HealthCheck,
+        started: DateTime<Utc>,
+        time: DateTime<Utc>,
+        result: HealthCheckResult,
+    ) -> Option<HealthCheckResult> {
+        self.records.push_back(HealthCheckRecord {
+            time,
+            result: result.clone(),
+        });
+        while self.records.len() > Self::LENGTH {
+            self.records.pop_front();
+        }
+        if let HealthCheckResult::Failure { .. } = result {
+            if let Some(start_period) = check.start_period {
+                if time
+                    .signed_duration_since(started)
+                    .to_std()
+                    .unwrap_or_default()
+                    < *start_period
+                {
+                    return Some(HealthCheckResult::Starting);
+                }
+            }
+            self.consecutive_failures += 1;
+            if self.consecutive_failures < check.failure_threshold {
+                return None;
+            }
+        } else {
+            self.consecutive_failures = 0;
+        }
+        Some(result)
+    }
+}
+
 impl std::fmt::Display for HealthCheckResult {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -128,3 +298,155 @@ impl std::fmt::Display for HealthCheckResult {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn check(fields: serde_json::Value) -> HealthCheck {
+        let mut check = serde_json::json!({
+            "name": "Test",
+            "type": "docker",
+            "image": "main",
+            "entrypoint": "check",
+        });
+        if let serde_json::Value::Object(fields) = fields {
+            check.as_object_mut().unwrap().extend(fields);
+        }
+        serde_json::from_value(check).unwrap()
+    }
+
+    fn failure() -> HealthCheckResult {
+        HealthCheckResult::Failure {
+            error: "down".to_owned(),
+        }
+    }
+
+    #[test]
+    fn history_failure_threshold() {
+        let check = check(serde_json::json!({ "failure-threshold": 3 }));
+        let started = Utc::now();
+        let at = |secs| started + chrono::Duration::seconds(secs);
+        let mut history = HealthCheckHistory::default();
+        assert!(history.record(&check, started, at(1), failure()).is_none());
+        assert!(history.record(&check, started, at(2), failure()).is_none());
+        assert!(matches!(
+            history.record(&check, started, at(3), failure()),
+            Some(HealthCheckResult::Failure { .. })
+        ));
+        assert_eq!(history.consecutive_failures, 3);
+
+        // a success resets the count
+        assert!(matches!(
+            history.record(&check, started, at(4), HealthCheckResult::Success),
+            Some(HealthCheckResult::Success)
+        ));
+        assert_eq!(history.consecutive_failures, 0);
+        assert!(history.record(&check, started, at(5), failure()).is_none());
+        assert_eq!(history.records.len(), 5);
+    }
+
+    #[test]
+    fn history_start_period() {
+        let check = check(serde_json::json!({ "start-period": "30s" }));
+        let started = Utc::now();
+        let at = |secs| started + chrono::Duration::seconds(secs);
+        let mut history = HealthCheckHistory::default();
+        assert!(matches!(
+            history.record(&check, started, at(10), failure()),
+            Some(HealthCheckResult::Starting)
+        ));
+        assert_eq!(history.consecutive_failures, 0);
+        assert!(matches!(
+            history.record(&check, started, at(40), failure()),
+            Some(HealthCheckResult::Failure { .. })
+        ));
+        assert_eq!(history.consecutive_failures, 1);
+    }
+
+    #[test]
+    fn history_length() {
+        let check = check(serde_json::json!({}));
+        let started = Utc::now();
+        let mut history = HealthCheckHistory::default();
+        for secs in 0..(HealthCheckHistory::LENGTH as i64 + 10) {
+            history.record(
+                &check,
+                started,
+                started + chrono::Duration::seconds(secs),
+                HealthCheckResult::Success,
+            );
+        }
+        assert_eq!(history.records.len(), HealthCheckHistory::LENGTH);
+        assert_eq!(
+            history.records.front().unwrap().time,
+            started + chrono::Duration::seconds(10)
+        );
+    }
+
+    fn implementation(check: serde_json::Value) -> HealthCheckImplementation {
+        serde_json::from_value::<HealthCheck>(check)
+            .unwrap()
+            .implementation
+    }
+
+    #[test]
+    fn implementations() {
+        use serde_json::json;
+
+        assert!(matches!(
+            implementation(json!({
+                "name": "Web",
+                "type": "docker",
+                "image": "main",
+                "entrypoint": "check-web.sh",
+                "args": [],
+                "io-format": "yaml",
+                "inject": true,
+            })),
+            HealthCheckImplementation::Procedure(PackageProcedure::Docker(_))
+        ));
+        #[cfg(feature = "js_engine")]
+        assert!(matches!(
+            implementation(json!({ "name": "Web", "type": "script", "args": [] })),
+            HealthCheckImplementation::Procedure(PackageProcedure::Script(_))
+        ));
+        assert!(matches!(
+            implementation(json!({
+                "name": "Web",
+                "type": "http",
+                "interface": "main",
+                "port": 80,
+                "path": "/health",
+                "status": 200,
+                "body": "ok",
+            })),
+            HealthCheckImplementation::Native(NativeHealthCheck::Http(_))
+        ));
+        assert!(matches!(
+            implementation(
+                json!({ "name": "RPC", "type": "tcp", "interface": "main", "port": 8332 })
+            ),
+            HealthCheckImplementation::Native(NativeHealthCheck::Tcp(_))
+        ));
+        assert!(matches!(
+            implementation(json!({
+                "name": "Synced",
+                "type": "file",
+                "volume": "main",
+                "path": "synced",
+                "max-age": "1m",
+            })),
+            HealthCheckImplementation::Native(NativeHealthCheck::File(_))
+        ));
+        assert!(matches!(
+            implementation(json!({
+                "name": "DNS",
+                "type": "dns",
+                "interface": "main",
+                "hostname": "example.com",
+            })),
+            HealthCheckImplementation::Native(NativeHealthCheck::Dns(_))
+        ));
+    }
+}
@@ -1,6 +1,7 @@
 use std::collections::BTreeMap;
 
 use chrono::{DateTime, Utc};
+use models::DependencyEvent;
 use patch_db::{HasModel, Model};
 use serde::{Deserialize, Serialize};
 
@@ -9,6 +10,7 @@ use crate::dependencies::DependencyErrors;
 use crate::status::health_check::HealthCheckResult;
 
 pub mod health_check;
+pub mod native_health_check;
 #[derive(Clone, Debug, Deserialize, Serialize, HasModel)]
 #[serde(rename_all = "kebab-case")]
 pub struct Status {
@@ -37,40 +39,89 @@ pub enum MainStatus {
         started: Option<DateTime<Utc>>,
         health: BTreeMap<HealthCheckId, HealthCheckResult>,
     },
+    /// The container is frozen, keeping its memory, until it is resumed
+    Paused {
+        started: DateTime<Utc>,
+    },
+    /// The Manager gave up restarting the service after it crashed too often
+    CrashLooping {
+        since: DateTime<Utc>,
+        failures: u32,
+        /// None if the service failed to start at all
+        exit_code: Option<i32>,
+        /// The end of the error output of the last crash
+        stderr: String,
+    },
 }
 impl MainStatus {
     pub fn running(&self) -> bool {
         match self {
             MainStatus::Starting { .. }
             | MainStatus::Running { .. }
+            | MainStatus::Paused { .. }
             | MainStatus::BackingUp {
                 started: Some(_), ..
             } => true,
             MainStatus::Stopped
             | MainStatus::Stopping
             | MainStatus::Restarting
-            | MainStatus::BackingUp { started: None, .. } => false,
+            | MainStatus::BackingUp { started: None, .. }
+            | MainStatus::CrashLooping { .. } => false,
         }
     }
     pub fn stop(&mut self) {
         match self {
-            MainStatus::Starting { .. } | MainStatus::Running { .. } => {
+            MainStatus::Starting { .. }
+            | MainStatus::Running { .. }
+            | MainStatus::Paused { .. } => {
                 *self = MainStatus::Stopping;
             }
             MainStatus::BackingUp { started, .. } => {
                 *started = None;
             }
-            MainStatus::Stopped | MainStatus::Stopping | MainStatus::Restarting => (),
+            MainStatus::Stopped
+            | MainStatus::Stopping
+            | MainStatus::Restarting
+            | MainStatus::CrashLooping { .. } => (),
+        }
+    }
+    /// Whether the service is up as far as its dependents are concerned.
+    /// Stopping and Restarting count, since the service only goes down once the Manager has stopped it.
+    fn up(&self) -> bool {
+        match self {
+            MainStatus::Running { .. }
+            | MainStatus::Paused { .. }
+            | MainStatus::BackingUp {
+                started: Some(_), ..
+            }
+            | MainStatus::Stopping
+            | MainStatus::Restarting => true,
+            MainStatus::Stopped
+            | MainStatus::Starting { .. }
+            | MainStatus::BackingUp { started: None, .. }
+            | MainStatus::CrashLooping { .. } => false,
+        }
+    }
+    /// The event to send dependents when the status goes from `self` to `next`, if the service came up or went down
+    pub fn transition(&self, next: &MainStatus) -> Option<DependencyEvent> {
+        match (self.up(), next.up()) {
+            (false, true) if matches!(next, MainStatus::Running { .. }) => {
+                Some(DependencyEvent::Started)
+            }
+            (true, false) => Some(DependencyEvent::Stopped),
+            _ => None,
         }
     }
     pub fn started(&self) -> Option<DateTime<Utc>> {
         match self {
             MainStatus::Running { started, .. } => Some(*started),
+            MainStatus::Paused { started } => Some(*started),
             MainStatus::BackingUp { started, .. } => *started,
             MainStatus::Stopped => None,
             MainStatus::Restarting => None,
             MainStatus::Stopping => None,
             MainStatus::Starting { .. } => None,
+            MainStatus::CrashLooping { .. } => None,
         }
     }
 }
@@ -79,3 +130,69 @@ impl MainStatusModel {
         self.0.child("started")
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn dependency_events() {
+        let running = || MainStatus::Running {
+            started: Utc::now(),
+            health: BTreeMap::new(),
+        };
+        let crash_looping = MainStatus::CrashLooping {
+            since: Utc::now(),
+            failures: 5,
+            exit_code: Some(1),
+            stderr: String::new(),
+        };
+        let starting = |restarting| MainStatus::Starting { restarting };
+        let event = |before: &MainStatus, after: &MainStatus| before.transition(after);
+
+        // started then stopped by the user
+        assert_eq!(
+            event(&starting(false), &running()),
+            Some(DependencyEvent::Started)
+        );
+        assert_eq!(event(&running(), &MainStatus::Stopping), None);
+        assert_eq!(
+            event(&MainStatus::Stopping, &MainStatus::Stopped),
+            Some(DependencyEvent::Stopped)
+        );
+        // left stopped by its restart policy, or crash looping
+        assert_eq!(
+            event(&running(), &MainStatus::Stopped),
+            Some(DependencyEvent::Stopped)
+        );
+        assert_eq!(
+            event(&running(), &crash_looping),
+            Some(DependencyEvent::Stopped)
+        );
+        // restarted: stopped, then started again
+        assert_eq!(event(&running(), &MainStatus::Restarting), None);
+        assert_eq!(
+            event(&MainStatus::Restarting, &starting(true)),
+            Some(DependencyEvent::Stopped)
+        );
+        assert_eq!(
+            event(&starting(true), &running()),
+            Some(DependencyEvent::Started)
+        );
+        // never came up
+        assert_eq!(event(&starting(false), &crash_looping), None);
+        assert_eq!(event(&MainStatus::Stopped, &starting(false)), None);
+        // paused and backed up while running
+        let paused = MainStatus::Paused {
+            started: Utc::now(),
+        };
+        assert_eq!(event(&running(), &paused), None);
+        assert_eq!(event(&paused, &running()), None);
+        let backing_up = MainStatus::BackingUp {
+            started: Some(Utc::now()),
+            health: BTreeMap::new(),
+        };
+        assert_eq!(event(&running(), &backing_up), None);
+        assert_eq!(event(&backing_up, &running()), None);
+    }
+}
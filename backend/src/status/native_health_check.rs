@@ -0,0 +1,407 @@
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::{Component, PathBuf};
+use std::str::FromStr;
+
+use color_eyre::eyre::{bail, eyre};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use tokio::net::{TcpStream, UdpSocket};
+use tracing::instrument;
+use trust_dns_server::proto::op::{Message, MessageType, OpCode, Query, ResponseCode};
+use trust_dns_server::proto::rr::{Name, RData, RecordType};
+use trust_dns_server::proto::serialize::binary::{BinDecodable, BinEncodable};
+
+use super::health_check::HealthCheckResult;
+use crate::context::RpcContext;
+use crate::net::interface::{InterfaceId, Interfaces};
+use crate::procedure::docker::DockerProcedure;
+use crate::s9pk::manifest::PackageId;
+use crate::util::serde::Duration;
+use crate::util::Version;
+use crate::volume::{VolumeId, Volumes};
+use crate::Error;
+
+/// A health check that embassyd evaluates itself, instead of running a procedure in the service
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+#[serde(tag = "type")]
+pub enum NativeHealthCheck {
+    Http(HttpHealthCheck),
+    Tcp(TcpHealthCheck),
+    File(FileHealthCheck),
+    Dns(DnsHealthCheck),
+}
+impl NativeHealthCheck {
+    #[instrument]
+    pub fn validate(
+        &self,
+        interfaces: &Interfaces,
+        volumes: &Volumes,
+    ) -> Result<(), color_eyre::eyre::Report> {
+        match self {
+            NativeHealthCheck::Http(check) => {
+                validate_port(interfaces, &check.interface, check.port)?;
+                if !check.path.starts_with('/') {
+                    bail!("path must start with /: {}", check.path);
+                }
+                if let Some(body) = &check.body {
+                    Regex::new(body)?;
+                }
+            }
+            NativeHealthCheck::Tcp(check) => {
+                validate_port(interfaces, &check.interface, check.port)?;
+            }
+            NativeHealthCheck::File(check) => {
+                if !volumes.contains_key(&check.volume) {
+                    bail!("unknown volume: {}", check.volume);
+                }
+                if !check
+                    .path
+                    .components()
+                    .all(|c| matches!(c, Component::Normal(_) | Component::CurDir))
+                {
+                    bail!(
+                        "path must be relative to the volume: {}",
+                        check.path.display()
+                    );
+                }
+            }
+            NativeHealthCheck::Dns(check) => {
+                validate_port(interfaces, &check.interface, check.port)?;
+                Name::from_str(&check.hostname)?;
+            }
+        }
+        Ok(())
+    }
+
+    #[instrument(skip(ctx))]
+    pub async fn check(
+        &self,
+        ctx: &RpcContext,
+        pkg_id: &PackageId,
+        pkg_version: &Version,
+        volumes: &Volumes,
+        timeout: std::time::Duration,
+    ) -> Result<HealthCheckResult, Error> {
+        let res = tokio::time::timeout(timeout, async {
+            match self {
+                NativeHealthCheck::Http(check) => check.check(ctx, pkg_id).await,
+                NativeHealthCheck::Tcp(check) => check.check(ctx, pkg_id).await,
+                NativeHealthCheck::File(check) => {
+                    check.check(ctx, pkg_id, pkg_version, volumes).await
+                }
+                NativeHealthCheck::Dns(check) => check.check(ctx, pkg_id).await,
+            }
+        })
+        .await;
+        Ok(match res {
+            Ok(Ok(())) => HealthCheckResult::Success,
+            Ok(Err(e)) => HealthCheckResult::Failure {
+                error: e.to_string(),
+            },
+            Err(_) => HealthCheckResult::Failure {
+                error: format!("Timed out after {}s", timeout.as_secs()),
+            },
+        })
+    }
+}
+
+/// An HTTP GET against a port of one of the service's interfaces
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct HttpHealthCheck {
+    pub interface: InterfaceId,
+    /// The port inside the container
+    pub port: u16,
+    #[serde(default = "default_http_path")]
+    pub path: String,
+    /// Self signed certificates are accepted
+    #[serde(default)]
+    pub https: bool,
+    /// The expected status code. Any 2xx status is accepted if unset
+    #[serde(default)]
+    pub status: Option<u16>,
+    /// A regex the response body must match
+    #[serde(default)]
+    pub body: Option<String>,
+}
+fn default_http_path() -> String {
+    "/".to_owned()
+}
+impl HttpHealthCheck {
+    async fn check(
+        &self,
+        ctx: &RpcContext,
+        pkg_id: &PackageId,
+    ) -> Result<(), color_eyre::eyre::Report> {
+        let ip = container_ip(ctx, pkg_id).await?;
+        let url = format!(
+            "{}://{}:{}{}",
+            if self.https { "https" } else { "http" },
+            ip,
+            self.port,
+            self.path
+        );
+        let res = reqwest::Client::builder()
+            .danger_accept_invalid_certs(true)
+            .build()?
+            .get(&url)
+            .send()
+            .await?;
+        let status = res.status();
+        match self.status {
+            Some(expected) if status.as_u16() != expected => {
+                bail!("{} returned {}, expected {}", url, status, expected)
+            }
+            None if !status.is_success() => bail!("{} returned {}", url, status),
+            _ => (),
+        }
+        if let Some(body) = &self.body {
+            let re = Regex::new(body)?;
+            if !re.is_match(&res.text().await?) {
+                bail!("Response from {} does not match {}", url, body);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A TCP connection to a port of one of the service's interfaces
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct TcpHealthCheck {
+    pub interface: InterfaceId,
+    /// The port inside the container
+    pub port: u16,
+}
+impl TcpHealthCheck {
+    async fn check(
+        &self,
+        ctx: &RpcContext,
+        pkg_id: &PackageId,
+    ) -> Result<(), color_eyre::eyre::Report> {
+        let addr = SocketAddr::new(container_ip(ctx, pkg_id).await?.into(), self.port);
+        TcpStream::connect(addr)
+            .await
+            .map_err(|e| eyre!("Could not connect to {}: {}", addr, e))?;
+        Ok(())
+    }
+}
+
+/// A file that must exist in one of the service's volumes
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct FileHealthCheck {
+    pub volume: VolumeId,
+    /// Relative to the root of the volume
+    pub path: PathBuf,
+    /// If set, the file must also have been modified at most this long ago
+    #[serde(default)]
+    pub max_age: Option<Duration>,
+}
+impl FileHealthCheck {
+    async fn check(
+        &self,
+        ctx: &RpcContext,
+        pkg_id: &PackageId,
+        pkg_version: &Version,
+        volumes: &Volumes,
+    ) -> Result<(), color_eyre::eyre::Report> {
+        let path = volumes
+            .get_path_for(&ctx.datadir, pkg_id, pkg_version, &self.volume)
+            .ok_or_else(|| eyre!("unknown volume: {}", self.volume))?
+            .join(&self.path);
+        let metadata = tokio::fs::metadata(&path)
+            .await
+            .map_err(|e| eyre!("{} in {}: {}", self.path.display(), self.volume, e))?;
+        if let Some(max_age) = self.max_age {
+            let age = metadata.modified()?.elapsed().unwrap_or_default();
+            if age > *max_age {
+                bail!(
+                    "{} in {} was last modified {}s ago",
+                    self.path.display(),
+                    self.volume,
+                    age.as_secs()
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A DNS query sent to a DNS server the service runs, which must answer it
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct DnsHealthCheck {
+    pub interface: InterfaceId,
+    /// The port inside the container
+    #[serde(default = "default_dns_port")]
+    pub port: u16,
+    pub hostname: String,
+    /// If set, one of the answers must be this address
+    #[serde(default)]
+    pub expected: Option<IpAddr>,
+}
+fn default_dns_port() -> u16 {
+    53
+}
+impl DnsHealthCheck {
+    async fn check(
+        &self,
+        ctx: &RpcContext,
+        pkg_id: &PackageId,
+    ) -> Result<(), color_eyre::eyre::Report> {
+        let addr = SocketAddr::new(container_ip(ctx, pkg_id).await?.into(), self.port);
+        let record_type = match self.expected {
+            Some(IpAddr::V6(_)) => RecordType::AAAA,
+            _ => RecordType::A,
+        };
+        let mut query = Message::new();
+        query
+            .set_id(rand::random())
+            .set_message_type(MessageType::Query)
+            .set_op_code(OpCode::Query)
+            .set_recursion_desired(true)
+            .add_query(Query::query(Name::from_str(&self.hostname)?, record_type));
+        let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).await?;
+        socket.connect(addr).await?;
+        socket.send(&query.to_bytes()?).await?;
+        let mut buf = vec![0; 4096];
+        let len = socket.recv(&mut buf).await?;
+        let res = Message::from_bytes(&buf[..len])?;
+        if res.id() != query.id() {
+            bail!("{} answered a different query", addr);
+        }
+        if res.response_code() != ResponseCode::NoError {
+            bail!(
+                "{} could not resolve {}: {}",
+                addr,
+                self.hostname,
+                res.response_code()
+            );
+        }
+        let answers = res
+            .answers()
+            .iter()
+            .filter_map(|record| match record.data() {
+                Some(RData::A(ip)) => Some(IpAddr::V4(*ip)),
+                Some(RData::AAAA(ip)) => Some(IpAddr::V6(*ip)),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        match self.expected {
+            Some(expected) if !answers.contains(&expected) => {
+                bail!(
+                    "{} resolved {} to {:?}, expected {}",
+                    addr,
+                    self.hostname,
+                    answers,
+                    expected
+                )
+            }
+            None if answers.is_empty() => bail!("{} has no address for {}", addr, self.hostname),
+            _ => Ok(()),
+        }
+    }
+}
+
+fn validate_port(
+    interfaces: &Interfaces,
+    interface_id: &InterfaceId,
+    port: u16,
+) -> Result<(), color_eyre::eyre::Report> {
+    let interface = interfaces
+        .0
+        .get(interface_id)
+        .ok_or_else(|| eyre!("unknown interface: {}", interface_id))?;
+    let tor_ports = interface
+        .tor_config
+        .iter()
+        .flat_map(|tor| tor.port_mapping.values().map(|p| p.0));
+    let lan_ports = interface
+        .lan_config
+        .iter()
+        .flat_map(|lan| lan.values().map(|cfg| cfg.internal));
+    if !tor_ports.chain(lan_ports).any(|p| p == port) {
+        bail!("interface {} does not expose port {}", interface_id, port);
+    }
+    Ok(())
+}
+
+/// The address of the main container on the service network
+async fn container_ip(
+    ctx: &RpcContext,
+    pkg_id: &PackageId,
+) -> Result<Ipv4Addr, color_eyre::eyre::Report> {
+    let container_name = DockerProcedure::container_name(pkg_id, None);
+    let ip = ctx
+        .docker
+        .inspect_container(&container_name, None)
+        .await?
+        .network_settings
+        .and_then(|ns| ns.networks)
+        .and_then(|mut n| n.remove("start9"))
+        .and_then(|es| es.ip_address)
+        .filter(|ip| !ip.is_empty())
+        .ok_or_else(|| eyre!("{} has no address", container_name))?;
+    Ok(ip.parse()?)
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use super::*;
+
+    fn validate(check: serde_json::Value) -> Result<(), color_eyre::eyre::Report> {
+        let interfaces: Interfaces = serde_json::from_value(json!({
+            "main": {
+                "name": "Main",
+                "description": "",
+                "tor-config": { "port-mapping": { "80": "8080" } },
+                "lan-config": { "443": { "ssl": true, "internal": 8443 } },
+                "ui": true,
+                "protocols": ["tcp", "http"],
+            }
+        }))
+        .unwrap();
+        let volumes: Volumes =
+            serde_json::from_value(json!({ "main": { "type": "data" } })).unwrap();
+        serde_json::from_value::<NativeHealthCheck>(check)
+            .unwrap()
+            .validate(&interfaces, &volumes)
+    }
+
+    #[test]
+    fn validate_ports() {
+        validate(json!({ "type": "tcp", "interface": "main", "port": 8080 })).unwrap();
+        validate(json!({ "type": "http", "interface": "main", "port": 8443 })).unwrap();
+        validate(json!({ "type": "tcp", "interface": "main", "port": 80 })).unwrap_err();
+        validate(json!({ "type": "tcp", "interface": "rpc", "port": 8080 })).unwrap_err();
+    }
+
+    #[test]
+    fn validate_http() {
+        validate(json!({
+            "type": "http",
+            "interface": "main",
+            "port": 8080,
+            "path": "/health",
+            "body": "^ok$",
+        }))
+        .unwrap();
+        validate(json!({ "type": "http", "interface": "main", "port": 8080, "path": "health" }))
+            .unwrap_err();
+        validate(json!({ "type": "http", "interface": "main", "port": 8080, "body": "(" }))
+            .unwrap_err();
+    }
+
+    #[test]
+    fn validate_file() {
+        validate(json!({ "type": "file", "volume": "main", "path": "./state/ready" })).unwrap();
+        validate(json!({ "type": "file", "volume": "main", "path": "/state/ready" })).unwrap_err();
+        validate(json!({ "type": "file", "volume": "main", "path": "../other/ready" }))
+            .unwrap_err();
+        validate(json!({ "type": "file", "volume": "other", "path": "ready" })).unwrap_err();
+    }
+}
@@ -1039,4 +1039,45 @@ export const action = {
     }
   },
 
+  /**
+   * Want to test the crypto and encoding effects
+   * @param {*} effects 
+   * @param {*} _input 
+   * @returns 
+   */
+  async "test-crypto"(effects, _input) {
+    assert(
+      effects.hash({ data: "abc" }) ===
+        "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad",
+      "sha256 should match the known digest"
+    );
+    assert(
+      effects.hmac({ key: "key", data: "The quick brown fox jumps over the lazy dog" }) ===
+        "f7bc83f430538424b13298e6aa6fb143ef4d59a14946175997479dbc2d1a3cd8",
+      "hmac should match the known digest"
+    );
+    assert(
+      effects.encode({ data: effects.encode({ data: "hello", to: "base32" }), from: "base32", to: "utf8" }) === "hello",
+      "base32 should round trip"
+    );
+    assert(effects.randomBytes({ length: 16 }).length === 32, "Should be 16 hex encoded bytes");
+    for (const algorithm of ["ed25519", "secp256k1"]) {
+      const { publicKey, secretKey } = effects.generateKeyPair({ algorithm });
+      const signature = effects.sign({ algorithm, secretKey, data: "message" });
+      assert(effects.verify({ algorithm, publicKey, data: "message", signature }), `${algorithm} signature should verify`);
+      assert(!effects.verify({ algorithm, publicKey, data: "other", signature }), `${algorithm} signature should not verify other data`);
+    }
+    const hashed = await effects.argon2Hash({ password: "password" });
+    assert(await effects.argon2Verify({ hash: hashed, password: "password" }), "argon2 should verify");
+    assert(!(await effects.argon2Verify({ hash: hashed, password: "wrong" })), "argon2 should not verify the wrong password");
+    return {
+      result: {
+        copyable: false,
+        message: "Done",
+        version: "0",
+        qr: false,
+      },
+    };
+  },
+
 };
@@ -153,6 +153,7 @@ export class MarketplaceShowControlsComponent {
       const breakages = await this.embassyApi.dryUpdatePackage({
         id,
         version: `${version}`,
+        'marketplace-url': url,
       })
 
       if (isEmptyObject(breakages)) {
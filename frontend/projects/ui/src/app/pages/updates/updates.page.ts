@@ -96,6 +96,7 @@ export class UpdatesPage {
       const breakages = await this.api.dryUpdatePackage({
         id,
         version: `${version}`,
+        'marketplace-url': url,
       })
       await loader.dismiss()
 
@@ -1782,6 +1782,7 @@ export module Mock {
       'dependency-info': {},
       'marketplace-url': 'https://registry.start9.com/',
       'developer-key': 'developer-key',
+      usage: null,
     },
     'install-progress': undefined,
   }
@@ -1831,6 +1832,7 @@ export module Mock {
       },
       'marketplace-url': 'https://registry.start9.com/',
       'developer-key': 'developer-key',
+      usage: null,
     },
     'install-progress': undefined,
   }
@@ -1891,6 +1893,7 @@ export module Mock {
       },
       'marketplace-url': 'https://registry.start9.com/',
       'developer-key': 'developer-key',
+      usage: null,
     },
     'install-progress': undefined,
   }
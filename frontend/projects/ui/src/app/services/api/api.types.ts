@@ -189,7 +189,11 @@ export module RR {
   } // package.install
   export type InstallPackageRes = null
 
-  export type DryUpdatePackageReq = { id: string; version: string } // package.update.dry
+  export type DryUpdatePackageReq = {
+    id: string
+    version: string
+    'marketplace-url': string
+  } // package.update.dry
   export type DryUpdatePackageRes = Breakages
 
   export type GetPackageConfigReq = { id: string } // package.config.get
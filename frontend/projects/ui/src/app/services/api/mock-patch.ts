@@ -455,6 +455,7 @@ export const mockPatchData: DataModel = {
         'dependency-info': {},
         'marketplace-url': 'https://registry.start9.com/',
         'developer-key': 'developer-key',
+        usage: null,
       },
     },
     lnd: {
@@ -669,6 +670,7 @@ export const mockPatchData: DataModel = {
         },
         'marketplace-url': 'https://registry.start9.com/',
         'developer-key': 'developer-key',
+        usage: null,
       },
     },
   },
@@ -125,6 +125,14 @@ export interface InstalledPackageDataEntry {
   }
   'marketplace-url': string | null
   'developer-key': string
+  usage: PackageUsage | null
+}
+
+// rough resource usage of a running service: cpu in whole percent, memory and disk in MiB
+export interface PackageUsage {
+  cpu: number
+  memory: number
+  disk: number
 }
 
 export interface CurrentDependencyInfo {
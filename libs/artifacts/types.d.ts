@@ -24,6 +24,17 @@ export namespace ExpectedExports {
     effects: Effects,
     version: string,
   ) => Promise<ResultType<MigrationRes>>;
+  export type configMigration = {
+    /** Should be the config spec version being migrated to. Receives the config from the version before it. */
+    [version: string]: (
+      effects: Effects,
+      input: Config,
+    ) => Promise<ResultType<Config>>;
+  };
+  export type scheduledTask = {
+    /** Should be the scheduled task id */
+    [id: string]: (effects: Effects) => Promise<ResultType<null | void>>;
+  };
 }
 
 
@@ -62,8 +73,151 @@ export type Effects = {
 
   /** Sandbox mode lets us read but not write */
   is_sandboxed(): boolean;
+
+  /** Run a command in the container, resolving to its stdout */
+  runCommand(
+    input: { command: string; args?: string[]; timeoutMillis?: number } & CommandOptions,
+  ): Promise<string>;
+  /** Start a command in the container and leave it running */
+  runDaemon(input: { command: string; args?: string[] } & CommandOptions): Daemon;
+  /** Start a command in the container, and restart it with backoff whenever it exits */
+  supervise(
+    input: { command: string; args?: string[]; policy?: RestartPolicy } & Omit<CommandOptions, "stdin">,
+  ): Supervisor;
+
+  /** Read a value from the package's key-value store, null if missing */
+  storeGet(input: { key: string }): Promise<unknown>;
+  /** Write a value into the package's key-value store. Usable when not sandboxed */
+  storeSet(input: { key: string; value: unknown }): Promise<void>;
+  /** Remove a key, resolving to whether it was present. Usable when not sandboxed */
+  storeDelete(input: { key: string }): Promise<boolean>;
+  /** All entries whose key starts with the prefix */
+  storeList(input?: { prefix?: string }): Promise<{ [key: string]: unknown }>;
+  /** Replace the value (or remove it when value is null) only if the current value equals expected (null meaning missing). Usable when not sandboxed */
+  storeCompareAndSwap(
+    input: { key: string; expected?: unknown; value?: unknown },
+  ): Promise<boolean>;
+
+  /** Select from the config of a dependency declared in the manifest, using a json path like a config pointer */
+  getDependencyConfig(
+    input: { packageId: string; selector?: string; multi?: boolean },
+  ): Promise<unknown>;
+  /** The tor address of an interface of a declared dependency */
+  getDependencyTorAddress(
+    input: { packageId: string; interface: string },
+  ): Promise<string | null>;
+  /** The lan address of an interface of a declared dependency */
+  getDependencyLanAddress(
+    input: { packageId: string; interface: string },
+  ): Promise<string | null>;
+  /** The properties published by a declared dependency */
+  getDependencyProperties(input: { packageId: string }): Promise<Properties | null>;
+
+  /** Convert data between encodings, eg. utf8 to base64 */
+  encode(input: { data: string; from?: Encoding; to?: Encoding }): string;
+  /** Cryptographically secure random bytes, hex encoded by default */
+  randomBytes(input: { length: number; encoding?: Encoding }): string;
+  /** SHA-2 digest of the data, hex encoded by default */
+  hash(
+    input: {
+      algorithm?: HashAlgorithm;
+      data: string;
+      encoding?: Encoding;
+      outputEncoding?: Encoding;
+    },
+  ): string;
+  hmac(
+    input: {
+      algorithm?: HashAlgorithm;
+      key: string;
+      keyEncoding?: Encoding;
+      data: string;
+      encoding?: Encoding;
+      outputEncoding?: Encoding;
+    },
+  ): string;
+  /** Argon2 hash with a random salt, in the PHC string format */
+  argon2Hash(input: { password: string }): Promise<string>;
+  argon2Verify(input: { hash: string; password: string }): Promise<boolean>;
+  /** Keys are hex encoded by default. secp256k1 public keys are SEC1 compressed */
+  generateKeyPair(
+    input: { algorithm: KeyAlgorithm; encoding?: Encoding },
+  ): { publicKey: string; secretKey: string };
+  /** secp256k1 signs the SHA-256 of the data, giving a 64 byte r || s signature */
+  sign(
+    input: {
+      algorithm: KeyAlgorithm;
+      secretKey: string;
+      keyEncoding?: Encoding;
+      data: string;
+      encoding?: Encoding;
+      outputEncoding?: Encoding;
+    },
+  ): string;
+  verify(
+    input: {
+      algorithm: KeyAlgorithm;
+      publicKey: string;
+      keyEncoding?: Encoding;
+      data: string;
+      encoding?: Encoding;
+      signature: string;
+      signatureEncoding?: Encoding;
+    },
+  ): boolean;
   
 };
+export type CommandOptions = {
+  /** Written to the command's stdin, which is then closed */
+  stdin?: string;
+  /** Added to the container's environment */
+  env?: { [name: string]: string };
+  /** Working directory, inside the container */
+  cwd?: string;
+  /** Run as this user id rather than the container's default */
+  uid?: number;
+  /** Run as this group id, defaulting to the primary group of the user */
+  gid?: number;
+};
+export type Daemon = {
+  processId: Promise<number>;
+  wait(): Promise<string>;
+  term(signal?: number): Promise<void>;
+  /** Lines of output as they happen, starting from when the daemon started, ending with its exit */
+  output(): AsyncIterable<OutputEvent>;
+};
+export type OutputEvent =
+  | { type: "stdout"; data: string }
+  | { type: "stderr"; data: string }
+  | { type: "exit"; data: { code: number | null; signal: number | null } };
+export type RestartPolicy = {
+  /** Milliseconds before the first restart, doubling with each restart after that. Defaults to 1000 */
+  initialBackoff?: number;
+  /** Defaults to 60000 */
+  maxBackoff?: number;
+  /** Give up after this many restarts. Unlimited by default */
+  maxRestarts?: number;
+  /** Restart after the command exits successfully as well */
+  restartOnSuccess?: boolean;
+};
+export type Supervisor = {
+  id: Promise<number>;
+  /** Terminates the command without restarting it, resolving to the final state */
+  stop(): Promise<SupervisorState>;
+  /** The current state, then every change, ending with a final state: failed, exited or stopped */
+  states(): AsyncIterable<SupervisorState>;
+};
+type ProcessExit = { code: number | null; signal: number | null };
+export type SupervisorState =
+  | { state: "starting" }
+  | { state: "running"; pid: number; restarts: number }
+  | { state: "backing-off"; exit: ProcessExit; restarts: number; delay: number }
+  | { state: "failed"; exit: ProcessExit | null; restarts: number; error: string | null }
+  | { state: "exited"; exit: ProcessExit; restarts: number }
+  | { state: "stopped"; restarts: number };
+export type Encoding = "utf8" | "hex" | "base64" | "base64url" | "base32";
+export type HashAlgorithm = "sha256" | "sha384" | "sha512";
+export type KeyAlgorithm = "ed25519" | "secp256k1";
 export type Metadata = {
   fileType: string,
   isDir: boolean,
@@ -375,5 +529,10 @@ export type Dependencies = {
     check(effects: Effects, input: Config): Promise<ResultType<void | null>>;
     /** This is called after we know that the dependency package needs a new configuration, this would be a transform for defaults */
     autoConfigure(effects: Effects, input: Config): Promise<ResultType<Config>>;
+    /** Hooks for an optional dependency, called after the dependency is installed, started, stopped or uninstalled */
+    installed?(effects: Effects): Promise<ResultType<void | null>>;
+    started?(effects: Effects): Promise<ResultType<void | null>>;
+    stopped?(effects: Effects): Promise<ResultType<void | null>>;
+    uninstalled?(effects: Effects): Promise<ResultType<void | null>>;
   };
 };
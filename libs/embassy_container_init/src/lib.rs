@@ -1,3 +1,7 @@
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::time::Duration;
+
 use nix::unistd::Pid;
 use serde::{Deserialize, Serialize, Serializer};
 use yajrc::RpcMethod;
@@ -47,6 +51,19 @@ pub struct RunCommandParams {
     pub command: String,
     pub args: Vec<String>,
     pub output: OutputStrategy,
+    /// Written to the command's stdin, which is then closed
+    #[serde(default)]
+    pub stdin: Option<String>,
+    /// Added to the environment of the container
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+    #[serde(default)]
+    pub cwd: Option<PathBuf>,
+    #[serde(default)]
+    pub uid: Option<u32>,
+    /// Group to run as, defaulting to the primary group of `uid`
+    #[serde(default)]
+    pub group: Option<u32>,
 }
 impl RpcMethod for RunCommand {
     type Params = RunCommandParams;
@@ -165,6 +182,59 @@ impl RpcMethod for Output {
     }
 }
 
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ProcessExit {
+    pub code: Option<i32>,
+    pub signal: Option<i32>,
+}
+impl ProcessExit {
+    pub fn success(&self) -> bool {
+        self.code == Some(0)
+    }
+}
+
+/// A line of output or the end of a command started with [OutputStrategy::Inherit]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(tag = "type", content = "data", rename_all = "kebab-case")]
+pub enum OutputEvent {
+    Stdout(String),
+    Stderr(String),
+    Exit(ProcessExit),
+}
+
+/// The method of the notifications that follow the response to [SubscribeOutput]
+pub const OUTPUT_EVENT: &str = "output-event";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OutputEventParams {
+    pub pid: ProcessId,
+    pub event: OutputEvent,
+}
+
+/// Streams [OutputEvent]s as [OUTPUT_EVENT] notifications, starting with any output from before the subscription.
+/// The server closes the connection after the exit event, so this wants a connection of its own.
+#[derive(Debug, Clone, Copy)]
+pub struct SubscribeOutput;
+impl Serialize for SubscribeOutput {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        Serialize::serialize(Self.as_str(), serializer)
+    }
+}
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SubscribeOutputParams {
+    pub pid: ProcessId,
+}
+impl RpcMethod for SubscribeOutput {
+    type Params = SubscribeOutputParams;
+    type Response = ();
+    fn as_str<'a>(&'a self) -> &'a str {
+        "subscribe-output"
+    }
+}
+
 #[derive(Debug, Clone, Copy)]
 pub struct SendSignal;
 impl Serialize for SendSignal {
@@ -213,6 +283,185 @@ impl RpcMethod for SignalGroup {
     }
 }
 
+#[derive(Debug, Serialize, Deserialize, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SupervisorId(pub u32);
+
+/// When and how quickly a supervised command is restarted after it exits
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RestartPolicy {
+    /// Milliseconds before the first restart, doubling with every restart after that
+    #[serde(default = "RestartPolicy::default_initial_backoff")]
+    pub initial_backoff: u64,
+    #[serde(default = "RestartPolicy::default_max_backoff")]
+    pub max_backoff: u64,
+    /// Give up after this many restarts in a row, or never if unset. A run lasting `max_backoff` resets the count
+    #[serde(default)]
+    pub max_restarts: Option<u32>,
+    /// Restart after the command exits successfully as well
+    #[serde(default)]
+    pub restart_on_success: bool,
+}
+impl RestartPolicy {
+    fn default_initial_backoff() -> u64 {
+        1000
+    }
+    fn default_max_backoff() -> u64 {
+        60_000
+    }
+    /// How long to wait before restarting a command that has already been restarted `restarts` times
+    pub fn delay(&self, restarts: u32) -> Duration {
+        Duration::from_millis(
+            self.initial_backoff
+                .saturating_mul(1_u64.checked_shl(restarts).unwrap_or(u64::MAX))
+                .min(self.max_backoff),
+        )
+    }
+}
+impl Default for RestartPolicy {
+    fn default() -> Self {
+        RestartPolicy {
+            initial_backoff: Self::default_initial_backoff(),
+            max_backoff: Self::default_max_backoff(),
+            max_restarts: None,
+            restart_on_success: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(tag = "state", rename_all = "kebab-case")]
+pub enum SupervisorState {
+    Starting,
+    Running {
+        pid: ProcessId,
+        restarts: u32,
+    },
+    /// Waiting `delay` milliseconds before the next restart
+    BackingOff {
+        exit: ProcessExit,
+        restarts: u32,
+        delay: u64,
+    },
+    /// Out of restarts, or the command could not be started at all
+    Failed {
+        exit: Option<ProcessExit>,
+        restarts: u32,
+        error: Option<String>,
+    },
+    /// Exited successfully, and the policy does not restart on success
+    Exited {
+        exit: ProcessExit,
+        restarts: u32,
+    },
+    Stopped {
+        restarts: u32,
+    },
+}
+impl SupervisorState {
+    /// Whether the supervisor is done and will not change state again
+    pub fn is_final(&self) -> bool {
+        matches!(
+            self,
+            SupervisorState::Failed { .. }
+                | SupervisorState::Exited { .. }
+                | SupervisorState::Stopped { .. }
+        )
+    }
+}
+
+/// Runs a command with its output inherited, restarting it according to the [RestartPolicy] every time it exits
+/// The supervisor is forgotten once it reaches a final state, so [SubscribeSupervisor] and [StopSupervisor] no longer find it
+#[derive(Debug, Clone, Copy)]
+pub struct Supervise;
+impl Serialize for Supervise {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        Serialize::serialize(Self.as_str(), serializer)
+    }
+}
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SuperviseParams {
+    pub gid: Option<ProcessGroupId>,
+    pub command: String,
+    pub args: Vec<String>,
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+    #[serde(default)]
+    pub cwd: Option<PathBuf>,
+    #[serde(default)]
+    pub uid: Option<u32>,
+    /// Group to run as, defaulting to the primary group of `uid`
+    #[serde(default)]
+    pub group: Option<u32>,
+    #[serde(default)]
+    pub policy: RestartPolicy,
+}
+impl RpcMethod for Supervise {
+    type Params = SuperviseParams;
+    type Response = SupervisorId;
+    fn as_str<'a>(&'a self) -> &'a str {
+        "supervise"
+    }
+}
+
+/// The method of the notifications that follow the response to [SubscribeSupervisor]
+pub const SUPERVISOR_EVENT: &str = "supervisor-event";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SupervisorEventParams {
+    pub id: SupervisorId,
+    pub state: SupervisorState,
+}
+
+/// Streams every [SupervisorState] as a [SUPERVISOR_EVENT] notification, starting with the current one.
+/// The server closes the connection after a final state, so this wants a connection of its own.
+#[derive(Debug, Clone, Copy)]
+pub struct SubscribeSupervisor;
+impl Serialize for SubscribeSupervisor {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        Serialize::serialize(Self.as_str(), serializer)
+    }
+}
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SubscribeSupervisorParams {
+    pub id: SupervisorId,
+}
+impl RpcMethod for SubscribeSupervisor {
+    type Params = SubscribeSupervisorParams;
+    type Response = ();
+    fn as_str<'a>(&'a self) -> &'a str {
+        "subscribe-supervisor"
+    }
+}
+
+/// Stops restarting the command and terminates it, responding with the final state
+#[derive(Debug, Clone, Copy)]
+pub struct StopSupervisor;
+impl Serialize for StopSupervisor {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        Serialize::serialize(Self.as_str(), serializer)
+    }
+}
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StopSupervisorParams {
+    pub id: SupervisorId,
+}
+impl RpcMethod for StopSupervisor {
+    type Params = StopSupervisorParams;
+    type Response = SupervisorState;
+    fn as_str<'a>(&'a self) -> &'a str {
+        "stop-supervisor"
+    }
+}
+
 #[test]
 fn example_echo_line() {
     let input = r#"{"id":0,"jsonrpc":"2.0","method":"command","params":{"command":"echo","args":["world I am here"]}}"#;
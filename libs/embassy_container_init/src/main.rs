@@ -1,12 +1,16 @@
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, VecDeque};
 use std::ops::DerefMut;
 use std::os::unix::process::ExitStatusExt;
 use std::process::Stdio;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use embassy_container_init::{
-    OutputParams, OutputStrategy, ProcessGroupId, ProcessId, ReadLineStderrParams,
-    ReadLineStdoutParams, RunCommandParams, SendSignalParams, SignalGroupParams, LogParams,
+    LogParams, OutputEvent, OutputEventParams, OutputParams, OutputStrategy, ProcessExit,
+    ProcessGroupId, ProcessId, ReadLineStderrParams, ReadLineStdoutParams, RunCommandParams,
+    SendSignalParams, SignalGroupParams, StopSupervisorParams, SubscribeOutputParams,
+    SubscribeSupervisorParams, SuperviseParams, SupervisorEventParams, SupervisorId,
+    SupervisorState, OUTPUT_EVENT, SUPERVISOR_EVENT,
 };
 use futures::StreamExt;
 use helpers::NonDetachingJoinHandle;
@@ -15,9 +19,10 @@ use nix::sys::signal::Signal;
 use serde::{Deserialize, Serialize};
 use serde_json::json;
 use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::unix::OwnedWriteHalf;
 use tokio::process::{Child, ChildStderr, ChildStdout, Command};
 use tokio::select;
-use tokio::sync::{watch, Mutex};
+use tokio::sync::{broadcast, oneshot, watch, Mutex};
 use yajrc::{Id, RpcError};
 
 /// Outputs embedded in the JSONRpc output of the executable.
@@ -31,6 +36,8 @@ enum Output {
     Log,
     Signal,
     SignalGroup,
+    Supervise(SupervisorId),
+    StopSupervisor(SupervisorState),
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -50,6 +57,14 @@ enum Input {
     Signal(SendSignalParams),
     /// Signal a group of processes
     SignalGroup(SignalGroupParams),
+    /// Stream the output of the command, answered in [Handler::subscribe_output] rather than [Handler::handle]
+    SubscribeOutput(SubscribeOutputParams),
+    /// Run a command and restart it whenever it exits
+    Supervise(SuperviseParams),
+    /// Stream the state changes of a supervisor, answered in [Handler::subscribe_supervisor]
+    SubscribeSupervisor(SubscribeSupervisorParams),
+    /// Stop restarting a supervised command, and terminate it
+    StopSupervisor(StopSupervisorParams),
 }
 
 #[derive(Deserialize)]
@@ -69,10 +84,47 @@ struct InheritOutput {
     _thread: NonDetachingJoinHandle<()>,
     stdout: watch::Receiver<String>,
     stderr: watch::Receiver<String>,
+    events: Arc<std::sync::Mutex<EventLog>>,
+    exit: watch::Receiver<Option<ProcessExit>>,
+}
+
+const MAX_BACKLOG: usize = 1024;
+
+/// Output that has already happened, so that a subscriber who shows up late still sees it
+struct EventLog {
+    backlog: VecDeque<OutputEvent>,
+    send: broadcast::Sender<OutputEvent>,
+}
+impl EventLog {
+    fn new() -> Self {
+        EventLog {
+            backlog: VecDeque::new(),
+            send: broadcast::channel(MAX_BACKLOG).0,
+        }
+    }
+    fn push(&mut self, event: OutputEvent) {
+        if self.backlog.len() >= MAX_BACKLOG {
+            self.backlog.pop_front();
+        }
+        self.backlog.push_back(event.clone());
+        let _ = self.send.send(event);
+    }
+}
+
+/// How long a supervised command gets to exit after SIGTERM before it is killed
+const STOP_TIMEOUT: Duration = Duration::from_secs(30);
+
+struct SupervisorInfo {
+    gid: Option<ProcessGroupId>,
+    state: watch::Receiver<SupervisorState>,
+    stop: Option<oneshot::Sender<()>>,
+    _thread: NonDetachingJoinHandle<()>,
 }
 
 struct HandlerMut {
     processes: BTreeMap<ProcessId, ChildInfo>,
+    supervisors: BTreeMap<SupervisorId, SupervisorInfo>,
+    next_supervisor: u32,
     // groups: BTreeMap<ProcessGroupId, Cgroup>,
 }
 
@@ -85,18 +137,15 @@ impl Handler {
         Handler {
             children: Arc::new(Mutex::new(HandlerMut {
                 processes: BTreeMap::new(),
+                supervisors: BTreeMap::new(),
+                next_supervisor: 0,
                 // groups: BTreeMap::new(),
             })),
         }
     }
     async fn handle(&self, req: Input) -> Result<Output, RpcError> {
         Ok(match req {
-            Input::Command(RunCommandParams {
-                gid,
-                command,
-                args,
-                output,
-            }) => Output::Command(self.command(gid, command, args, output).await?),
+            Input::Command(params) => Output::Command(self.command(params).await?),
             // Input::ReadLineStdout(ReadLineStdoutParams { pid }) => {
             //     Output::ReadLineStdout(self.read_line_stdout(pid).await?)
             // }
@@ -116,19 +165,54 @@ impl Handler {
                 self.signal_group(gid, signal).await?;
                 Output::SignalGroup
             }
+            Input::Supervise(params) => Output::Supervise(self.supervise(params).await),
+            Input::StopSupervisor(StopSupervisorParams { id }) => {
+                Output::StopSupervisor(self.stop_supervisor(id).await?)
+            }
+            Input::SubscribeOutput(_) | Input::SubscribeSupervisor(_) => {
+                let mut err = yajrc::INVALID_REQUEST_ERROR.clone();
+                err.data = Some(json!("Subscriptions are not a request-response method"));
+                return Err(err);
+            }
         })
     }
 
     async fn command(
         &self,
-        gid: Option<ProcessGroupId>,
-        command: String,
-        args: Vec<String>,
-        output: OutputStrategy,
+        RunCommandParams {
+            gid,
+            command,
+            args,
+            output,
+            stdin,
+            env,
+            cwd,
+            uid,
+            group,
+        }: RunCommandParams,
     ) -> Result<ProcessId, RpcError> {
         let mut cmd = Command::new(command);
         cmd.args(args);
+        cmd.envs(env);
+        if let Some(cwd) = cwd {
+            cmd.current_dir(cwd);
+        }
+        if let Some(gid) = group.or_else(|| uid.map(primary_group)) {
+            cmd.gid(gid);
+        }
+        if let Some(uid) = uid {
+            // switching away from root also drops our supplementary groups
+            cmd.uid(uid);
+        } else if group.is_some() {
+            // staying root, so the supplementary groups have to be dropped by hand
+            unsafe {
+                cmd.pre_exec(|| nix::unistd::setgroups(&[]).map_err(std::io::Error::from));
+            }
+        }
         cmd.kill_on_drop(true);
+        if stdin.is_some() {
+            cmd.stdin(Stdio::piped());
+        }
         cmd.stdout(Stdio::piped());
         cmd.stderr(Stdio::piped());
         let mut child = cmd.spawn().map_err(|e| {
@@ -136,77 +220,98 @@ impl Handler {
             err.data = Some(json!(e.to_string()));
             err
         })?;
+        if let (Some(input), Some(mut child_stdin)) = (stdin, child.stdin.take()) {
+            tokio::spawn(async move {
+                if let Err(e) = child_stdin.write_all(input.as_bytes()).await {
+                    tracing::error!("Error writing stdin of command: {}", e);
+                }
+            });
+        }
         let pid = ProcessId(child.id().ok_or_else(|| {
             let mut err = yajrc::INTERNAL_ERROR.clone();
             err.data = Some(json!("Child has no pid"));
             err
         })?);
-        let output = match output {
-            OutputStrategy::Inherit => {
-                let (stdout_send, stdout) = watch::channel(String::new());
-                let (stderr_send, stderr) = watch::channel(String::new());
-                if let (Some(child_stdout), Some(child_stderr)) =
-                    (child.stdout.take(), child.stderr.take())
-                {
-                    Some(InheritOutput {
-                        _thread: tokio::spawn(async move {
-                            tokio::join!(
-                                async {
-                                    if let Err(e) = async {
-                                        let mut lines = BufReader::new(child_stdout).lines();
-                                        while let Some(line) = lines.next_line().await? {
-                                            tracing::info!("({}): {}", pid.0, line);
-                                            let _ = stdout_send.send(line);
-                                        }
-                                        Ok::<_, std::io::Error>(())
-                                    }
-                                    .await
-                                    {
-                                        tracing::error!(
-                                            "Error reading stdout of pid {}: {}",
-                                            pid.0,
-                                            e
-                                        );
-                                    }
-                                },
-                                async {
-                                    if let Err(e) = async {
-                                        let mut lines = BufReader::new(child_stderr).lines();
-                                        while let Some(line) = lines.next_line().await? {
-                                            tracing::warn!("({}): {}", pid.0, line);
-                                            let _ = stderr_send.send(line);
-                                        }
-                                        Ok::<_, std::io::Error>(())
-                                    }
-                                    .await
-                                    {
-                                        tracing::error!(
-                                            "Error reading stdout of pid {}: {}",
-                                            pid.0,
-                                            e
-                                        );
-                                    }
-                                }
-                            );
-                        })
-                        .into(),
-                        stdout,
-                        stderr,
-                    })
-                } else {
-                    None
-                }
-            }
+        let pipes = match output {
+            OutputStrategy::Inherit => child.stdout.take().zip(child.stderr.take()),
             OutputStrategy::Collect => None,
         };
-        self.children.lock().await.processes.insert(
-            pid,
-            ChildInfo {
-                gid,
-                child: Arc::new(Mutex::new(Some(child))),
-                output,
-            },
-        );
+        let child = Arc::new(Mutex::new(Some(child)));
+        let output = if let Some((child_stdout, child_stderr)) = pipes {
+            let (stdout_send, stdout) = watch::channel(String::new());
+            let (stderr_send, stderr) = watch::channel(String::new());
+            let (exit_send, exit) = watch::channel(None);
+            let events = Arc::new(std::sync::Mutex::new(EventLog::new()));
+            let thread_events = events.clone();
+            let thread_child = child.clone();
+            Some(InheritOutput {
+                _thread: tokio::spawn(async move {
+                    tokio::join!(
+                        async {
+                            if let Err(e) = async {
+                                let mut lines = BufReader::new(child_stdout).lines();
+                                while let Some(line) = lines.next_line().await? {
+                                    tracing::info!("({}): {}", pid.0, line);
+                                    thread_events
+                                        .lock()
+                                        .unwrap()
+                                        .push(OutputEvent::Stdout(line.clone()));
+                                    let _ = stdout_send.send(line);
+                                }
+                                Ok::<_, std::io::Error>(())
+                            }
+                            .await
+                            {
+                                tracing::error!("Error reading stdout of pid {}: {}", pid.0, e);
+                            }
+                        },
+                        async {
+                            if let Err(e) = async {
+                                let mut lines = BufReader::new(child_stderr).lines();
+                                while let Some(line) = lines.next_line().await? {
+                                    tracing::warn!("({}): {}", pid.0, line);
+                                    thread_events
+                                        .lock()
+                                        .unwrap()
+                                        .push(OutputEvent::Stderr(line.clone()));
+                                    let _ = stderr_send.send(line);
+                                }
+                                Ok::<_, std::io::Error>(())
+                            }
+                            .await
+                            {
+                                tracing::error!("Error reading stderr of pid {}: {}", pid.0, e);
+                            }
+                        }
+                    );
+                    let status = if let Some(child) = thread_child.lock().await.as_mut() {
+                        child.wait().await.ok()
+                    } else {
+                        None
+                    };
+                    if let Some(status) = status {
+                        let exit = ProcessExit {
+                            code: status.code(),
+                            signal: status.signal(),
+                        };
+                        thread_events.lock().unwrap().push(OutputEvent::Exit(exit));
+                        let _ = exit_send.send(Some(exit));
+                    }
+                })
+                .into(),
+                stdout,
+                stderr,
+                events,
+                exit,
+            })
+        } else {
+            None
+        };
+        self.children
+            .lock()
+            .await
+            .processes
+            .insert(pid, ChildInfo { gid, child, output });
         Ok(pid)
     }
 
@@ -216,17 +321,33 @@ impl Handler {
             err.data = Some(json!(format!("Child with pid {} not found", pid.0)));
             err
         };
-        let mut child = {
-            self.children
-                .lock()
-                .await.processes
-                .get(&pid)
-                .ok_or_else(not_found)?
-                .child
-                .clone()
+        let (child, inherited) = {
+            let children = self.children.lock().await;
+            let child_info = children.processes.get(&pid).ok_or_else(not_found)?;
+            (
+                child_info.child.clone(),
+                child_info
+                    .output
+                    .as_ref()
+                    .map(|output| (output.exit.clone(), output.stderr.clone())),
+            )
+        };
+        if let Some((exit, stderr)) = inherited {
+            let exit = wait_for_exit(exit).await.ok_or_else(not_found)?;
+            return if exit.success() {
+                Ok(String::new())
+            } else {
+                Err(RpcError {
+                    code: exit
+                        .code
+                        .or_else(|| exit.signal.map(|s| 128 + s))
+                        .unwrap_or(0),
+                    message: "Command failed".into(),
+                    data: Some(json!(stderr.borrow().clone())),
+                })
+            };
         }
-        .lock_owned()
-        .await;
+        let mut child = child.lock_owned().await;
         if let Some(child) = child.take() {
             let output = child.wait_with_output().await?;
             if output.status.success() {
@@ -252,6 +373,319 @@ impl Handler {
         }
     }
 
+    async fn subscribe_output(
+        &self,
+        id: Id,
+        pid: ProcessId,
+        w: &Mutex<OwnedWriteHalf>,
+    ) -> Result<(), std::io::Error> {
+        let subscription = {
+            let children = self.children.lock().await;
+            match children.processes.get(&pid) {
+                Some(ChildInfo {
+                    output: Some(output),
+                    ..
+                }) => {
+                    let events = output.events.lock().unwrap();
+                    Ok((events.backlog.clone(), events.send.subscribe()))
+                }
+                Some(_) => Err(format!("Output of pid {} is being collected", pid.0)),
+                None => Err(format!("Child with pid {} not found", pid.0)),
+            }
+        };
+        let (backlog, mut recv) = match subscription {
+            Ok(a) => a,
+            Err(e) => {
+                let mut err = yajrc::INTERNAL_ERROR.clone();
+                err.data = Some(json!(e));
+                return w
+                    .lock()
+                    .await
+                    .write_all(
+                        format!("{}\n", json!({ "id": id, "jsonrpc": "2.0", "error": err }))
+                            .as_bytes(),
+                    )
+                    .await;
+            }
+        };
+        let send = |event: OutputEvent| async move {
+            w.lock()
+                .await
+                .write_all(
+                    format!(
+                        "{}\n",
+                        json!({
+                            "jsonrpc": "2.0",
+                            "method": OUTPUT_EVENT,
+                            "params": OutputEventParams { pid, event },
+                        })
+                    )
+                    .as_bytes(),
+                )
+                .await
+        };
+        w.lock()
+            .await
+            .write_all(
+                format!(
+                    "{}\n",
+                    json!({ "id": id, "jsonrpc": "2.0", "result": null })
+                )
+                .as_bytes(),
+            )
+            .await?;
+        let mut exited = false;
+        for event in backlog {
+            exited = matches!(event, OutputEvent::Exit(_));
+            send(event).await?;
+        }
+        while !exited {
+            match recv.recv().await {
+                Ok(event) => {
+                    exited = matches!(event, OutputEvent::Exit(_));
+                    send(event).await?;
+                }
+                Err(broadcast::error::RecvError::Lagged(n)) => {
+                    tracing::warn!("Output subscriber for pid {} missed {} lines", pid.0, n);
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            }
+        }
+        w.lock().await.shutdown().await
+    }
+
+    async fn supervise(&self, params: SuperviseParams) -> SupervisorId {
+        let (state_send, state) = watch::channel(SupervisorState::Starting);
+        let (stop_send, stop) = oneshot::channel();
+        let gid = params.gid;
+        // held until the entry is inserted, so the supervisor cannot finish and remove it first
+        let mut children = self.children.lock().await;
+        let id = SupervisorId(children.next_supervisor);
+        children.next_supervisor += 1;
+        let handler = self.clone();
+        let thread = tokio::spawn(async move {
+            let children = handler.children.clone();
+            handler.run_supervisor(params, state_send, stop).await;
+            // the state is final, and whoever is waiting on it already has a receiver
+            children.lock().await.supervisors.remove(&id);
+        });
+        children.supervisors.insert(
+            id,
+            SupervisorInfo {
+                gid,
+                state,
+                stop: Some(stop_send),
+                _thread: thread.into(),
+            },
+        );
+        id
+    }
+
+    async fn run_supervisor(
+        self,
+        SuperviseParams {
+            gid,
+            command,
+            args,
+            env,
+            cwd,
+            uid,
+            group,
+            policy,
+        }: SuperviseParams,
+        state: watch::Sender<SupervisorState>,
+        mut stop: oneshot::Receiver<()>,
+    ) {
+        let mut restarts = 0;
+        loop {
+            let started = Instant::now();
+            let pid = match self
+                .command(RunCommandParams {
+                    gid,
+                    command: command.clone(),
+                    args: args.clone(),
+                    output: OutputStrategy::Inherit,
+                    stdin: None,
+                    env: env.clone(),
+                    cwd: cwd.clone(),
+                    uid,
+                    group,
+                })
+                .await
+            {
+                Ok(pid) => pid,
+                Err(e) => {
+                    let _ = state.send(SupervisorState::Failed {
+                        exit: None,
+                        restarts,
+                        error: Some(format!("{}: {:?}", e.message, e.data)),
+                    });
+                    return;
+                }
+            };
+            let _ = state.send(SupervisorState::Running { pid, restarts });
+            let exit = select! {
+                exit = self.wait_exit(pid) => exit,
+                _ = &mut stop => {
+                    let _ = Self::killall(pid, Signal::SIGTERM);
+                    if tokio::time::timeout(STOP_TIMEOUT, self.wait_exit(pid)).await.is_err() {
+                        tracing::warn!("Supervised pid {} did not stop in time, killing it", pid.0);
+                        let _ = Self::killall(pid, Signal::SIGKILL);
+                        self.wait_exit(pid).await;
+                    }
+                    self.children.lock().await.processes.remove(&pid);
+                    let _ = state.send(SupervisorState::Stopped { restarts });
+                    return;
+                }
+            };
+            self.children.lock().await.processes.remove(&pid);
+            let exit = exit.unwrap_or(ProcessExit {
+                code: None,
+                signal: None,
+            });
+            if exit.success() && !policy.restart_on_success {
+                let _ = state.send(SupervisorState::Exited { exit, restarts });
+                return;
+            }
+            // a run that outlasted the longest backoff was stable, so this is a fresh failure
+            if started.elapsed() >= Duration::from_millis(policy.max_backoff) {
+                restarts = 0;
+            }
+            if policy.max_restarts.map_or(false, |max| restarts >= max) {
+                tracing::error!("{} failed after {} restarts, giving up", command, restarts);
+                let _ = state.send(SupervisorState::Failed {
+                    exit: Some(exit),
+                    restarts,
+                    error: None,
+                });
+                return;
+            }
+            let delay = policy.delay(restarts);
+            tracing::warn!(
+                "{} exited with {:?}, restarting in {:?}",
+                command,
+                exit,
+                delay
+            );
+            let _ = state.send(SupervisorState::BackingOff {
+                exit,
+                restarts,
+                delay: delay.as_millis() as u64,
+            });
+            select! {
+                _ = tokio::time::sleep(delay) => (),
+                _ = &mut stop => {
+                    let _ = state.send(SupervisorState::Stopped { restarts });
+                    return;
+                }
+            }
+            restarts += 1;
+        }
+    }
+
+    /// None if the process is not known or its output was collected rather than inherited
+    async fn wait_exit(&self, pid: ProcessId) -> Option<ProcessExit> {
+        let exit = self
+            .children
+            .lock()
+            .await
+            .processes
+            .get(&pid)
+            .and_then(|child_info| child_info.output.as_ref())
+            .map(|output| output.exit.clone())?;
+        wait_for_exit(exit).await
+    }
+
+    async fn stop_supervisor(&self, id: SupervisorId) -> Result<SupervisorState, RpcError> {
+        let (stop, state) = {
+            let mut children = self.children.lock().await;
+            let supervisor = children.supervisors.get_mut(&id).ok_or_else(|| {
+                let mut err = yajrc::INTERNAL_ERROR.clone();
+                err.data = Some(json!(format!("Supervisor {} not found", id.0)));
+                err
+            })?;
+            (supervisor.stop.take(), supervisor.state.clone())
+        };
+        if let Some(stop) = stop {
+            let _ = stop.send(());
+        }
+        Ok(wait_for_final(state).await)
+    }
+
+    async fn subscribe_supervisor(
+        &self,
+        id: Id,
+        supervisor: SupervisorId,
+        w: &Mutex<OwnedWriteHalf>,
+    ) -> Result<(), std::io::Error> {
+        let state = self
+            .children
+            .lock()
+            .await
+            .supervisors
+            .get(&supervisor)
+            .map(|info| info.state.clone());
+        let mut state = match state {
+            Some(a) => a,
+            None => {
+                let mut err = yajrc::INTERNAL_ERROR.clone();
+                err.data = Some(json!(format!("Supervisor {} not found", supervisor.0)));
+                return w
+                    .lock()
+                    .await
+                    .write_all(
+                        format!("{}\n", json!({ "id": id, "jsonrpc": "2.0", "error": err }))
+                            .as_bytes(),
+                    )
+                    .await;
+            }
+        };
+        w.lock()
+            .await
+            .write_all(
+                format!(
+                    "{}\n",
+                    json!({ "id": id, "jsonrpc": "2.0", "result": null })
+                )
+                .as_bytes(),
+            )
+            .await?;
+        loop {
+            let current = state.borrow_and_update().clone();
+            let is_final = current.is_final();
+            w.lock()
+                .await
+                .write_all(
+                    format!(
+                        "{}\n",
+                        json!({
+                            "jsonrpc": "2.0",
+                            "method": SUPERVISOR_EVENT,
+                            "params": SupervisorEventParams { id: supervisor, state: current },
+                        })
+                    )
+                    .as_bytes(),
+                )
+                .await?;
+            if is_final || state.changed().await.is_err() {
+                break;
+            }
+        }
+        w.lock().await.shutdown().await
+    }
+
+    /// Keeps signal_group from racing the supervisors of the group, which would restart what it kills
+    async fn stop_supervisors(&self, gid: Option<ProcessGroupId>) {
+        let mut children = self.children.lock().await;
+        for supervisor in children.supervisors.values_mut() {
+            if gid.is_none() || supervisor.gid == gid {
+                if let Some(stop) = supervisor.stop.take() {
+                    let _ = stop.send(());
+                }
+            }
+        }
+    }
+
     async fn signal(&self, pid: ProcessId, signal: u32) -> Result<(), RpcError> {
         let not_found = || {
             let mut err = yajrc::INTERNAL_ERROR.clone();
@@ -264,7 +698,8 @@ impl Handler {
         if signal == 9 {
             self.children
                 .lock()
-                .await.processes
+                .await
+                .processes
                 .remove(&pid)
                 .ok_or_else(not_found)?;
         }
@@ -272,6 +707,7 @@ impl Handler {
     }
 
     async fn signal_group(&self, gid: ProcessGroupId, signal: u32) -> Result<(), RpcError> {
+        self.stop_supervisors(Some(gid)).await;
         let mut to_kill = Vec::new();
         {
             let mut children_ref = self.children.lock().await;
@@ -308,6 +744,7 @@ impl Handler {
     }
 
     async fn graceful_exit(self) {
+        self.stop_supervisors(None).await;
         let kill_all = futures::stream::iter(
             std::mem::take(&mut self.children.lock().await.deref_mut().processes).into_iter(),
         )
@@ -321,6 +758,33 @@ impl Handler {
     }
 }
 
+/// The primary group of the user `uid` in the container, or the group with the same id if there is no such user
+fn primary_group(uid: u32) -> u32 {
+    match nix::unistd::User::from_uid(nix::unistd::Uid::from_raw(uid)) {
+        Ok(Some(user)) => user.gid.as_raw(),
+        _ => uid,
+    }
+}
+
+async fn wait_for_exit(mut exit: watch::Receiver<Option<ProcessExit>>) -> Option<ProcessExit> {
+    loop {
+        let current = *exit.borrow();
+        if current.is_some() {
+            return current;
+        }
+        exit.changed().await.ok()?;
+    }
+}
+
+async fn wait_for_final(mut state: watch::Receiver<SupervisorState>) -> SupervisorState {
+    loop {
+        let current = state.borrow().clone();
+        if current.is_final() || state.changed().await.is_err() {
+            return current;
+        }
+    }
+}
+
 #[tokio::main]
 async fn main() {
     use tokio::signal::unix::{signal, SignalKind};
@@ -354,12 +818,19 @@ async fn main() {
             tokio::spawn(async move {
                 let w = Arc::new(Mutex::new(w));
                 while let Some(line) = lines.next_line().await.transpose() {
-                    
                     let handler = handler.clone();
                     let w = w.clone();
                     tokio::spawn(async move {
                         if let Err(e) = async {
                             let req = serde_json::from_str::<IncomingRpc>(&line?)?;
+                            if let Input::SubscribeOutput(SubscribeOutputParams { pid }) = req.input {
+                                handler.subscribe_output(req.id, pid, &*w).await?;
+                                return Ok(());
+                            }
+                            if let Input::SubscribeSupervisor(SubscribeSupervisorParams { id }) = req.input {
+                                handler.subscribe_supervisor(req.id, id, &*w).await?;
+                                return Ok(());
+                            }
                             match handler.handle(req.input).await {
                                 Ok(output) => {
                                     if let Err(err) = w.lock().await.write_all(
@@ -370,7 +841,7 @@ async fn main() {
                                         tracing::error!("Error sending to {id:?}", id = req.id);
                                     }
                                 }
-                                Err(e) => 
+                                Err(e) =>
                                 if let Err(err) = w
                                     .lock()
                                     .await
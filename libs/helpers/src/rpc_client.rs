@@ -4,7 +4,8 @@ use std::sync::atomic::AtomicUsize;
 use std::sync::{Arc, Weak};
 
 use futures::future::BoxFuture;
-use futures::{FutureExt, TryFutureExt};
+use futures::stream::BoxStream;
+use futures::{FutureExt, StreamExt, TryFutureExt};
 use lazy_async_pool::Pool;
 use models::{Error, ErrorKind, ResultExt};
 use serde::{Deserialize, Serialize};
@@ -13,6 +14,7 @@ use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader
 use tokio::net::UnixStream;
 use tokio::runtime::Handle;
 use tokio::sync::{oneshot, Mutex};
+use tokio_stream::wrappers::LinesStream;
 use yajrc::{Id, RpcError, RpcMethod, RpcRequest, RpcResponse};
 
 use crate::NonDetachingJoinHandle;
@@ -132,6 +134,7 @@ impl RpcClient {
 }
 
 pub struct UnixRpcClient {
+    path: PathBuf,
     pool: Pool<
         RpcClient,
         Box<dyn Fn() -> BoxFuture<'static, Result<RpcClient, std::io::Error>> + Send + Sync>,
@@ -144,6 +147,7 @@ impl UnixRpcClient {
         let rt = Handle::current();
         let id = Arc::new(AtomicUsize::new(0));
         Self {
+            path: path.clone(),
             pool: Pool::new(
                 0,
                 Box::new(move || {
@@ -189,4 +193,51 @@ impl UnixRpcClient {
         };
         res
     }
+
+    /// Subscriptions get a connection of their own: the server answers the request, then sends
+    /// notifications until it closes the connection
+    pub async fn subscribe<T: RpcMethod, N: for<'de> Deserialize<'de> + Send + 'static>(
+        &self,
+        method: T,
+        params: T::Params,
+    ) -> Result<BoxStream<'static, Result<N, RpcError>>, RpcError>
+    where
+        T: Serialize,
+        T::Params: Serialize,
+    {
+        #[derive(Deserialize)]
+        struct Notification<N> {
+            params: N,
+        }
+        let io_err = |e: std::io::Error| {
+            let mut err = yajrc::INTERNAL_ERROR.clone();
+            err.data = Some(json!(e.to_string()));
+            err
+        };
+        let (r, mut w) = UnixStream::connect(&self.path)
+            .await
+            .map_err(io_err)?
+            .into_split();
+        let request = RpcRequest {
+            id: Some(Id::Number(0usize.into())),
+            method,
+            params,
+        };
+        w.write_all((serde_json::to_string(&request)? + "\n").as_bytes())
+            .await
+            .map_err(io_err)?;
+        let mut lines = BufReader::new(r).lines();
+        let response = lines.next_line().await.map_err(io_err)?.ok_or_else(|| {
+            let mut err = yajrc::INTERNAL_ERROR.clone();
+            err.data = Some(json!("Connection closed before subscribing"));
+            err
+        })?;
+        serde_json::from_str::<RpcResponse>(&response)?.result?;
+        Ok(LinesStream::new(lines)
+            .map(move |line| {
+                let _writer = &w;
+                Ok(serde_json::from_str::<Notification<N>>(&line.map_err(io_err)?)?.params)
+            })
+            .boxed())
+    }
 }
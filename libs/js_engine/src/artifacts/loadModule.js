@@ -40,10 +40,27 @@ const readFile = (
 
 
 
+const outputEvents = (processId) => ({
+  [Symbol.asyncIterator]() {
+    let id = null;
+    return {
+      async next() {
+        id = id || Deno.core.opAsync("output_subscribe", await processId);
+        const value = await Deno.core.opAsync("output_next", await id);
+        return value == null ? { done: true, value: undefined } : { done: false, value };
+      },
+      async return() {
+        if (id) await Deno.core.opAsync("output_close", await id);
+        return { done: true, value: undefined };
+      },
+    };
+  },
+});
+
 const runDaemon = (
-  { command = requireParam("command"), args = [] } = requireParam("options"),
+  { command = requireParam("command"), args = [], stdin = null, env = {}, cwd = null, uid = null, gid = null } = requireParam("options"),
 ) => {
-  let id = Deno.core.opAsync("start_command", command, args, "inherit", null);
+  let id = Deno.core.opAsync("start_command", command, args, "inherit", null, { stdin, env, cwd, uid, gid });
   let processId = id.then(x => x.processId)
   let waitPromise = null;
   return {
@@ -54,13 +71,47 @@ const runDaemon = (
     },
     async term(signal = 15) {
       return Deno.core.opAsync("send_signal", await processId, 15)
+    },
+    output() {
+      return outputEvents(processId)
+    }
+  }
+};
+const supervisorStates = (supervisorId) => ({
+  [Symbol.asyncIterator]() {
+    let id = null;
+    return {
+      async next() {
+        id = id || Deno.core.opAsync("supervisor_subscribe", await supervisorId);
+        const value = await Deno.core.opAsync("supervisor_next", await id);
+        return value == null ? { done: true, value: undefined } : { done: false, value };
+      },
+      async return() {
+        if (id) await Deno.core.opAsync("supervisor_close", await id);
+        return { done: true, value: undefined };
+      },
+    };
+  },
+});
+
+const supervise = (
+  { command = requireParam("command"), args = [], env = {}, cwd = null, uid = null, gid = null, policy = {} } = requireParam("options"),
+) => {
+  let id = Deno.core.opAsync("supervise", command, args, { env, cwd, uid, gid }, policy);
+  return {
+    id,
+    async stop() {
+      return Deno.core.opAsync("supervisor_stop", await id)
+    },
+    states() {
+      return supervisorStates(id)
     }
   }
 };
 const runCommand = async (
-  { command = requireParam("command"), args = [], timeoutMillis = 30000 } = requireParam("options"),
+  { command = requireParam("command"), args = [], timeoutMillis = 30000, stdin = null, env = {}, cwd = null, uid = null, gid = null } = requireParam("options"),
 ) => {
-  let id = Deno.core.opAsync("start_command", command, args, "collect", timeoutMillis);
+  let id = Deno.core.opAsync("start_command", command, args, "collect", timeoutMillis, { stdin, env, cwd, uid, gid });
   let pid = id.then(x => x.processId)
   return Deno.core.opAsync("wait_command", await pid)
 };
@@ -165,6 +216,85 @@ const runRsync = (
   }
 };
 
+const storeGet = (
+  { key = requireParam("key") } = requireParam("options"),
+) => Deno.core.opAsync("store_get", key);
+const storeSet = (
+  { key = requireParam("key"), value = requireParam("value") } = requireParam("options"),
+) => Deno.core.opAsync("store_set", key, value);
+const storeDelete = (
+  { key = requireParam("key") } = requireParam("options"),
+) => Deno.core.opAsync("store_delete", key);
+const storeList = (
+  { prefix = "" } = {},
+) => Deno.core.opAsync("store_list", prefix);
+const storeCompareAndSwap = (
+  { key = requireParam("key"), expected = null, value = null } = requireParam("options"),
+) => Deno.core.opAsync("store_compare_and_swap", key, expected, value);
+
+const getDependencyConfig = (
+  { packageId = requireParam("packageId"), selector = "$", multi = false } = requireParam("options"),
+) => Deno.core.opAsync("read_pointer", { "package-id": packageId, target: "config", selector, multi });
+const getDependencyTorAddress = (
+  { packageId = requireParam("packageId"), interface: iface = requireParam("interface") } = requireParam("options"),
+) => Deno.core.opAsync("read_pointer", { "package-id": packageId, target: "tor-address", interface: iface });
+const getDependencyLanAddress = (
+  { packageId = requireParam("packageId"), interface: iface = requireParam("interface") } = requireParam("options"),
+) => Deno.core.opAsync("read_pointer", { "package-id": packageId, target: "lan-address", interface: iface });
+const getDependencyProperties = (
+  { packageId = requireParam("packageId") } = requireParam("options"),
+) => Deno.core.opAsync("dependency_properties", packageId);
+
+const encode = (
+  { data = requireParam("data"), from = "utf8", to = "base64" } = requireParam("options"),
+) => Deno.core.opSync("encode", { data, from, to });
+const randomBytes = (
+  { length = requireParam("length"), encoding = "hex" } = requireParam("options"),
+) => Deno.core.opSync("random_bytes", length, encoding);
+const hash = (
+  { algorithm = "sha256", data = requireParam("data"), encoding = "utf8", outputEncoding = "hex" } = requireParam("options"),
+) => Deno.core.opSync("hash", { algorithm, data, encoding, outputEncoding });
+const hmac = (
+  {
+    algorithm = "sha256",
+    key = requireParam("key"),
+    keyEncoding = "utf8",
+    data = requireParam("data"),
+    encoding = "utf8",
+    outputEncoding = "hex",
+  } = requireParam("options"),
+) => Deno.core.opSync("hmac", { algorithm, key, keyEncoding, data, encoding, outputEncoding });
+const argon2Hash = (
+  { password = requireParam("password") } = requireParam("options"),
+) => Deno.core.opAsync("argon2_hash", password);
+const argon2Verify = (
+  { hash = requireParam("hash"), password = requireParam("password") } = requireParam("options"),
+) => Deno.core.opAsync("argon2_verify", hash, password);
+const generateKeyPair = (
+  { algorithm = requireParam("algorithm"), encoding = "hex" } = requireParam("options"),
+) => Deno.core.opSync("generate_key_pair", algorithm, encoding);
+const sign = (
+  {
+    algorithm = requireParam("algorithm"),
+    secretKey = requireParam("secretKey"),
+    keyEncoding = "hex",
+    data = requireParam("data"),
+    encoding = "utf8",
+    outputEncoding = "hex",
+  } = requireParam("options"),
+) => Deno.core.opSync("sign", { algorithm, secretKey, keyEncoding, data, encoding, outputEncoding });
+const verify = (
+  {
+    algorithm = requireParam("algorithm"),
+    publicKey = requireParam("publicKey"),
+    keyEncoding = "hex",
+    data = requireParam("data"),
+    encoding = "utf8",
+    signature = requireParam("signature"),
+    signatureEncoding = "hex",
+  } = requireParam("options"),
+) => Deno.core.opSync("verify", { algorithm, publicKey, keyEncoding, data, encoding, signature, signatureEncoding });
+
 const currentFunction = Deno.core.opSync("current_function");
 const input = Deno.core.opSync("get_input");
 const variable_args = Deno.core.opSync("get_variable_args");
@@ -189,9 +319,28 @@ const effects = {
   runCommand,
   sleep,
   runDaemon,
+  supervise,
   signalGroup,
   runRsync,
-  readDir
+  readDir,
+  storeGet,
+  storeSet,
+  storeDelete,
+  storeList,
+  storeCompareAndSwap,
+  getDependencyConfig,
+  getDependencyTorAddress,
+  getDependencyLanAddress,
+  getDependencyProperties,
+  encode,
+  randomBytes,
+  hash,
+  hmac,
+  argon2Hash,
+  argon2Verify,
+  generateKeyPair,
+  sign,
+  verify,
 };
 
 const defaults = {
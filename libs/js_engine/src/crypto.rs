@@ -0,0 +1,327 @@
+//! Hashing, signing and encoding for package scripts, since V8 gives us no Web Crypto
+use deno_core::anyhow::{anyhow, bail};
+use deno_core::error::AnyError;
+use deno_core::op;
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256, Sha384, Sha512};
+
+#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Encoding {
+    Utf8,
+    Hex,
+    Base64,
+    Base64url,
+    Base32,
+}
+impl Encoding {
+    fn decode(&self, data: &str) -> Result<Vec<u8>, AnyError> {
+        Ok(match self {
+            Encoding::Utf8 => data.as_bytes().to_vec(),
+            Encoding::Hex => hex::decode(data)?,
+            Encoding::Base64 => base64::decode(data)?,
+            Encoding::Base64url => base64::decode_config(data, base64::URL_SAFE_NO_PAD)?,
+            Encoding::Base32 => base32::decode(
+                base32::Alphabet::RFC4648 { padding: false },
+                data.trim_end_matches('='),
+            )
+            .ok_or_else(|| anyhow!("Invalid base32"))?,
+        })
+    }
+    fn encode(&self, data: &[u8]) -> Result<String, AnyError> {
+        Ok(match self {
+            Encoding::Utf8 => String::from_utf8(data.to_vec())?,
+            Encoding::Hex => hex::encode(data),
+            Encoding::Base64 => base64::encode(data),
+            Encoding::Base64url => base64::encode_config(data, base64::URL_SAFE_NO_PAD),
+            Encoding::Base32 => {
+                base32::encode(base32::Alphabet::RFC4648 { padding: false }, data).to_lowercase()
+            }
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HashAlgorithm {
+    Sha256,
+    Sha384,
+    Sha512,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum KeyAlgorithm {
+    Ed25519,
+    Secp256k1,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EncodeParams {
+    data: String,
+    from: Encoding,
+    to: Encoding,
+}
+#[op]
+fn encode(params: EncodeParams) -> Result<String, AnyError> {
+    params.to.encode(&params.from.decode(&params.data)?)
+}
+
+#[op]
+fn random_bytes(length: usize, encoding: Encoding) -> Result<String, AnyError> {
+    if length > 1024 * 1024 {
+        bail!("Will not generate more than 1MiB of random bytes");
+    }
+    let mut buf = vec![0; length];
+    rand::rngs::OsRng.fill_bytes(&mut buf);
+    encoding.encode(&buf)
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HashParams {
+    algorithm: HashAlgorithm,
+    data: String,
+    encoding: Encoding,
+    output_encoding: Encoding,
+}
+#[op]
+fn hash(params: HashParams) -> Result<String, AnyError> {
+    let data = params.encoding.decode(&params.data)?;
+    params.output_encoding.encode(&match params.algorithm {
+        HashAlgorithm::Sha256 => Sha256::digest(&data).to_vec(),
+        HashAlgorithm::Sha384 => Sha384::digest(&data).to_vec(),
+        HashAlgorithm::Sha512 => Sha512::digest(&data).to_vec(),
+    })
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HmacParams {
+    algorithm: HashAlgorithm,
+    key: String,
+    key_encoding: Encoding,
+    data: String,
+    encoding: Encoding,
+    output_encoding: Encoding,
+}
+#[op]
+fn hmac(params: HmacParams) -> Result<String, AnyError> {
+    fn mac<M: Mac + hmac::digest::KeyInit>(key: &[u8], data: &[u8]) -> Result<Vec<u8>, AnyError> {
+        let mut mac = <M as Mac>::new_from_slice(key)?;
+        mac.update(data);
+        Ok(mac.finalize().into_bytes().to_vec())
+    }
+    let key = params.key_encoding.decode(&params.key)?;
+    let data = params.encoding.decode(&params.data)?;
+    params.output_encoding.encode(&match params.algorithm {
+        HashAlgorithm::Sha256 => mac::<Hmac<Sha256>>(&key, &data)?,
+        HashAlgorithm::Sha384 => mac::<Hmac<Sha384>>(&key, &data)?,
+        HashAlgorithm::Sha512 => mac::<Hmac<Sha512>>(&key, &data)?,
+    })
+}
+
+/// Returns the hash in the PHC string format, which carries its own salt and parameters
+#[op]
+async fn argon2_hash(password: String) -> Result<String, AnyError> {
+    tokio::task::spawn_blocking(move || {
+        let mut salt = [0; 16];
+        rand::rngs::OsRng.fill_bytes(&mut salt);
+        argon2::hash_encoded(password.as_bytes(), &salt, &argon2::Config::default())
+    })
+    .await?
+    .map_err(|e| anyhow!("{}", e))
+}
+
+#[op]
+async fn argon2_verify(hash: String, password: String) -> Result<bool, AnyError> {
+    tokio::task::spawn_blocking(move || argon2::verify_encoded(&hash, password.as_bytes()))
+        .await?
+        .map_err(|e| anyhow!("{}", e))
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct KeyPair {
+    public_key: String,
+    secret_key: String,
+}
+#[op]
+fn generate_key_pair(algorithm: KeyAlgorithm, encoding: Encoding) -> Result<KeyPair, AnyError> {
+    let (public, secret) = match algorithm {
+        KeyAlgorithm::Ed25519 => {
+            let mut bytes = [0; ed25519_dalek::SECRET_KEY_LENGTH];
+            rand::rngs::OsRng.fill_bytes(&mut bytes);
+            let secret = ed25519_dalek::SecretKey::from_bytes(&bytes)?;
+            let public = ed25519_dalek::PublicKey::from(&secret);
+            (public.to_bytes().to_vec(), secret.to_bytes().to_vec())
+        }
+        KeyAlgorithm::Secp256k1 => secp256k1::generate()?,
+    };
+    Ok(KeyPair {
+        public_key: encoding.encode(&public)?,
+        secret_key: encoding.encode(&secret)?,
+    })
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SignParams {
+    algorithm: KeyAlgorithm,
+    secret_key: String,
+    key_encoding: Encoding,
+    data: String,
+    encoding: Encoding,
+    output_encoding: Encoding,
+}
+/// secp256k1 signatures are ECDSA over the SHA-256 of the data, in the 64 byte `r || s` form
+#[op]
+fn sign(params: SignParams) -> Result<String, AnyError> {
+    let secret = params.key_encoding.decode(&params.secret_key)?;
+    let data = params.encoding.decode(&params.data)?;
+    params.output_encoding.encode(&match params.algorithm {
+        KeyAlgorithm::Ed25519 => {
+            use ed25519_dalek::Signer;
+            let secret = ed25519_dalek::SecretKey::from_bytes(&secret)?;
+            let public = ed25519_dalek::PublicKey::from(&secret);
+            ed25519_dalek::Keypair { secret, public }
+                .sign(&data)
+                .to_bytes()
+                .to_vec()
+        }
+        KeyAlgorithm::Secp256k1 => secp256k1::sign(&secret, &data)?,
+    })
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct VerifyParams {
+    algorithm: KeyAlgorithm,
+    public_key: String,
+    key_encoding: Encoding,
+    data: String,
+    encoding: Encoding,
+    signature: String,
+    signature_encoding: Encoding,
+}
+#[op]
+fn verify(params: VerifyParams) -> Result<bool, AnyError> {
+    let public = params.key_encoding.decode(&params.public_key)?;
+    let data = params.encoding.decode(&params.data)?;
+    let signature = params.signature_encoding.decode(&params.signature)?;
+    Ok(match params.algorithm {
+        KeyAlgorithm::Ed25519 => {
+            use ed25519_dalek::Verifier;
+            let signature = ed25519_dalek::Signature::try_from(&signature[..])?;
+            ed25519_dalek::PublicKey::from_bytes(&public)?
+                .verify(&data, &signature)
+                .is_ok()
+        }
+        KeyAlgorithm::Secp256k1 => secp256k1::verify(&public, &data, &signature)?,
+    })
+}
+
+/// secp256k1 through openssl, which the backend already links
+mod secp256k1 {
+    use deno_core::anyhow::bail;
+    use deno_core::error::AnyError;
+    use openssl::bn::{BigNum, BigNumContext};
+    use openssl::ec::{EcGroup, EcKey, EcPoint, PointConversionForm};
+    use openssl::ecdsa::EcdsaSig;
+    use openssl::nid::Nid;
+    use openssl::sha::sha256;
+
+    const SCALAR_LENGTH: usize = 32;
+
+    fn group() -> Result<EcGroup, AnyError> {
+        Ok(EcGroup::from_curve_name(Nid::SECP256K1)?)
+    }
+
+    /// The compressed SEC1 public key and the secret key of a new key pair
+    pub fn generate() -> Result<(Vec<u8>, Vec<u8>), AnyError> {
+        let group = group()?;
+        let key = EcKey::generate(&group)?;
+        let mut ctx = BigNumContext::new()?;
+        Ok((
+            key.public_key()
+                .to_bytes(&group, PointConversionForm::COMPRESSED, &mut ctx)?,
+            key.private_key().to_vec_padded(SCALAR_LENGTH as i32)?,
+        ))
+    }
+
+    pub fn sign(secret: &[u8], data: &[u8]) -> Result<Vec<u8>, AnyError> {
+        if secret.len() != SCALAR_LENGTH {
+            bail!("Invalid secp256k1 secret key length");
+        }
+        let group = group()?;
+        let mut ctx = BigNumContext::new()?;
+        let secret = BigNum::from_slice(secret)?;
+        let mut public = EcPoint::new(&group)?;
+        public.mul_generator(&group, &secret, &ctx)?;
+        let key = EcKey::from_private_components(&group, &secret, &public)?;
+        key.check_key()?;
+        let signature = EcdsaSig::sign(&sha256(data), &key)?;
+        // the low s form, which bitcoin requires and other secp256k1 libraries produce
+        let mut order = BigNum::new()?;
+        group.order(&mut order, &mut ctx)?;
+        let mut half_order = BigNum::new()?;
+        half_order.rshift1(&order)?;
+        let mut s = signature.s().to_owned()?;
+        if s > half_order {
+            s.checked_sub(&order, signature.s())?;
+        }
+        let mut bytes = signature.r().to_vec_padded(SCALAR_LENGTH as i32)?;
+        bytes.extend(s.to_vec_padded(SCALAR_LENGTH as i32)?);
+        Ok(bytes)
+    }
+
+    pub fn verify(public: &[u8], data: &[u8], signature: &[u8]) -> Result<bool, AnyError> {
+        if signature.len() != 2 * SCALAR_LENGTH {
+            bail!("Invalid secp256k1 signature length");
+        }
+        let group = group()?;
+        let mut ctx = BigNumContext::new()?;
+        let public =
+            EcKey::from_public_key(&group, &EcPoint::from_bytes(&group, public, &mut ctx)?)?;
+        let (r, s) = signature.split_at(SCALAR_LENGTH);
+        let signature =
+            EcdsaSig::from_private_components(BigNum::from_slice(r)?, BigNum::from_slice(s)?)?;
+        Ok(signature.verify(&sha256(data), &public)?)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn encodings_round_trip() {
+        let data = b"embassy\x00\xff";
+        for encoding in [
+            Encoding::Hex,
+            Encoding::Base64,
+            Encoding::Base64url,
+            Encoding::Base32,
+        ] {
+            let encoded = encoding.encode(data).unwrap();
+            assert_eq!(encoding.decode(&encoded).unwrap(), data, "{:?}", encoding);
+        }
+        assert_eq!(Encoding::Hex.encode(b"\x01\xab").unwrap(), "01ab");
+        assert_eq!(Encoding::Base64.encode(b"hi").unwrap(), "aGk=");
+        assert_eq!(Encoding::Base32.encode(b"hi").unwrap(), "nbuq");
+    }
+
+    #[test]
+    fn secp256k1_round_trip() {
+        let (public, secret) = secp256k1::generate().unwrap();
+        assert_eq!(public.len(), 33);
+        assert_eq!(secret.len(), 32);
+        let signature = secp256k1::sign(&secret, b"embassy").unwrap();
+        assert_eq!(signature.len(), 64);
+        assert!(secp256k1::verify(&public, b"embassy", &signature).unwrap());
+        assert!(!secp256k1::verify(&public, b"embassy!", &signature).unwrap());
+    }
+}
@@ -10,14 +10,16 @@ use deno_core::{
     resolve_import, Extension, JsRuntime, ModuleLoader, ModuleSource, ModuleSourceFuture,
     ModuleSpecifier, ModuleType, OpDecl, RuntimeOptions, Snapshot,
 };
-use embassy_container_init::ProcessGroupId;
+use embassy_container_init::{OutputEvent, ProcessGroupId, SupervisorState};
 use helpers::{script_dir, spawn_local, Rsync, UnixRpcClient};
-use models::{PackageId, ProcedureName, Version, VolumeId};
+use models::{Error, PackageId, ProcedureName, Version, VolumeId};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use tokio::io::AsyncReadExt;
 use tokio::sync::Mutex;
 
+mod crypto;
+
 pub trait PathForVolumeId: Send + Sync {
     fn path_for(
         &self,
@@ -29,6 +31,26 @@ pub trait PathForVolumeId: Send + Sync {
     fn readonly(&self, volume_id: &VolumeId) -> bool;
 }
 
+/// Services that the host provides to a running script, beyond the volumes it can touch.
+#[async_trait::async_trait]
+pub trait OsApi: Send + Sync {
+    async fn store_get(&self, key: &str) -> Result<Option<Value>, Error>;
+    async fn store_set(&self, key: &str, value: Value) -> Result<(), Error>;
+    /// Returns whether the key was present
+    async fn store_delete(&self, key: &str) -> Result<bool, Error>;
+    async fn store_list(&self, prefix: &str) -> Result<BTreeMap<String, Value>, Error>;
+    /// Sets the key to `new` (or removes it if `None`) only if its current value is `expected`
+    async fn store_compare_and_swap(
+        &self,
+        key: &str,
+        expected: Option<Value>,
+        new: Option<Value>,
+    ) -> Result<bool, Error>;
+    /// Resolves a package pointer, in the same shape as a config spec pointer, on behalf of the script's package
+    async fn read_pointer(&self, pointer: Value) -> Result<Value, Error>;
+    async fn dependency_properties(&self, package_id: &PackageId) -> Result<Value, Error>;
+}
+
 #[derive(Serialize, Deserialize, Debug, Default, Clone)]
 pub struct JsCode(String);
 
@@ -87,6 +109,7 @@ const SNAPSHOT_BYTES: &[u8] = include_bytes!("./artifacts/ARM_JS_SNAPSHOT.bin");
 #[derive(Clone)]
 struct JsContext {
     sandboxed: bool,
+    allow_fetch: bool,
     datadir: PathBuf,
     run_function: String,
     version: Version,
@@ -96,8 +119,14 @@ struct JsContext {
     variable_args: Vec<serde_json::Value>,
     container_process_gid: ProcessGroupId,
     container_rpc_client: Option<Arc<UnixRpcClient>>,
+    os: Option<Arc<dyn OsApi>>,
     rsyncs: Arc<Mutex<(usize, BTreeMap<usize, Rsync>)>>,
+    output_streams: Arc<Mutex<(usize, BTreeMap<usize, OutputStream>)>>,
+    supervisor_streams: Arc<Mutex<(usize, BTreeMap<usize, SupervisorStream>)>>,
 }
+type OutputStream = deno_core::futures::stream::BoxStream<'static, Result<OutputEvent, String>>;
+type SupervisorStream =
+    deno_core::futures::stream::BoxStream<'static, Result<SupervisorState, String>>;
 #[derive(Debug, Clone, serde::Deserialize, serde::Serialize)]
 #[serde(rename_all = "kebab-case")]
 enum ResultType {
@@ -178,6 +207,7 @@ impl ModuleLoader for ModsLoader {
 
 pub struct JsExecutionEnvironment {
     sandboxed: bool,
+    allow_fetch: bool,
     base_directory: PathBuf,
     module_loader: ModsLoader,
     package_id: PackageId,
@@ -185,6 +215,7 @@ pub struct JsExecutionEnvironment {
     volumes: Arc<dyn PathForVolumeId>,
     container_process_gid: ProcessGroupId,
     container_rpc_client: Option<Arc<UnixRpcClient>>,
+    os: Option<Arc<dyn OsApi>>,
 }
 
 impl JsExecutionEnvironment {
@@ -197,10 +228,31 @@ impl JsExecutionEnvironment {
         container_rpc_client: Option<Arc<UnixRpcClient>>,
     ) -> Result<JsExecutionEnvironment, (JsError, String)> {
         let data_dir = data_directory.as_ref();
-        let base_directory = data_dir;
+        Self::load_from_script(
+            script_dir(data_dir, package_id, version).join("embassy.js"),
+            data_dir,
+            package_id,
+            version,
+            volumes,
+            container_process_gid,
+            container_rpc_client,
+        )
+        .await
+    }
+    /// Like [JsExecutionEnvironment::load_from_package], but for a script that has not been installed, eg. one in a package's source tree
+    pub async fn load_from_script(
+        file_path: impl AsRef<std::path::Path>,
+        data_directory: impl AsRef<std::path::Path>,
+        package_id: &PackageId,
+        version: &Version,
+        volumes: Box<dyn PathForVolumeId>,
+        container_process_gid: ProcessGroupId,
+        container_rpc_client: Option<Arc<UnixRpcClient>>,
+    ) -> Result<JsExecutionEnvironment, (JsError, String)> {
+        let file_path = file_path.as_ref();
+        let base_directory = data_directory.as_ref();
         let js_code = JsCode({
-            let file_path = script_dir(data_dir, package_id, version).join("embassy.js");
-            let mut file = match tokio::fs::File::open(file_path.clone()).await {
+            let mut file = match tokio::fs::File::open(file_path).await {
                 Ok(x) => x,
                 Err(e) => {
                     tracing::debug!("path: {:?}", file_path);
@@ -228,14 +280,24 @@ impl JsExecutionEnvironment {
             version: version.clone(),
             volumes: volumes.into(),
             sandboxed: false,
+            allow_fetch: true,
             container_process_gid,
             container_rpc_client,
+            os: None,
         })
     }
     pub fn read_only_effects(mut self) -> Self {
         self.sandboxed = true;
         self
     }
+    pub fn with_os_api(mut self, os: Arc<dyn OsApi>) -> Self {
+        self.os = Some(os);
+        self
+    }
+    pub fn deny_fetch(mut self) -> Self {
+        self.allow_fetch = false;
+        self
+    }
 
     pub async fn run_action<I: Serialize, O: for<'de> Deserialize<'de>>(
         self,
@@ -300,6 +362,30 @@ impl JsExecutionEnvironment {
             fns::rsync::decl(),
             fns::rsync_wait::decl(),
             fns::rsync_progress::decl(),
+            fns::output_subscribe::decl(),
+            fns::output_next::decl(),
+            fns::output_close::decl(),
+            fns::supervise::decl(),
+            fns::supervisor_subscribe::decl(),
+            fns::supervisor_next::decl(),
+            fns::supervisor_close::decl(),
+            fns::supervisor_stop::decl(),
+            fns::store_get::decl(),
+            fns::store_set::decl(),
+            fns::store_delete::decl(),
+            fns::store_list::decl(),
+            fns::store_compare_and_swap::decl(),
+            fns::read_pointer::decl(),
+            fns::dependency_properties::decl(),
+            crypto::encode::decl(),
+            crypto::random_bytes::decl(),
+            crypto::hash::decl(),
+            crypto::hmac::decl(),
+            crypto::argon2_hash::decl(),
+            crypto::argon2_verify::decl(),
+            crypto::generate_key_pair::decl(),
+            crypto::sign::decl(),
+            crypto::verify::decl(),
         ]
     }
 
@@ -327,11 +413,15 @@ impl JsExecutionEnvironment {
             volumes: self.volumes.clone(),
             version: self.version.clone(),
             sandboxed: self.sandboxed,
+            allow_fetch: self.allow_fetch,
             input,
             variable_args,
             container_process_gid: self.container_process_gid,
             container_rpc_client: self.container_rpc_client.clone(),
+            os: self.os.clone(),
             rsyncs: Default::default(),
+            output_streams: Default::default(),
+            supervisor_streams: Default::default(),
         };
         let ext = Extension::builder()
             .ops(Self::declarations())
@@ -382,22 +472,26 @@ mod fns {
     use std::os::unix::fs::MetadataExt;
     use std::path::{Path, PathBuf};
     use std::rc::Rc;
+    use std::sync::Arc;
     use std::time::Duration;
 
     use deno_core::anyhow::{anyhow, bail};
     use deno_core::error::AnyError;
     use deno_core::*;
     use embassy_container_init::{
-        OutputParams, OutputStrategy, ProcessGroupId, ProcessId, RunCommand, RunCommandParams,
-        SendSignal, SendSignalParams, SignalGroup, SignalGroupParams,
+        OutputEvent, OutputEventParams, OutputParams, OutputStrategy, ProcessGroupId, ProcessId,
+        RestartPolicy, RunCommand, RunCommandParams, SendSignal, SendSignalParams, SignalGroup,
+        SignalGroupParams, StopSupervisor, StopSupervisorParams, SubscribeOutput,
+        SubscribeOutputParams, SubscribeSupervisor, SubscribeSupervisorParams, Supervise,
+        SuperviseParams, SupervisorEventParams, SupervisorId, SupervisorState,
     };
     use helpers::{to_tmp_path, AtomicFile, Rsync, RsyncOptions};
-    use models::VolumeId;
+    use models::{PackageId, VolumeId};
     use serde::{Deserialize, Serialize};
     use serde_json::{json, Value};
     use tokio::io::AsyncWriteExt;
 
-    use super::{AnswerState, JsContext};
+    use super::{AnswerState, JsContext, OsApi};
     use crate::{system_time_as_unix_ms, MetadataJs, ResultType};
 
     #[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Default)]
@@ -420,15 +514,18 @@ mod fns {
         url: url::Url,
         options: Option<FetchOptions>,
     ) -> Result<FetchResponse, AnyError> {
-        let sandboxed = {
+        let (sandboxed, allow_fetch) = {
             let state = state.borrow();
             let ctx: &JsContext = state.borrow();
-            ctx.sandboxed
+            (ctx.sandboxed, ctx.allow_fetch)
         };
 
         if sandboxed {
             bail!("Will not run fetch in sandboxed mode");
         }
+        if !allow_fetch {
+            bail!("Fetch is not allowed in this environment");
+        }
 
         let client = reqwest::Client::new();
         let options = options.unwrap_or_default();
@@ -1100,6 +1197,21 @@ mod fns {
         process_id: ProcessId,
     }
 
+    #[derive(Debug, Clone, Default, Serialize, Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    pub struct CommandOptions {
+        #[serde(default)]
+        stdin: Option<String>,
+        #[serde(default)]
+        env: BTreeMap<String, String>,
+        #[serde(default)]
+        cwd: Option<PathBuf>,
+        #[serde(default)]
+        uid: Option<u32>,
+        #[serde(default)]
+        gid: Option<u32>,
+    }
+
     #[op]
     async fn start_command(
         state: Rc<RefCell<OpState>>,
@@ -1107,7 +1219,15 @@ mod fns {
         args: Vec<String>,
         output: OutputStrategy,
         timeout: Option<u64>,
+        options: Option<CommandOptions>,
     ) -> Result<StartCommand, AnyError> {
+        let CommandOptions {
+            stdin,
+            env,
+            cwd,
+            uid,
+            gid: group,
+        } = options.unwrap_or_default();
         if let (gid, Some(rpc_client)) = {
             let state = state.borrow();
             let ctx = state.borrow::<JsContext>();
@@ -1121,6 +1241,11 @@ mod fns {
                         command,
                         args,
                         output,
+                        stdin,
+                        env,
+                        cwd,
+                        uid,
+                        group,
                     },
                 )
                 .await
@@ -1176,6 +1301,321 @@ mod fns {
         }
     }
 
+    /// Only for commands started with the inherit output strategy, ie. daemons
+    #[op]
+    async fn output_subscribe(
+        state: Rc<RefCell<OpState>>,
+        pid: ProcessId,
+    ) -> Result<usize, AnyError> {
+        use futures::StreamExt;
+        let (rpc_client, output_streams) = {
+            let state = state.borrow();
+            let ctx = state.borrow::<JsContext>();
+            (ctx.container_rpc_client.clone(), ctx.output_streams.clone())
+        };
+        let rpc_client =
+            rpc_client.ok_or_else(|| anyhow!("No RpcClient for command operations"))?;
+        let stream = rpc_client
+            .subscribe::<_, OutputEventParams>(SubscribeOutput, SubscribeOutputParams { pid })
+            .await
+            .map_err(|e| anyhow!("{}: {:?}", e.message, e.data))?
+            .map(|event| {
+                event
+                    .map(|event| event.event)
+                    .map_err(|e| format!("{}: {:?}", e.message, e.data))
+            })
+            .boxed();
+        let mut output_streams = output_streams.lock().await;
+        let next = output_streams.0 + 1;
+        output_streams.0 = next;
+        output_streams.1.insert(next, stream);
+        Ok(next)
+    }
+    /// Resolves to null once the command has exited and its exit event was returned
+    #[op]
+    async fn output_next(
+        state: Rc<RefCell<OpState>>,
+        id: usize,
+    ) -> Result<Option<OutputEvent>, AnyError> {
+        use futures::StreamExt;
+        let output_streams = {
+            let state = state.borrow();
+            let ctx: &JsContext = state.borrow();
+            ctx.output_streams.clone()
+        };
+        let mut stream = match output_streams.lock().await.1.remove(&id) {
+            Some(a) => a,
+            None => return Ok(None),
+        };
+        let event = stream
+            .next()
+            .await
+            .transpose()
+            .map_err(|e| anyhow!("{}", e))?;
+        if let Some(OutputEvent::Stdout(_) | OutputEvent::Stderr(_)) = &event {
+            output_streams.lock().await.1.insert(id, stream);
+        }
+        Ok(event)
+    }
+    #[op]
+    async fn output_close(state: Rc<RefCell<OpState>>, id: usize) -> Result<(), AnyError> {
+        let output_streams = {
+            let state = state.borrow();
+            let ctx: &JsContext = state.borrow();
+            ctx.output_streams.clone()
+        };
+        output_streams.lock().await.1.remove(&id);
+        Ok(())
+    }
+
+    #[derive(Debug, Clone, Default, Serialize, Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    pub struct SupervisePolicy {
+        #[serde(default)]
+        initial_backoff: Option<u64>,
+        #[serde(default)]
+        max_backoff: Option<u64>,
+        #[serde(default)]
+        max_restarts: Option<u32>,
+        #[serde(default)]
+        restart_on_success: bool,
+    }
+    impl From<SupervisePolicy> for RestartPolicy {
+        fn from(policy: SupervisePolicy) -> Self {
+            let default = RestartPolicy::default();
+            RestartPolicy {
+                initial_backoff: policy.initial_backoff.unwrap_or(default.initial_backoff),
+                max_backoff: policy.max_backoff.unwrap_or(default.max_backoff),
+                max_restarts: policy.max_restarts,
+                restart_on_success: policy.restart_on_success,
+            }
+        }
+    }
+
+    #[op]
+    async fn supervise(
+        state: Rc<RefCell<OpState>>,
+        command: String,
+        args: Vec<String>,
+        options: Option<CommandOptions>,
+        policy: Option<SupervisePolicy>,
+    ) -> Result<SupervisorId, AnyError> {
+        let CommandOptions {
+            stdin,
+            env,
+            cwd,
+            uid,
+            gid: group,
+        } = options.unwrap_or_default();
+        if stdin.is_some() {
+            bail!("Supervised commands cannot be given stdin");
+        }
+        let (gid, rpc_client) = {
+            let state = state.borrow();
+            let ctx = state.borrow::<JsContext>();
+            (ctx.container_process_gid, ctx.container_rpc_client.clone())
+        };
+        let rpc_client =
+            rpc_client.ok_or_else(|| anyhow!("No RpcClient for command operations"))?;
+        rpc_client
+            .request(
+                Supervise,
+                SuperviseParams {
+                    gid: Some(gid),
+                    command,
+                    args,
+                    env,
+                    cwd,
+                    uid,
+                    group,
+                    policy: policy.unwrap_or_default().into(),
+                },
+            )
+            .await
+            .map_err(|e| anyhow!("{}: {:?}", e.message, e.data))
+    }
+    #[op]
+    async fn supervisor_subscribe(
+        state: Rc<RefCell<OpState>>,
+        id: SupervisorId,
+    ) -> Result<usize, AnyError> {
+        use futures::StreamExt;
+        let (rpc_client, supervisor_streams) = {
+            let state = state.borrow();
+            let ctx = state.borrow::<JsContext>();
+            (
+                ctx.container_rpc_client.clone(),
+                ctx.supervisor_streams.clone(),
+            )
+        };
+        let rpc_client =
+            rpc_client.ok_or_else(|| anyhow!("No RpcClient for command operations"))?;
+        let stream = rpc_client
+            .subscribe::<_, SupervisorEventParams>(
+                SubscribeSupervisor,
+                SubscribeSupervisorParams { id },
+            )
+            .await
+            .map_err(|e| anyhow!("{}: {:?}", e.message, e.data))?
+            .map(|event| {
+                event
+                    .map(|event| event.state)
+                    .map_err(|e| format!("{}: {:?}", e.message, e.data))
+            })
+            .boxed();
+        let mut supervisor_streams = supervisor_streams.lock().await;
+        let next = supervisor_streams.0 + 1;
+        supervisor_streams.0 = next;
+        supervisor_streams.1.insert(next, stream);
+        Ok(next)
+    }
+    /// Resolves to null once a final state was returned
+    #[op]
+    async fn supervisor_next(
+        state: Rc<RefCell<OpState>>,
+        id: usize,
+    ) -> Result<Option<SupervisorState>, AnyError> {
+        use futures::StreamExt;
+        let supervisor_streams = {
+            let state = state.borrow();
+            let ctx: &JsContext = state.borrow();
+            ctx.supervisor_streams.clone()
+        };
+        let mut stream = match supervisor_streams.lock().await.1.remove(&id) {
+            Some(a) => a,
+            None => return Ok(None),
+        };
+        let state = stream
+            .next()
+            .await
+            .transpose()
+            .map_err(|e| anyhow!("{}", e))?;
+        if matches!(&state, Some(state) if !state.is_final()) {
+            supervisor_streams.lock().await.1.insert(id, stream);
+        }
+        Ok(state)
+    }
+    #[op]
+    async fn supervisor_close(state: Rc<RefCell<OpState>>, id: usize) -> Result<(), AnyError> {
+        let supervisor_streams = {
+            let state = state.borrow();
+            let ctx: &JsContext = state.borrow();
+            ctx.supervisor_streams.clone()
+        };
+        supervisor_streams.lock().await.1.remove(&id);
+        Ok(())
+    }
+    #[op]
+    async fn supervisor_stop(
+        state: Rc<RefCell<OpState>>,
+        id: SupervisorId,
+    ) -> Result<SupervisorState, AnyError> {
+        let rpc_client = {
+            let state = state.borrow();
+            let ctx = state.borrow::<JsContext>();
+            ctx.container_rpc_client.clone()
+        }
+        .ok_or_else(|| anyhow!("No RpcClient for command operations"))?;
+        rpc_client
+            .request(StopSupervisor, StopSupervisorParams { id })
+            .await
+            .map_err(|e| anyhow!("{}: {:?}", e.message, e.data))
+    }
+
+    fn os_api(state: &Rc<RefCell<OpState>>) -> Result<Arc<dyn OsApi>, AnyError> {
+        let state = state.borrow();
+        let ctx = state.borrow::<JsContext>();
+        ctx.os
+            .clone()
+            .ok_or_else(|| anyhow!("No OsApi for host operations"))
+    }
+
+    fn writable_os_api(state: &Rc<RefCell<OpState>>) -> Result<Arc<dyn OsApi>, AnyError> {
+        let sandboxed = {
+            let state = state.borrow();
+            let ctx = state.borrow::<JsContext>();
+            ctx.sandboxed
+        };
+        if sandboxed {
+            bail!("Will not write to the store in sandboxed mode");
+        }
+        os_api(state)
+    }
+
+    #[op]
+    async fn store_get(
+        state: Rc<RefCell<OpState>>,
+        key: String,
+    ) -> Result<Option<Value>, AnyError> {
+        os_api(&state)?
+            .store_get(&key)
+            .await
+            .map_err(|e| anyhow!("{}", e))
+    }
+
+    #[op]
+    async fn store_set(
+        state: Rc<RefCell<OpState>>,
+        key: String,
+        value: Value,
+    ) -> Result<(), AnyError> {
+        writable_os_api(&state)?
+            .store_set(&key, value)
+            .await
+            .map_err(|e| anyhow!("{}", e))
+    }
+
+    #[op]
+    async fn store_delete(state: Rc<RefCell<OpState>>, key: String) -> Result<bool, AnyError> {
+        writable_os_api(&state)?
+            .store_delete(&key)
+            .await
+            .map_err(|e| anyhow!("{}", e))
+    }
+
+    #[op]
+    async fn store_list(
+        state: Rc<RefCell<OpState>>,
+        prefix: Option<String>,
+    ) -> Result<BTreeMap<String, Value>, AnyError> {
+        os_api(&state)?
+            .store_list(prefix.as_deref().unwrap_or_default())
+            .await
+            .map_err(|e| anyhow!("{}", e))
+    }
+
+    #[op]
+    async fn store_compare_and_swap(
+        state: Rc<RefCell<OpState>>,
+        key: String,
+        expected: Option<Value>,
+        new: Option<Value>,
+    ) -> Result<bool, AnyError> {
+        writable_os_api(&state)?
+            .store_compare_and_swap(&key, expected, new)
+            .await
+            .map_err(|e| anyhow!("{}", e))
+    }
+
+    #[op]
+    async fn read_pointer(state: Rc<RefCell<OpState>>, pointer: Value) -> Result<Value, AnyError> {
+        os_api(&state)?
+            .read_pointer(pointer)
+            .await
+            .map_err(|e| anyhow!("{}", e))
+    }
+
+    #[op]
+    async fn dependency_properties(
+        state: Rc<RefCell<OpState>>,
+        package_id: PackageId,
+    ) -> Result<Value, AnyError> {
+        os_api(&state)?
+            .dependency_properties(&package_id)
+            .await
+            .map_err(|e| anyhow!("{}", e))
+    }
+
     #[op]
     async fn sleep(time_ms: u64) -> Result<(), AnyError> {
         tokio::time::sleep(Duration::from_millis(time_ms)).await;
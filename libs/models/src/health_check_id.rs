@@ -1,8 +1,9 @@
 use std::path::Path;
+use std::str::FromStr;
 
 use serde::{Deserialize, Deserializer, Serialize};
 
-use crate::Id;
+use crate::{Id, InvalidId};
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
 pub struct HealthCheckId<S: AsRef<str> = String>(Id<S>);
@@ -11,6 +12,12 @@ impl<S: AsRef<str>> std::fmt::Display for HealthCheckId<S> {
         write!(f, "{}", &self.0)
     }
 }
+impl FromStr for HealthCheckId {
+    type Err = InvalidId;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(HealthCheckId(Id::try_from(s.to_owned())?))
+    }
+}
 impl<S: AsRef<str>> AsRef<str> for HealthCheckId<S> {
     fn as_ref(&self) -> &str {
         self.0.as_ref()
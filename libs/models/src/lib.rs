@@ -7,6 +7,7 @@ mod interface_id;
 mod invalid_id;
 mod package_id;
 mod procedure_name;
+mod scheduled_task_id;
 mod version;
 mod volume_id;
 
@@ -19,5 +20,6 @@ pub use interface_id::*;
 pub use invalid_id::*;
 pub use package_id::*;
 pub use procedure_name::*;
+pub use scheduled_task_id::*;
 pub use version::*;
 pub use volume_id::*;
@@ -1,4 +1,9 @@
-use crate::{ActionId, HealthCheckId, PackageId};
+use std::str::FromStr;
+
+use color_eyre::eyre::eyre;
+use serde::{Deserialize, Serialize};
+
+use crate::{ActionId, Error, ErrorKind, HealthCheckId, PackageId, ScheduledTaskId};
 
 #[derive(Debug, Clone)]
 pub enum ProcedureName {
@@ -8,15 +13,60 @@ pub enum ProcedureName {
     GetConfig,
     SetConfig,
     Migration,
+    ConfigMigration(u32),
     Properties,
     LongRunning,
     Check(PackageId),
     AutoConfig(PackageId),
     Health(HealthCheckId),
     Action(ActionId),
+    ScheduledTask(ScheduledTaskId),
+    DependencyHook(PackageId, DependencyEvent),
     Signal,
 }
 
+/// Something that happened to a dependency, which a dependent can react to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum DependencyEvent {
+    Installed,
+    Started,
+    Stopped,
+    Uninstalled,
+}
+impl DependencyEvent {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DependencyEvent::Installed => "installed",
+            DependencyEvent::Started => "started",
+            DependencyEvent::Stopped => "stopped",
+            DependencyEvent::Uninstalled => "uninstalled",
+        }
+    }
+}
+impl std::fmt::Display for DependencyEvent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+impl FromStr for DependencyEvent {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "installed" => DependencyEvent::Installed,
+            "started" => DependencyEvent::Started,
+            "stopped" => DependencyEvent::Stopped,
+            "uninstalled" => DependencyEvent::Uninstalled,
+            _ => {
+                return Err(Error::new(
+                    eyre!("Unknown dependency event: {}", s),
+                    ErrorKind::InvalidRequest,
+                ))
+            }
+        })
+    }
+}
+
 impl ProcedureName {
     pub fn docker_name(&self) -> Option<String> {
         match self {
@@ -27,9 +77,14 @@ impl ProcedureName {
             ProcedureName::GetConfig => Some("GetConfig".to_string()),
             ProcedureName::SetConfig => Some("SetConfig".to_string()),
             ProcedureName::Migration => Some("Migration".to_string()),
+            ProcedureName::ConfigMigration(version) => Some(format!("ConfigMigration{}", version)),
             ProcedureName::Properties => Some(format!("Properties-{}", rand::random::<u64>())),
             ProcedureName::Health(id) => Some(format!("{}Health", id)),
             ProcedureName::Action(id) => Some(format!("{}Action", id)),
+            ProcedureName::ScheduledTask(id) => Some(format!("{}ScheduledTask", id)),
+            ProcedureName::DependencyHook(id, event) => {
+                Some(format!("{}-{}DependencyHook", id, event))
+            }
             ProcedureName::Check(_) => None,
             ProcedureName::AutoConfig(_) => None,
             ProcedureName::Signal => None,
@@ -44,12 +99,51 @@ impl ProcedureName {
             ProcedureName::GetConfig => Some("/getConfig".to_string()),
             ProcedureName::SetConfig => Some("/setConfig".to_string()),
             ProcedureName::Migration => Some("/migration".to_string()),
+            ProcedureName::ConfigMigration(version) => {
+                Some(format!("/configMigration/{}", version))
+            }
             ProcedureName::Properties => Some("/properties".to_string()),
             ProcedureName::Health(id) => Some(format!("/health/{}", id)),
             ProcedureName::Action(id) => Some(format!("/action/{}", id)),
+            ProcedureName::ScheduledTask(id) => Some(format!("/scheduledTask/{}", id)),
             ProcedureName::Check(id) => Some(format!("/dependencies/{}/check", id)),
             ProcedureName::AutoConfig(id) => Some(format!("/dependencies/{}/autoConfigure", id)),
+            ProcedureName::DependencyHook(id, event) => {
+                Some(format!("/dependencies/{}/{}", id, event))
+            }
             ProcedureName::Signal => Some("/handleSignal".to_string()),
         }
     }
 }
+impl FromStr for ProcedureName {
+    type Err = Error;
+    /// Parses the same form as [ProcedureName::js_function_name], with or without the leading `/`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.strip_prefix('/').unwrap_or(s);
+        Ok(match s.split('/').collect::<Vec<_>>().as_slice() {
+            ["main"] => ProcedureName::Main,
+            ["createBackup"] => ProcedureName::CreateBackup,
+            ["restoreBackup"] => ProcedureName::RestoreBackup,
+            ["getConfig"] => ProcedureName::GetConfig,
+            ["setConfig"] => ProcedureName::SetConfig,
+            ["migration"] => ProcedureName::Migration,
+            ["configMigration", version] => ProcedureName::ConfigMigration(version.parse()?),
+            ["properties"] => ProcedureName::Properties,
+            ["handleSignal"] => ProcedureName::Signal,
+            ["health", id] => ProcedureName::Health(id.parse()?),
+            ["action", id] => ProcedureName::Action(id.parse()?),
+            ["scheduledTask", id] => ProcedureName::ScheduledTask(id.parse()?),
+            ["dependencies", id, "check"] => ProcedureName::Check(id.parse()?),
+            ["dependencies", id, "autoConfigure"] => ProcedureName::AutoConfig(id.parse()?),
+            ["dependencies", id, event] => {
+                ProcedureName::DependencyHook(id.parse()?, event.parse()?)
+            }
+            _ => {
+                return Err(Error::new(
+                    eyre!("Unknown procedure: {}", s),
+                    ErrorKind::InvalidRequest,
+                ))
+            }
+        })
+    }
+}
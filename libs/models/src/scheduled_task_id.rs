@@ -0,0 +1,54 @@
+use std::path::Path;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{Id, InvalidId};
+
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+pub struct ScheduledTaskId<S: AsRef<str> = String>(Id<S>);
+impl FromStr for ScheduledTaskId {
+    type Err = InvalidId;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(ScheduledTaskId(Id::try_from(s.to_owned())?))
+    }
+}
+impl From<ScheduledTaskId> for String {
+    fn from(value: ScheduledTaskId) -> Self {
+        value.0.into()
+    }
+}
+impl<S: AsRef<str>> AsRef<ScheduledTaskId<S>> for ScheduledTaskId<S> {
+    fn as_ref(&self) -> &ScheduledTaskId<S> {
+        self
+    }
+}
+impl<S: AsRef<str>> std::fmt::Display for ScheduledTaskId<S> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", &self.0)
+    }
+}
+impl<S: AsRef<str>> AsRef<str> for ScheduledTaskId<S> {
+    fn as_ref(&self) -> &str {
+        self.0.as_ref()
+    }
+}
+impl<S: AsRef<str>> AsRef<Path> for ScheduledTaskId<S> {
+    fn as_ref(&self) -> &Path {
+        self.0.as_ref().as_ref()
+    }
+}
+impl<'de, S> Deserialize<'de> for ScheduledTaskId<S>
+where
+    S: AsRef<str>,
+    Id<S>: Deserialize<'de>,
+{
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::de::Deserializer<'de>,
+    {
+        Ok(ScheduledTaskId(serde::Deserialize::deserialize(
+            deserializer,
+        )?))
+    }
+}